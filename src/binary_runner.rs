@@ -0,0 +1,214 @@
+//! Support for `cackle test` and `cackle run`, which, once everything has been built and checked,
+//! run the test binaries or the main binary in a sandbox.
+
+use crate::config::SandboxConfig;
+use crate::config::SandboxKind;
+use crate::outcome;
+use crate::outcome::ExitCode;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use cargo_metadata::Message;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Flag that we pass to `cargo build` so that it tells us what executables it built.
+pub(crate) const MESSAGE_FORMAT_FLAG: &str = "--message-format=json-render-diagnostics";
+
+/// An executable produced by cargo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Executable {
+    path: PathBuf,
+
+    /// The name of the target from which the executable was built.
+    target_name: String,
+
+    /// The directory containing the package's Cargo.toml. Cargo runs tests from here.
+    package_dir: PathBuf,
+
+    /// Whether this executable runs tests.
+    is_test: bool,
+}
+
+/// Returns the executables reported in JSON messages from cargo.
+pub(crate) fn executables_from_cargo_output(cargo_stdout: &[u8]) -> Result<Vec<Executable>> {
+    let mut executables = Vec::new();
+    for message in Message::parse_stream(cargo_stdout) {
+        let Message::CompilerArtifact(artifact) = message.context("Failed to read cargo output")?
+        else {
+            continue;
+        };
+        let Some(path) = artifact.executable else {
+            continue;
+        };
+        let package_dir = artifact
+            .manifest_path
+            .parent()
+            .map(|dir| dir.as_std_path().to_owned())
+            .unwrap_or_default();
+        let executable = Executable {
+            path: path.into_std_path_buf(),
+            target_name: artifact.target.name,
+            package_dir,
+            is_test: artifact.profile.test,
+        };
+        if !executables.contains(&executable) {
+            executables.push(executable);
+        }
+    }
+    Ok(executables)
+}
+
+/// Returns the test executables from `executables`.
+pub(crate) fn tests(executables: Vec<Executable>) -> Vec<Executable> {
+    executables.into_iter().filter(|exe| exe.is_test).collect()
+}
+
+/// Returns the binary to run from `executables`. If `bin` is specified, then the binary with that
+/// name is returned, otherwise there must be only one binary.
+pub(crate) fn main_binary(executables: Vec<Executable>, bin: Option<&str>) -> Result<Executable> {
+    let mut binaries: Vec<Executable> = executables
+        .into_iter()
+        .filter(|exe| !exe.is_test && bin.map_or(true, |bin| exe.target_name == bin))
+        .collect();
+    // If we built multiple configurations, then we'll have a binary for each. We use the first.
+    let mut seen = HashSet::new();
+    binaries.retain(|exe| seen.insert(exe.target_name.clone()));
+    match (binaries.len(), bin) {
+        (1, _) => Ok(binaries.remove(0)),
+        (0, Some(bin)) => bail!("No binary named `{bin}` was built"),
+        (0, None) => bail!("No binaries were built"),
+        _ => bail!(
+            "Multiple binaries were built, use --bin to select one of: {}",
+            binaries
+                .iter()
+                .map(|exe| exe.target_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Runs each of `executables` with `args`, sandboxed as per `sandbox_config`. Read-only access is
+/// granted to `root_path`, which contains the crate that we're checking. `tmp_dir` is created if
+/// necessary and provided as a writable temporary directory.
+pub(crate) fn run(
+    executables: &[Executable],
+    sandbox_config: &SandboxConfig,
+    root_path: &Path,
+    tmp_dir: &Path,
+    args: &[String],
+) -> Result<ExitCode> {
+    std::fs::create_dir_all(tmp_dir)
+        .with_context(|| format!("Failed to create directory `{}`", tmp_dir.display()))?;
+    let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+    let mut exit_code = outcome::SUCCESS;
+    for exe in executables {
+        if exe.is_test {
+            println!("Running `{}`", exe.path.display());
+        }
+        let exe_exit_code = run_executable(exe, sandbox_config, root_path, tmp_dir, &args)?;
+        if !exe_exit_code.is_ok() {
+            exit_code = exe_exit_code;
+        }
+    }
+    Ok(exit_code)
+}
+
+fn run_executable(
+    exe: &Executable,
+    sandbox_config: &SandboxConfig,
+    root_path: &Path,
+    tmp_dir: &Path,
+    args: &[OsString],
+) -> Result<ExitCode> {
+    if sandbox_config.kind == SandboxKind::Inherit {
+        bail!(
+            "No sandbox is configured for running `{}`. Set `kind` in `[sandbox]`, or to run it \
+             without a sandbox, set `kind = \"Disabled\"`",
+            exe.path.display()
+        );
+    }
+    let Some(mut sandbox) = crate::sandbox::from_config(sandbox_config)? else {
+        return Ok(Command::new(&exe.path)
+            .args(args)
+            .current_dir(&exe.package_dir)
+            .status()
+            .with_context(|| format!("Failed to run `{}`", exe.path.display()))?
+            .into());
+    };
+    sandbox.ro_bind(root_path);
+    sandbox.writable_bind(tmp_dir);
+    sandbox.set_env(OsStr::new("TMPDIR"), tmp_dir.as_os_str());
+    sandbox.set_env(
+        OsStr::new("CARGO_MANIFEST_DIR"),
+        exe.package_dir.as_os_str(),
+    );
+    sandbox.pass_env("RUST_BACKTRACE");
+    Ok(sandbox
+        .run_interactive(&exe.path, args, &exe.package_dir)?
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Executable;
+    use crate::config::SandboxConfig;
+    use crate::config::SandboxKind;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    fn exe(name: &str, is_test: bool) -> Executable {
+        Executable {
+            path: PathBuf::from(format!("/target/{name}")),
+            target_name: name.to_owned(),
+            package_dir: PathBuf::new(),
+            is_test,
+        }
+    }
+
+    #[test]
+    fn select_executables() {
+        let executables = vec![exe("a", false), exe("a", true), exe("b", false)];
+        assert_eq!(super::tests(executables.clone()), vec![exe("a", true)]);
+        assert_eq!(
+            super::main_binary(executables.clone(), Some("b")).unwrap(),
+            exe("b", false)
+        );
+        assert!(super::main_binary(executables.clone(), None).is_err());
+        assert!(super::main_binary(executables, Some("c")).is_err());
+        assert_eq!(
+            super::main_binary(vec![exe("a", false)], None).unwrap(),
+            exe("a", false)
+        );
+    }
+
+    #[test]
+    fn requires_sandbox_kind() {
+        let tmp = tempfile::tempdir().unwrap();
+        let exe = Executable {
+            path: PathBuf::from("true"),
+            package_dir: tmp.path().to_owned(),
+            ..exe("a", false)
+        };
+        let run = |kind| {
+            let sandbox_config = SandboxConfig {
+                kind,
+                ..SandboxConfig::default()
+            };
+            super::run(
+                std::slice::from_ref(&exe),
+                &sandbox_config,
+                Path::new("/"),
+                tmp.path(),
+                &[],
+            )
+        };
+        assert!(run(SandboxKind::Inherit).is_err());
+        assert!(run(SandboxKind::Disabled).unwrap().is_ok());
+    }
+}
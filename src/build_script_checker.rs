@@ -1,11 +1,31 @@
 use crate::config::Config;
-use crate::config::CrateName;
+use crate::config::PackageConfig;
 use crate::problem::DisallowedBuildInstruction;
+use crate::problem::DisallowedExec;
+use crate::problem::DisallowedNetworkAccess;
 use crate::problem::Problem;
 use crate::problem::ProblemList;
+use crate::problem::SuggestedRules;
 use crate::proxy::rpc::BuildScriptOutput;
+use crate::sandbox::exec_trace;
+use std::collections::HashSet;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
 
 pub(crate) fn check(outputs: &BuildScriptOutput, config: &Config) -> ProblemList {
+    if outputs.exceeded_limit.is_some() {
+        return Problem::ResourceLimitExceeded(crate::problem::BuildScriptFailed {
+            output: outputs.clone(),
+        })
+        .into();
+    }
+    // If the build script was blocked from accessing the network, then that's likely why it failed,
+    // if it did.
+    let network_problems = check_network_attempts(outputs);
+    if !network_problems.is_empty() {
+        return network_problems;
+    }
     if outputs.exit_code != 0 {
         return Problem::BuildScriptFailed(crate::problem::BuildScriptFailed {
             output: outputs.clone(),
@@ -13,11 +33,7 @@ pub(crate) fn check(outputs: &BuildScriptOutput, config: &Config) -> ProblemList
         .into();
     }
     let crate_name = &outputs.crate_name;
-    let allow_build_instructions = config
-        .packages
-        .get(crate_name)
-        .map(|cfg| cfg.allow_build_instructions.as_slice())
-        .unwrap_or(&[]);
+    let package_config = config.package_config(crate_name);
     let Ok(stdout) = std::str::from_utf8(&outputs.stdout) else {
         return Problem::new(format!(
             "The build script `{crate_name}` emitted invalid UTF-8"
@@ -27,7 +43,34 @@ pub(crate) fn check(outputs: &BuildScriptOutput, config: &Config) -> ProblemList
     let mut problems = ProblemList::default();
     for line in stdout.lines() {
         if line.starts_with("cargo:") {
-            problems.merge(check_directive(line, crate_name, allow_build_instructions));
+            problems.merge(check_directive(line, outputs, package_config));
+        }
+    }
+    let allow_exec = package_config
+        .map(|cfg| cfg.allow_exec.as_slice())
+        .unwrap_or(&[]);
+    for program in &outputs.executed_programs {
+        if !exec_trace::is_allowed(program, allow_exec) {
+            problems.push(Problem::DisallowedExec(DisallowedExec {
+                crate_name: crate_name.clone(),
+                program: program.clone(),
+            }));
+        }
+    }
+    problems
+}
+
+/// Returns a problem for each host and port that the build script was blocked from connecting to.
+fn check_network_attempts(outputs: &BuildScriptOutput) -> ProblemList {
+    let mut problems = ProblemList::default();
+    let mut reported = HashSet::new();
+    for attempt in &outputs.network_attempts {
+        if !attempt.allowed && reported.insert((&attempt.host, attempt.port)) {
+            problems.push(Problem::DisallowedNetworkAccess(DisallowedNetworkAccess {
+                crate_name: outputs.crate_name.clone(),
+                host: attempt.host.clone(),
+                port: attempt.port,
+            }));
         }
     }
     problems
@@ -35,32 +78,207 @@ pub(crate) fn check(outputs: &BuildScriptOutput, config: &Config) -> ProblemList
 
 /// Cargo instructions that should be harmless, so would just add noise if we were required to
 /// explicitly allow them.
-const ALWAYS_PERMITTED: &[&str] = &["cargo:rerun-if-", "cargo:warning", "cargo:rustc-cfg="];
+const ALWAYS_PERMITTED: &[&str] = &[
+    "cargo:rerun-if-",
+    "cargo:warning",
+    "cargo:error",
+    "cargo:rustc-cfg=",
+    "cargo:rustc-check-cfg=",
+];
 
 fn check_directive(
     instruction: &str,
-    crate_name: &CrateName,
-    allow_build_instructions: &[String],
+    outputs: &BuildScriptOutput,
+    package_config: Option<&PackageConfig>,
 ) -> ProblemList {
+    let instruction = normalise(instruction);
     if ALWAYS_PERMITTED
         .iter()
         .any(|prefix| instruction.starts_with(prefix))
     {
         return ProblemList::default();
     }
-    if allow_build_instructions
+    let empty = PackageConfig::default();
+    let package_config = package_config.unwrap_or(&empty);
+    if package_config
+        .allow_build_instructions
         .iter()
-        .any(|i| matches(instruction, i))
+        .any(|rule| matches(&instruction, &normalise(rule)))
     {
         return ProblemList::default();
     }
+    let structured = Structured::parse(&instruction);
+    if structured.as_ref().map_or(false, |structured| {
+        structured.is_allowed(package_config, outputs)
+    }) {
+        return ProblemList::default();
+    }
     Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
-        crate_name: crate_name.to_owned(),
-        instruction: instruction.to_owned(),
+        crate_name: outputs.crate_name.to_owned(),
+        suggested_rules: structured.map(|structured| structured.suggested_rules(outputs)),
+        instruction,
     })
     .into()
 }
 
+/// An instruction that can be permitted by a rule that's specific to the kind of instruction,
+/// rather than by a pattern.
+#[derive(Debug, PartialEq, Eq)]
+enum Structured<'a> {
+    /// `rustc-link-lib`. `kind` is `dylib` if the instruction didn't specify a kind.
+    LinkLib { kind: &'a str, name: &'a str },
+    /// `rustc-link-search`.
+    LinkSearch { path: &'a Path },
+    /// `rustc-env`.
+    Env { var: &'a str },
+}
+
+impl<'a> Structured<'a> {
+    /// Parses `instruction`, which should have already been normalised.
+    fn parse(instruction: &'a str) -> Option<Self> {
+        let (key, value) = instruction.strip_prefix("cargo:")?.split_once('=')?;
+        match key {
+            // The value is `[KIND[:MODIFIERS]=]NAME[:RENAME]`.
+            "rustc-link-lib" => {
+                let (kind, name) = value.split_once('=').unwrap_or(("dylib", value));
+                let kind = kind.split(':').next()?.trim();
+                let name = name.split(':').next()?.trim();
+                Some(Structured::LinkLib { kind, name })
+            }
+            // The value is `[KIND=]PATH`.
+            "rustc-link-search" => {
+                let path = match value.split_once('=') {
+                    Some((kind, path)) if LINK_SEARCH_KINDS.contains(&kind.trim()) => path,
+                    _ => value,
+                };
+                Some(Structured::LinkSearch {
+                    path: Path::new(path.trim()),
+                })
+            }
+            "rustc-env" => Some(Structured::Env {
+                var: value.split_once('=')?.0.trim(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn is_allowed(&self, package_config: &PackageConfig, outputs: &BuildScriptOutput) -> bool {
+        match self {
+            Structured::LinkLib { kind, name } => {
+                package_config.allow_link_libs.iter().any(|rule| {
+                    let (rule_kind, rule_name) = rule.split_once('=').unwrap_or((kind, rule));
+                    rule_kind == *kind && matches(name, rule_name)
+                })
+            }
+            Structured::LinkSearch { path } => {
+                // We don't want to permit something like `$OUT_DIR/../..`.
+                !path
+                    .components()
+                    .any(|component| component == Component::ParentDir)
+                    && package_config
+                        .allow_link_search
+                        .iter()
+                        .filter_map(|rule| expand_dirs(rule, outputs))
+                        .any(|dir| path.starts_with(dir))
+            }
+            Structured::Env { var } => package_config
+                .allow_rustc_env
+                .iter()
+                .any(|rule| matches(var, rule)),
+        }
+    }
+
+    /// Returns rules that would permit this instruction, from most to least specific.
+    fn suggested_rules(&self, outputs: &BuildScriptOutput) -> SuggestedRules {
+        match self {
+            Structured::LinkLib { kind, name } => SuggestedRules {
+                config_key: "allow_link_libs",
+                rules: vec![format!("{kind}={name}"), name.to_string()],
+            },
+            Structured::LinkSearch { path } => {
+                let mut rules = Vec::new();
+                for (var, dir) in [
+                    ("$OUT_DIR", &outputs.out_dir),
+                    ("$CARGO_MANIFEST_DIR", &outputs.manifest_dir),
+                ] {
+                    if dir.as_os_str().is_empty() {
+                        continue;
+                    }
+                    if let Ok(relative) = path.strip_prefix(dir) {
+                        if !relative.as_os_str().is_empty() {
+                            rules.push(format!("{var}/{}", relative.display()));
+                        }
+                        rules.push(var.to_owned());
+                        break;
+                    }
+                }
+                if rules.is_empty() {
+                    rules.push(path.display().to_string());
+                }
+                SuggestedRules {
+                    config_key: "allow_link_search",
+                    rules,
+                }
+            }
+            Structured::Env { var } => SuggestedRules {
+                config_key: "allow_rustc_env",
+                rules: vec![var.to_string()],
+            },
+        }
+    }
+}
+
+/// The kinds of search path that can be given to `rustc-link-search`.
+const LINK_SEARCH_KINDS: &[&str] = &["dependency", "crate", "native", "framework", "all"];
+
+/// Returns `rule` with a leading `$OUT_DIR` or `$CARGO_MANIFEST_DIR` replaced by the corresponding
+/// directory for the build script that produced `outputs`. Returns `None` if we don't know that
+/// directory, e.g. because `outputs` was cached by an older version, since an empty directory would
+/// otherwise match every path.
+fn expand_dirs(rule: &str, outputs: &BuildScriptOutput) -> Option<PathBuf> {
+    for (var, dir) in [
+        ("$OUT_DIR", &outputs.out_dir),
+        ("$CARGO_MANIFEST_DIR", &outputs.manifest_dir),
+    ] {
+        if let Some(rest) = rule.strip_prefix(var) {
+            if rest.is_empty() || rest.starts_with('/') {
+                if dir.as_os_str().is_empty() {
+                    return None;
+                }
+                return Some(match rest.strip_prefix('/') {
+                    Some(rest) => dir.join(rest),
+                    None => dir.clone(),
+                });
+            }
+        }
+    }
+    Some(PathBuf::from(rule))
+}
+
+/// Returns `instruction` in the original single-colon syntax with any whitespace around the key
+/// removed, so that it can be checked the same way regardless of which syntax the build script
+/// used. In the double-colon syntax, metadata is emitted as `cargo::metadata=KEY=VALUE`, which is
+/// `cargo:KEY=VALUE` in the single-colon syntax.
+fn normalise(instruction: &str) -> String {
+    let (rest, double_colon) = match instruction.strip_prefix("cargo::") {
+        Some(rest) => (rest, true),
+        None => (
+            instruction.strip_prefix("cargo:").unwrap_or(instruction),
+            false,
+        ),
+    };
+    let Some((key, value)) = rest.split_once('=') else {
+        return format!("cargo:{}", rest.trim());
+    };
+    let key = key.trim();
+    if double_colon && key == "metadata" {
+        if let Some((key, value)) = value.split_once('=') {
+            return format!("cargo:{}={value}", key.trim());
+        }
+    }
+    format!("cargo:{key}={value}")
+}
+
 fn matches(instruction: &str, rule: &str) -> bool {
     if let Some(prefix) = rule.strip_suffix('*') {
         instruction.starts_with(prefix)
@@ -75,26 +293,81 @@ mod tests {
 
     use crate::config;
     use crate::config::CrateName;
-    use crate::config::SandboxConfig;
     use crate::problem::DisallowedBuildInstruction;
+    use crate::problem::DisallowedExec;
+    use crate::problem::DisallowedNetworkAccess;
     use crate::problem::Problem;
     use crate::problem::ProblemList;
+    use crate::problem::SuggestedRules;
     use crate::proxy::rpc::BuildScriptOutput;
+    use crate::sandbox::net_proxy::NetworkAttempt;
 
     #[track_caller]
     fn check(stdout: &str, config_str: &str) -> ProblemList {
         let config = config::testing::parse(config_str).unwrap();
         let outputs = BuildScriptOutput {
-            exit_code: 0,
             stdout: stdout.as_bytes().to_owned(),
-            stderr: vec![],
             crate_name: CrateName::for_build_script("my_pkg"),
-            sandbox_config: SandboxConfig::default(),
-            build_script: PathBuf::new(),
+            out_dir: PathBuf::from("/target/out"),
+            manifest_dir: PathBuf::from("/src/my_pkg"),
+            ..BuildScriptOutput::default()
         };
         super::check(&outputs, &config)
     }
 
+    #[test]
+    fn test_network_access() {
+        let config = config::testing::parse("").unwrap();
+        let attempt = |host: &str, allowed| NetworkAttempt {
+            host: host.to_owned(),
+            port: 443,
+            allowed,
+        };
+        let outputs = BuildScriptOutput {
+            exit_code: 1,
+            crate_name: CrateName::for_build_script("my_pkg"),
+            network_attempts: vec![
+                attempt("crates.io", true),
+                attempt("example.com", false),
+                attempt("example.com", false),
+            ],
+            ..BuildScriptOutput::default()
+        };
+        assert_eq!(
+            super::check(&outputs, &config),
+            Problem::DisallowedNetworkAccess(DisallowedNetworkAccess {
+                crate_name: CrateName::for_build_script("my_pkg"),
+                host: "example.com".to_owned(),
+                port: 443,
+            })
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_exec() {
+        let config = config::testing::parse(
+            r#"
+            [pkg.my_pkg.build]
+            allow_exec = ["cc"]
+            "#,
+        )
+        .unwrap();
+        let outputs = BuildScriptOutput {
+            crate_name: CrateName::for_build_script("my_pkg"),
+            executed_programs: vec![PathBuf::from("/usr/bin/cc"), PathBuf::from("/bin/curl")],
+            ..BuildScriptOutput::default()
+        };
+        assert_eq!(
+            super::check(&outputs, &config),
+            Problem::DisallowedExec(DisallowedExec {
+                crate_name: CrateName::for_build_script("my_pkg"),
+                program: PathBuf::from("/bin/curl"),
+            })
+            .into()
+        );
+    }
+
     #[test]
     fn test_empty() {
         assert_eq!(check("", ""), ProblemList::default());
@@ -108,6 +381,151 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_double_colon_syntax() {
+        assert_eq!(
+            check(
+                "cargo::rerun-if-changed=a.txt\ncargo::rustc-check-cfg=cfg(foo)\ncargo:: warning=hi",
+                ""
+            ),
+            ProblemList::default()
+        );
+        assert_eq!(
+            check("cargo::rustc-link-lib=foo", ""),
+            Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
+                crate_name: CrateName::for_build_script("my_pkg"),
+                instruction: "cargo:rustc-link-lib=foo".to_owned(),
+                suggested_rules: Some(SuggestedRules {
+                    config_key: "allow_link_libs",
+                    rules: vec!["dylib=foo".to_owned(), "foo".to_owned()],
+                }),
+            })
+            .into()
+        );
+        assert_eq!(
+            check(
+                "cargo::rustc-link-lib=foo\ncargo:rustc-link-lib=bar",
+                r#"
+                [pkg.my_pkg.build]
+                allow_build_instructions = [ "cargo:rustc-link-lib=*" ]
+                "#
+            ),
+            ProblemList::default()
+        );
+        assert_eq!(
+            check(
+                "cargo::rustc-link-search=some_directory",
+                r#"
+                [pkg.my_pkg.build]
+                allow_build_instructions = [ "cargo::rustc-link-search=*" ]
+                "#
+            ),
+            ProblemList::default()
+        );
+        assert_eq!(
+            check("cargo::metadata=root=/foo\ncargo:include=/foo/include", ""),
+            ["cargo:root=/foo", "cargo:include=/foo/include"]
+                .into_iter()
+                .fold(ProblemList::default(), |mut problems, instruction| {
+                    problems.push(Problem::DisallowedBuildInstruction(
+                        DisallowedBuildInstruction {
+                            crate_name: CrateName::for_build_script("my_pkg"),
+                            instruction: instruction.to_owned(),
+                            suggested_rules: None,
+                        },
+                    ));
+                    problems
+                })
+        );
+    }
+
+    #[test]
+    fn test_structured_rules() {
+        let config = r#"
+            [pkg.my_pkg.build]
+            allow_link_libs = ["static=foo", "z", "dylib=ssl*"]
+            allow_link_search = ["$OUT_DIR", "$CARGO_MANIFEST_DIR/lib"]
+            allow_rustc_env = ["VERSION_*"]
+        "#;
+        for instruction in [
+            "cargo:rustc-link-lib=static=foo",
+            "cargo:rustc-link-lib=static:+whole-archive=foo",
+            "cargo:rustc-link-lib=dylib=z",
+            "cargo:rustc-link-lib=framework=z",
+            "cargo:rustc-link-lib=ssl3",
+            "cargo::rustc-link-search=native=/target/out/lib",
+            "cargo:rustc-link-search=/src/my_pkg/lib",
+            "cargo:rustc-env=VERSION_STRING=1.2.3",
+        ] {
+            assert_eq!(check(instruction, config), ProblemList::default());
+        }
+        for instruction in [
+            "cargo:rustc-link-lib=foo",
+            "cargo:rustc-link-lib=static=ssl3",
+            "cargo:rustc-link-lib=bar",
+            "cargo:rustc-link-search=native=/target/out/../../lib",
+            "cargo:rustc-link-search=/src/my_pkg",
+            "cargo:rustc-env=PATH=/evil",
+        ] {
+            assert_ne!(
+                check(instruction, config),
+                ProblemList::default(),
+                "{instruction}"
+            );
+        }
+        let problem = |instruction: &str, config_key, rules: &[&str]| {
+            Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
+                crate_name: CrateName::for_build_script("my_pkg"),
+                instruction: instruction.to_owned(),
+                suggested_rules: Some(SuggestedRules {
+                    config_key,
+                    rules: rules.iter().map(|rule| rule.to_string()).collect(),
+                }),
+            })
+            .into()
+        };
+        assert_eq!(
+            check("cargo:rustc-link-search=native=/target/out/lib", ""),
+            problem(
+                "cargo:rustc-link-search=native=/target/out/lib",
+                "allow_link_search",
+                &["$OUT_DIR/lib", "$OUT_DIR"]
+            )
+        );
+        assert_eq!(
+            check("cargo:rustc-env=FOO=bar", ""),
+            problem("cargo:rustc-env=FOO=bar", "allow_rustc_env", &["FOO"])
+        );
+    }
+
+    #[test]
+    fn test_unknown_dirs() {
+        // Output cached by an older version doesn't record the build script's directories. Rules
+        // that refer to them then shouldn't permit anything.
+        let config = config::testing::parse(
+            r#"
+            [pkg.my_pkg.build]
+            allow_link_search = ["$OUT_DIR", "$CARGO_MANIFEST_DIR/lib"]
+            "#,
+        )
+        .unwrap();
+        for instruction in [
+            "cargo:rustc-link-search=/usr/lib",
+            "cargo:rustc-link-search=lib",
+        ] {
+            let outputs = BuildScriptOutput {
+                stdout: instruction.as_bytes().to_owned(),
+                crate_name: CrateName::for_build_script("my_pkg"),
+                ..BuildScriptOutput::default()
+            };
+            assert_ne!(
+                super::check(&outputs, &config),
+                ProblemList::default(),
+                "{instruction}"
+            );
+        }
+    }
+
     #[test]
     fn test_link_directive() {
         assert_eq!(
@@ -115,6 +533,10 @@ mod tests {
             Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
                 crate_name: CrateName::for_build_script("my_pkg"),
                 instruction: "cargo:rustc-link-search=some_directory".to_owned(),
+                suggested_rules: Some(SuggestedRules {
+                    config_key: "allow_link_search",
+                    rules: vec!["some_directory".to_owned()],
+                }),
             })
             .into()
         );
@@ -0,0 +1,379 @@
+//! A persistent cache of analysis results. When running incrementally, we don't `cargo clean`
+//! before building, so cargo won't rerun rustc, the linker or build scripts for crates that haven't
+//! changed. The cache lets us recheck the results of those earlier runs against the current config.
+
+use crate::checker::SourceLocation;
+use crate::config::Config;
+use crate::config::CrateName;
+use crate::problem::ApiUsages;
+use crate::proxy::rpc::BuildScriptOutput;
+use crate::Args;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Cache {
+    key: CacheKey,
+    crates: BTreeMap<CrateName, CrateEntry>,
+    /// Keyed by the output file of the link.
+    links: BTreeMap<PathBuf, LinkEntry>,
+
+    /// Crates for which we've had fresh information during this run.
+    #[serde(skip)]
+    refreshed_crates: HashSet<CrateName>,
+
+    /// Link outputs that have been relinked during this run.
+    #[serde(skip)]
+    refreshed_links: HashSet<PathBuf>,
+}
+
+/// If any part of the key changes, the whole cache is invalid.
+#[derive(Serialize, Deserialize, Default, PartialEq, Eq, Debug)]
+pub(crate) struct CacheKey {
+    cackle_version: String,
+    rustc_version: String,
+    target: Option<String>,
+    /// The flattened config, excluding per-package config. Changes to per-package config are
+    /// handled by `CrateEntry::config_hash`.
+    config: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct CrateEntry {
+    /// A hash of the parts of the config that affect how the crate was compiled or how its build
+    /// script was run. See `crate_config_hash`.
+    config_hash: u64,
+    pub(crate) source_paths: Vec<PathBuf>,
+    pub(crate) unsafe_locations: Vec<SourceLocation>,
+    pub(crate) build_script_output: Option<BuildScriptOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct LinkEntry {
+    /// Hashes of the contents of each object file and archive that was linked.
+    object_hashes: Vec<(PathBuf, u64)>,
+    pub(crate) api_usages: Vec<ApiUsages>,
+}
+
+impl CacheKey {
+    pub(crate) fn new(root_path: &Path, config: &Config, args: &Args) -> Result<Self> {
+        let output = Command::new("rustc")
+            .arg("-vV")
+            .current_dir(root_path)
+            .output()
+            .context("Failed to run `rustc -vV`")?;
+        let mut non_package_config = config.clone();
+        non_package_config.packages.clear();
+        Ok(Self {
+            cackle_version: env!("CARGO_PKG_VERSION").to_owned(),
+            rustc_version: String::from_utf8_lossy(&output.stdout).into_owned(),
+            target: args.target.clone(),
+            config: non_package_config.flattened_toml()?,
+        })
+    }
+}
+
+impl Cache {
+    /// Loads the cache if it exists and matches `key`. The cache file is deleted once loaded. That
+    /// way, if we fail to save the cache at the end of the run, we won't later use a cache that
+    /// doesn't describe the results of that run.
+    pub(crate) fn load(target_dir: &Path, key: &CacheKey) -> Result<Option<Cache>> {
+        let path = cache_path(target_dir);
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove `{}`", path.display()))?;
+        let Ok(cache) = serde_json::from_str::<Cache>(&contents) else {
+            return Ok(None);
+        };
+        if cache.key != *key {
+            return Ok(None);
+        }
+        Ok(Some(cache))
+    }
+
+    pub(crate) fn new(key: CacheKey) -> Self {
+        Self {
+            key,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn save(&self, target_dir: &Path) -> Result<()> {
+        crate::fs::write_atomic(&cache_path(target_dir), &serde_json::to_string(self)?)
+    }
+
+    /// Removes and returns the names of packages where config that affects how the package was
+    /// built has changed. These packages will need to be rebuilt.
+    pub(crate) fn remove_stale_packages(&mut self, config: &Config) -> Vec<String> {
+        let stale: HashSet<String> = self
+            .crates
+            .iter()
+            .filter(|(crate_name, entry)| {
+                entry.config_hash != crate_config_hash(config, crate_name)
+            })
+            .map(|(crate_name, _)| crate_name.package_name().to_owned())
+            .collect();
+        self.crates
+            .retain(|crate_name, _| !stale.contains(crate_name.package_name()));
+        let mut stale: Vec<String> = stale.into_iter().collect();
+        stale.sort();
+        stale
+    }
+
+    pub(crate) fn crates(&self) -> impl Iterator<Item = (&CrateName, &CrateEntry)> {
+        self.crates.iter()
+    }
+
+    /// Returns entries for crates that haven't been recompiled or had their build scripts rerun
+    /// during this run.
+    pub(crate) fn unrefreshed_crates(&self) -> impl Iterator<Item = (&CrateName, &CrateEntry)> {
+        self.crates
+            .iter()
+            .filter(|(crate_name, _)| !self.refreshed_crates.contains(*crate_name))
+    }
+
+    /// Returns entries for link outputs that weren't relinked during this run. Entries where the
+    /// linked objects have since changed are removed and returned separately.
+    pub(crate) fn unrefreshed_links(&mut self) -> (Vec<&LinkEntry>, Vec<PathBuf>) {
+        let mut stale = Vec::new();
+        self.links.retain(|output, entry| {
+            if self.refreshed_links.contains(output) || entry.is_current() {
+                true
+            } else {
+                stale.push(output.clone());
+                false
+            }
+        });
+        let current = self
+            .links
+            .iter()
+            .filter(|(output, _)| !self.refreshed_links.contains(*output))
+            .map(|(_, entry)| entry)
+            .collect();
+        (current, stale)
+    }
+
+    fn crate_entry(&mut self, crate_name: &CrateName, config: &Config) -> &mut CrateEntry {
+        self.refreshed_crates.insert(crate_name.clone());
+        let entry = self.crates.entry(crate_name.clone()).or_default();
+        entry.config_hash = crate_config_hash(config, crate_name);
+        entry
+    }
+
+    /// Discards anything that we knew about `crate_name`, since it's being rebuilt.
+    pub(crate) fn record_rustc_started(&mut self, crate_name: &CrateName, config: &Config) {
+        *self.crate_entry(crate_name, config) = CrateEntry {
+            config_hash: crate_config_hash(config, crate_name),
+            ..Default::default()
+        };
+    }
+
+    pub(crate) fn record_source_paths(
+        &mut self,
+        crate_name: &CrateName,
+        config: &Config,
+        source_paths: &[PathBuf],
+    ) {
+        self.crate_entry(crate_name, config).source_paths = source_paths.to_owned();
+    }
+
+    pub(crate) fn record_unsafe(
+        &mut self,
+        crate_name: &CrateName,
+        config: &Config,
+        locations: &[SourceLocation],
+    ) {
+        self.crate_entry(crate_name, config).unsafe_locations = locations.to_owned();
+    }
+
+    pub(crate) fn record_build_script_output(
+        &mut self,
+        output: &BuildScriptOutput,
+        config: &Config,
+    ) {
+        self.crate_entry(&output.crate_name, config)
+            .build_script_output = Some(output.clone());
+    }
+
+    pub(crate) fn record_link(
+        &mut self,
+        output_file: &Path,
+        object_paths: &[PathBuf],
+        api_usages: &[ApiUsages],
+    ) -> Result<()> {
+        let object_hashes = object_paths
+            .iter()
+            .map(|path| Ok((path.clone(), hash_file(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.refreshed_links.insert(output_file.to_owned());
+        self.links.insert(
+            output_file.to_owned(),
+            LinkEntry {
+                object_hashes,
+                api_usages: api_usages.to_owned(),
+            },
+        );
+        Ok(())
+    }
+}
+
+impl LinkEntry {
+    /// Returns whether all the objects that were linked are unchanged.
+    fn is_current(&self) -> bool {
+        self.object_hashes
+            .iter()
+            .all(|(path, expected)| hash_file(path).ok() == Some(*expected))
+    }
+}
+
+/// Returns a hash of the parts of the config that affect how `crate_name` gets compiled, or if it's
+/// a build script, how it gets run.
+fn crate_config_hash(config: &Config, crate_name: &CrateName) -> u64 {
+    let relevant = (
+        config.unsafe_permitted_for_crate(crate_name),
+        config.sandbox_config_for_package(crate_name),
+    );
+    // Serialising these types can't fail, since they don't contain any maps with non-string keys.
+    stable_hash(
+        serde_json::to_string(&relevant)
+            .unwrap_or_default()
+            .as_bytes(),
+    )
+}
+
+fn hash_file(path: &Path) -> Result<u64> {
+    let contents =
+        std::fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+    Ok(stable_hash(&contents))
+}
+
+/// A 64 bit FNV-1a hash of `bytes`. Unlike `DefaultHasher`, the output is guaranteed to be the
+/// same across Rust releases, which matters since we write these hashes to disk.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+fn cache_path(target_dir: &Path) -> PathBuf {
+    target_dir
+        .join(crate::proxy::cargo::PROFILE_NAME)
+        .join("cache.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use super::CacheKey;
+    use crate::config::testing::parse;
+    use crate::config::CrateName;
+    use std::path::PathBuf;
+
+    #[test]
+    fn stable_hash() {
+        // Known FNV-1a values. If these change, previously written caches would never match.
+        assert_eq!(super::stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(super::stable_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        let config = parse(
+            r#"
+            [pkg.crab1]
+            allow_unsafe = true
+            "#,
+        )
+        .unwrap();
+        let key = || CacheKey {
+            cackle_version: "1.0.0".to_owned(),
+            rustc_version: "rustc 1.70.0".to_owned(),
+            target: None,
+            config: config.flattened_toml().unwrap(),
+        };
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(crate::proxy::cargo::PROFILE_NAME)).unwrap();
+        let crab1 = CrateName::from("crab1");
+        let mut cache = Cache::new(key());
+        cache.record_source_paths(&crab1, &config, &[PathBuf::from("src/lib.rs")]);
+        cache.save(dir.path()).unwrap();
+
+        let mut loaded = Cache::load(dir.path(), &key()).unwrap().unwrap();
+        assert!(loaded.remove_stale_packages(&config).is_empty());
+        let crates: Vec<_> = loaded.crates().collect();
+        assert_eq!(crates.len(), 1);
+        assert_eq!(crates[0].0, &crab1);
+        assert_eq!(crates[0].1.source_paths, vec![PathBuf::from("src/lib.rs")]);
+
+        // The cache is removed once loaded.
+        assert!(Cache::load(dir.path(), &key()).unwrap().is_none());
+
+        // A different toolchain invalidates the cache.
+        cache.save(dir.path()).unwrap();
+        let other_key = CacheKey {
+            rustc_version: "rustc 1.71.0".to_owned(),
+            ..key()
+        };
+        assert!(Cache::load(dir.path(), &other_key).unwrap().is_none());
+    }
+
+    #[test]
+    fn stale_packages() {
+        let config = parse(
+            r#"
+            [pkg.crab1]
+            allow_unsafe = true
+
+            [pkg.crab2]
+            allow_apis = ["fs"]
+
+            [api.fs]
+            include = ["std::fs"]
+            "#,
+        )
+        .unwrap();
+        let mut cache = Cache::new(CacheKey::default());
+        for crate_name in ["crab1", "crab2", "crab3.build"] {
+            cache.record_source_paths(&crate_name.into(), &config, &[]);
+        }
+        assert!(cache.remove_stale_packages(&config).is_empty());
+
+        // Changing which APIs a package may use doesn't require a rebuild, but changing whether it
+        // may use unsafe or how its build script is sandboxed does.
+        let config = parse(
+            r#"
+            [pkg.crab1]
+
+            [pkg.crab2]
+            allow_apis = []
+
+            [pkg.crab3.build.sandbox]
+            kind = "Disabled"
+
+            [api.fs]
+            include = ["std::fs"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cache.remove_stale_packages(&config), vec!["crab1", "crab3"]);
+        assert_eq!(
+            cache
+                .crates()
+                .map(|(crate_name, _)| crate_name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["crab2"]
+        );
+    }
+}
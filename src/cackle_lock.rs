@@ -0,0 +1,321 @@
+//! Support for `cackle.lock`, which records for each package the particular APIs that it was
+//! observed to use. The config only says what categories of API each package is permitted to use,
+//! so the lock lets us detect changes that the config would permit, such as a package that was
+//! permitted to use the filesystem starting to delete files.
+
+use crate::config::CrateName;
+use crate::config::PermissionName;
+use crate::crate_index::CrateIndex;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+const LOCK_VERSION: i64 = 1;
+
+const HEADER: &str =
+    "# This file is generated by cackle. It records the APIs used by each package.\n";
+
+/// For each crate, for each permission, the names of APIs that the crate was observed to use.
+pub(crate) type ObservedUsages = BTreeMap<CrateName, BTreeMap<PermissionName, BTreeSet<String>>>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct CackleLock {
+    version: i64,
+
+    #[serde(default, rename = "package")]
+    packages: Vec<LockedPackage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct LockedPackage {
+    name: String,
+
+    version: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+
+    /// APIs used by the package, keyed by permission name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    apis: BTreeMap<String, BTreeSet<String>>,
+
+    /// APIs used by the package's build script, keyed by permission name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    build_apis: BTreeMap<String, BTreeSet<String>>,
+
+    /// APIs used by the package's tests, benches and examples, or by the package itself if it's
+    /// only used via dev-dependencies, keyed by permission name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    test_apis: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Returns the path of the lock file that goes with the config file at `config_path`.
+pub(crate) fn lock_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("cackle.lock")
+}
+
+impl CackleLock {
+    pub(crate) fn new(crate_index: &CrateIndex, observed: &ObservedUsages) -> Self {
+        let apis_for = |crate_name: &CrateName| {
+            observed
+                .get(crate_name)
+                .map(|usages| {
+                    usages
+                        .iter()
+                        .map(|(permission, names)| (permission.to_string(), names.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mut package_names: Vec<&CrateName> = crate_index.package_names().collect();
+        package_names.sort();
+        let packages = package_names
+            .into_iter()
+            .filter_map(|pkg_name| {
+                let info = crate_index.package_info(pkg_name)?;
+                Some(LockedPackage {
+                    name: pkg_name.as_ref().to_owned(),
+                    version: info.version.to_string(),
+                    checksum: info.checksum.clone(),
+                    apis: apis_for(pkg_name),
+                    build_apis: apis_for(&pkg_name.build_script()),
+                    test_apis: apis_for(&pkg_name.tests()),
+                })
+            })
+            .collect();
+        Self {
+            version: LOCK_VERSION,
+            packages,
+        }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        let lock: CackleLock = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse `{}`", path.display()))?;
+        if lock.version != LOCK_VERSION {
+            bail!(
+                "`{}` has unsupported version {}",
+                path.display(),
+                lock.version
+            );
+        }
+        Ok(lock)
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let toml = format!("{HEADER}\n{}", toml::to_string(self)?);
+        crate::fs::write_atomic(path, &toml)
+    }
+
+    /// Returns a description of each package version in `new` that isn't in `self` or that has a
+    /// different checksum, and of each package version in `self` that's no longer in `new`.
+    pub(crate) fn package_differences(&self, new: &CackleLock) -> Vec<String> {
+        let old_versions = self.versions_by_package();
+        let new_versions = new.versions_by_package();
+        let empty = BTreeMap::new();
+        let names: BTreeSet<&str> = old_versions
+            .keys()
+            .chain(new_versions.keys())
+            .copied()
+            .collect();
+        let mut differences = Vec::new();
+        for name in names {
+            let old = old_versions.get(name).unwrap_or(&empty);
+            let new = new_versions.get(name).unwrap_or(&empty);
+            for (version, checksum) in new {
+                match old.get(version) {
+                    None => differences.push(format!("`{name}` {version} isn't in the lock")),
+                    Some(old_checksum) if old_checksum != checksum => differences.push(format!(
+                        "`{name}` {version} has checksum {} rather than {}",
+                        checksum.unwrap_or("none"),
+                        old_checksum.unwrap_or("none")
+                    )),
+                    Some(_) => {}
+                }
+            }
+            for version in old.keys().filter(|version| !new.contains_key(*version)) {
+                differences.push(format!("`{name}` {version} is no longer used"));
+            }
+        }
+        differences
+    }
+
+    /// Returns a description of each way in which the API usages recorded in `self` differ from
+    /// those in `new`. Packages that appear in only one of the locks are treated as using no APIs.
+    /// Packages are compared by name, so if there are multiple versions of a package, then their
+    /// usages are combined.
+    pub(crate) fn usage_differences(&self, new: &CackleLock) -> Vec<String> {
+        let empty = BTreeMap::new();
+        let old_usages = self.usages_by_crate();
+        let new_usages = new.usages_by_crate();
+        let crate_names: BTreeSet<&String> = old_usages.keys().chain(new_usages.keys()).collect();
+        let mut differences = Vec::new();
+        for crate_name in crate_names {
+            let old_apis = old_usages.get(crate_name).unwrap_or(&empty);
+            let new_apis = new_usages.get(crate_name).unwrap_or(&empty);
+            for (change, from, to) in [
+                ("now", old_apis, new_apis),
+                ("no longer", new_apis, old_apis),
+            ] {
+                for (permission, names) in to {
+                    let other_names = from.get(permission);
+                    for name in names {
+                        if !other_names.map_or(false, |other| other.contains(name)) {
+                            differences.push(format!(
+                                "`{crate_name}` {change} uses `{name}` ({permission})"
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        differences
+    }
+
+    /// Returns the checksum of each version of each package.
+    fn versions_by_package(&self) -> BTreeMap<&str, BTreeMap<&str, Option<&str>>> {
+        let mut versions: BTreeMap<&str, BTreeMap<&str, Option<&str>>> = BTreeMap::new();
+        for pkg in &self.packages {
+            versions
+                .entry(pkg.name.as_str())
+                .or_default()
+                .insert(pkg.version.as_str(), pkg.checksum.as_deref());
+        }
+        versions
+    }
+
+    fn usages_by_crate(&self) -> BTreeMap<String, BTreeMap<String, BTreeSet<String>>> {
+        let mut usages: BTreeMap<String, BTreeMap<String, BTreeSet<String>>> = BTreeMap::new();
+        for pkg in &self.packages {
+            let pkg_name = CrateName::from(pkg.name.as_str());
+            for (crate_name, apis) in [
+                (pkg_name.clone(), &pkg.apis),
+                (pkg_name.build_script(), &pkg.build_apis),
+                (pkg_name.tests(), &pkg.test_apis),
+            ] {
+                if apis.is_empty() {
+                    continue;
+                }
+                let crate_usages = usages.entry(crate_name.to_string()).or_default();
+                for (permission, names) in apis {
+                    crate_usages
+                        .entry(permission.clone())
+                        .or_default()
+                        .extend(names.iter().cloned());
+                }
+            }
+        }
+        usages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CackleLock;
+    use indoc::indoc;
+
+    #[test]
+    fn round_trip_and_differences() {
+        let old_toml = indoc! {r#"
+            version = 1
+
+            [[package]]
+            name = "crab1"
+            version = "1.0.0"
+            checksum = "abc123"
+
+            [package.apis]
+            fs = ["std::fs::read"]
+
+            [package.build_apis]
+            env = ["std::env::var"]
+
+            [[package]]
+            name = "crab2"
+            version = "0.1.0"
+        "#};
+        let old: CackleLock = toml::from_str(old_toml).unwrap();
+        assert_eq!(toml::to_string(&old).unwrap(), old_toml);
+
+        let new: CackleLock = toml::from_str(indoc! {r#"
+            version = 1
+
+            [[package]]
+            name = "crab1"
+            version = "1.0.1"
+
+            [package.apis]
+            fs = ["std::fs::read", "std::fs::remove_file"]
+
+            [[package]]
+            name = "crab2"
+            version = "0.1.0"
+        "#})
+        .unwrap();
+        assert_eq!(
+            old.usage_differences(&new),
+            vec![
+                "`crab1` now uses `std::fs::remove_file` (fs)",
+                "`crab1.build` no longer uses `std::env::var` (env)",
+            ]
+        );
+        assert!(old.usage_differences(&old).is_empty());
+    }
+
+    #[test]
+    fn package_differences() {
+        let old: CackleLock = toml::from_str(indoc! {r#"
+            version = 1
+
+            [[package]]
+            name = "crab1"
+            version = "1.0.0"
+            checksum = "abc123"
+
+            [[package]]
+            name = "crab2"
+            version = "0.1.0"
+            checksum = "def456"
+
+            [[package]]
+            name = "crab3"
+            version = "0.1.0"
+        "#})
+        .unwrap();
+        let new: CackleLock = toml::from_str(indoc! {r#"
+            version = 1
+
+            [[package]]
+            name = "crab1"
+            version = "1.0.1"
+            checksum = "abc124"
+
+            [[package]]
+            name = "crab2"
+            version = "0.1.0"
+            checksum = "bad000"
+
+            [[package]]
+            name = "crab3"
+            version = "0.1.0"
+        "#})
+        .unwrap();
+        assert_eq!(
+            old.package_differences(&new),
+            vec![
+                "`crab1` 1.0.1 isn't in the lock",
+                "`crab1` 1.0.0 is no longer used",
+                "`crab2` 0.1.0 has checksum bad000 rather than def456",
+            ]
+        );
+        assert!(old.package_differences(&old).is_empty());
+    }
+}
@@ -1,4 +1,7 @@
 use crate::build_script_checker;
+use crate::cache::Cache;
+use crate::cache::CacheKey;
+use crate::cackle_lock::ObservedUsages;
 use crate::config::ApiPath;
 use crate::config::Config;
 use crate::config::CrateName;
@@ -7,6 +10,7 @@ use crate::crate_index::CrateIndex;
 use crate::link_info::LinkInfo;
 use crate::names::Name;
 use crate::problem::ApiUsages;
+use crate::problem::DisallowedVersion;
 use crate::problem::Problem;
 use crate::problem::ProblemList;
 use crate::problem::UnusedAllowApi;
@@ -15,6 +19,7 @@ use crate::proxy::rpc::UnsafeUsage;
 use crate::symbol::Symbol;
 use crate::symbol_graph::object_file_path::ObjectFilePath;
 use crate::symbol_graph::UsageDebugData;
+use crate::why::CallPath;
 use crate::Args;
 use crate::CheckState;
 use anyhow::anyhow;
@@ -23,6 +28,7 @@ use anyhow::Result;
 use log::info;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::path::Path;
@@ -43,8 +49,24 @@ pub(crate) struct Checker {
     /// map to a single crate, but in rare cases multiple crates within a package could use the same
     /// source path.
     path_to_crate: HashMap<PathBuf, Vec<CrateName>>,
+    /// Results from previous runs. Only present when running incrementally.
+    cache: Option<Cache>,
+    /// The APIs that each crate was observed to use, whether or not they're permitted.
+    pub(crate) observed_usages: ObservedUsages,
+    /// Packages whose config we ignored because the package's version didn't match.
+    disallowed_versions: Vec<DisallowedVersion>,
+    /// Paths found when running `cackle why`.
+    pub(crate) call_paths: Vec<CallPath>,
+    /// API usages in code that isn't reachable, whether or not they're permitted.
+    unreachable_usages: Vec<ApiUsages>,
+    /// API usages in code that is reachable from at least one binary. A usage can be unreachable
+    /// in one binary, but reachable in another.
+    reachable_usages: HashSet<UsageKey>,
 }
 
+/// Identifies an API usage regardless of which binary it was found in.
+type UsageKey = (CrateName, PermissionName, Symbol<'static>, SourceLocation);
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub(crate) struct PermId(usize);
 
@@ -67,12 +89,17 @@ pub(crate) struct CrateInfo {
     allow_proc_macro: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct ApiUsage {
     pub(crate) source_location: SourceLocation,
     pub(crate) from: Symbol<'static>,
     pub(crate) to: Name,
     pub(crate) to_symbol: Symbol<'static>,
+    /// If the usage is in code that was inlined, the location within each function in the chain of
+    /// inlined functions, starting with the innermost. `source_location` is one of these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) inline_frames: Vec<SourceLocation>,
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
     pub(crate) debug_data: Option<UsageDebugData>,
 }
 
@@ -100,12 +127,53 @@ impl Checker {
             args,
             crate_index,
             path_to_crate: Default::default(),
+            cache: None,
+            observed_usages: Default::default(),
+            disallowed_versions: Vec::new(),
+            call_paths: Vec::new(),
+            unreachable_usages: Vec::new(),
+            reachable_usages: HashSet::new(),
+        }
+    }
+
+    /// Loads results from previous runs, cleaning packages for which those results are no longer
+    /// valid. If we have no valid results, then everything is cleaned. Must be called after the
+    /// config has been loaded.
+    pub(crate) fn init_cache(&mut self, root_path: &Path) -> Result<()> {
+        let key = CacheKey::new(root_path, &self.config, &self.args)?;
+        let Some(mut cache) = Cache::load(&self.target_dir, &key)? else {
+            info!("No usable cache, cleaning");
+            crate::proxy::clean(root_path, &self.args)?;
+            self.cache = Some(Cache::new(key));
+            return Ok(());
+        };
+        for package_name in cache.remove_stale_packages(&self.config) {
+            info!("Config for `{package_name}` changed, cleaning");
+            crate::proxy::clean_package(root_path, &self.args, &package_name)?;
+        }
+        for (crate_name, entry) in cache.crates() {
+            for path in &entry.source_paths {
+                self.path_to_crate
+                    .entry(path.to_owned())
+                    .or_default()
+                    .push(crate_name.clone());
+            }
         }
+        self.cache = Some(cache);
+        Ok(())
+    }
+
+    pub(crate) fn save_cache(&self) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            cache.save(&self.target_dir)?;
+        }
+        Ok(())
     }
 
     /// Load (or reload) config. Note in the case of reloading, permissions are only ever additive.
     pub(crate) fn load_config(&mut self) -> Result<()> {
         let config = crate::config::parse_file(&self.config_path, &self.crate_index)?;
+        self.disallowed_versions = config.disallowed_versions(&self.crate_index);
         // Every time we reload our configuration, we rewrite the flattened configuration. The
         // flattened configuration is used by subprocesses rather than using the original
         // configuration since using the original would require each subprocess to run `cargo
@@ -141,15 +209,26 @@ impl Checker {
             }
         }
         for (crate_name, crate_config) in &config.packages {
-            let crate_info = self
-                .crate_infos
-                .entry(crate_name.as_ref().into())
-                .or_default();
-            crate_info.has_config = true;
-            crate_info.allow_proc_macro = crate_config.allow_proc_macro;
-            for perm in &crate_config.allow_apis {
-                if crate_info.allowed_perms.insert(perm.clone()) {
-                    crate_info.unused_allowed_perms.insert(perm.clone());
+            // Config applies to each version of the crate in our dependency tree that it matches.
+            // If there are no versions of the crate, then we record the config against the
+            // unversioned name, so that we can report it as unused.
+            let mut versions: Vec<CrateName> = self
+                .crate_index
+                .versions_of(crate_name)
+                .filter(|name| config.package_config(name).is_some())
+                .cloned()
+                .collect();
+            if self.crate_index.versions_of(crate_name).next().is_none() {
+                versions.push(crate_name.clone());
+            }
+            for crate_name in versions {
+                let crate_info = self.crate_infos.entry(crate_name).or_default();
+                crate_info.has_config = true;
+                crate_info.allow_proc_macro = crate_config.allow_proc_macro;
+                for perm in &crate_config.allow_apis {
+                    if crate_info.allowed_perms.insert(perm.clone()) {
+                        crate_info.unused_allowed_perms.insert(perm.clone());
+                    }
                 }
             }
         }
@@ -163,6 +242,9 @@ impl Checker {
                 problems.push(Problem::IsProcMacro(crate_name.clone()));
             }
         }
+        for disallowed in &self.disallowed_versions {
+            problems.push(Problem::DisallowedVersion(disallowed.clone()));
+        }
         problems
     }
 
@@ -175,22 +257,76 @@ impl Checker {
             return Ok(self.base_problems());
         };
         match request {
-            rpc::Request::CrateUsesUnsafe(usage) => Ok(self.crate_uses_unsafe(usage)),
+            rpc::Request::CrateUsesUnsafe(usage) => {
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.record_unsafe(&usage.crate_name, &self.config, &usage.locations);
+                }
+                Ok(self.crate_uses_unsafe(usage))
+            }
             rpc::Request::LinkerInvoked(link_info) => {
                 self.check_linker_invocation(link_info, check_state)
             }
-            rpc::Request::BuildScriptComplete(output) => Ok(self.check_build_script_output(output)),
+            rpc::Request::BuildScriptComplete(output) => {
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.record_build_script_output(output, &self.config);
+                }
+                Ok(self.check_build_script_output(output))
+            }
             rpc::Request::RustcComplete(info) => {
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.record_source_paths(&info.crate_name, &self.config, &info.source_paths);
+                }
                 self.record_crate_paths(info);
                 Ok(ProblemList::default())
             }
             rpc::Request::RustcStarted(crate_name) => {
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.record_rustc_started(crate_name, &self.config);
+                }
                 info!("Rustc started compiling {crate_name}");
                 Ok(ProblemList::default())
             }
+            rpc::Request::LibraryBuilt(info) => self.check_library(info, check_state),
         }
     }
 
+    /// Checks all the code in a library that has just been built. Only used in library mode.
+    fn check_library(
+        &mut self,
+        info: &rpc::LibraryInfo,
+        check_state: &mut CheckState,
+    ) -> Result<ProblemList> {
+        if check_state.graph_outputs.is_none() {
+            let start = std::time::Instant::now();
+            let graph_outputs = crate::symbol_graph::scan_library(&info.rlib_path, self)?;
+            if self.args.print_timing {
+                println!(
+                    "Scanning {} took {}ms",
+                    info.crate_name,
+                    start.elapsed().as_millis()
+                );
+            }
+            check_state.graph_outputs = Some(graph_outputs);
+        }
+        let graph_outputs = check_state.graph_outputs.as_ref().unwrap();
+        if let Some(cache) = self.cache.as_mut() {
+            cache.record_link(
+                &info.rlib_path,
+                std::slice::from_ref(&info.rlib_path),
+                graph_outputs.api_usages(),
+            )?;
+        }
+        let problems = graph_outputs
+            .problems(self)?
+            .grouped_by_type_crate_and_api();
+        info!(
+            "Checking library {}. {} problems",
+            info.crate_name,
+            problems.len()
+        );
+        Ok(problems)
+    }
+
     fn check_linker_invocation(
         &mut self,
         info: &LinkInfo,
@@ -200,11 +336,18 @@ impl Checker {
         if info.is_build_script {
             problems.merge(self.verify_build_script_permitted(&info.package_name));
         }
-        problems.merge(self.check_object_paths(
-            &info.object_paths_under(&self.target_dir),
-            &info.output_file,
-            check_state,
-        )?);
+        let mut object_paths = info.object_paths_under(&self.target_dir);
+        if self.config.common.library_mode {
+            // Rlibs are checked in their entirety as they're built, so we only need to check the
+            // binary's own objects.
+            object_paths.retain(|path| path.extension().map_or(true, |ext| ext != "rlib"));
+        }
+        problems.merge(self.check_object_paths(&object_paths, &info.output_file, check_state)?);
+        if let (Some(cache), Some(graph_outputs)) =
+            (self.cache.as_mut(), check_state.graph_outputs.as_ref())
+        {
+            cache.record_link(&info.output_file, &object_paths, graph_outputs.api_usages())?;
+        }
         let problems = problems.grouped_by_type_crate_and_api();
         info!(
             "Checking linker args for {} with {} objects. {} problems",
@@ -223,10 +366,13 @@ impl Checker {
     ) -> Result<ProblemList> {
         if check_state.graph_outputs.is_none() {
             let start = std::time::Instant::now();
-            let graph_outputs = crate::symbol_graph::scan_objects(paths, exe_path, self)?;
+            let mut graph_outputs = crate::symbol_graph::scan_objects(paths, exe_path, self)?;
             if self.args.print_timing {
                 println!("Graph computation took {}ms", start.elapsed().as_millis());
             }
+            self.call_paths.extend(graph_outputs.take_call_paths());
+            self.unreachable_usages
+                .extend_from_slice(graph_outputs.unreachable_api_usages());
             check_state.graph_outputs = Some(graph_outputs);
         }
         let graph_outputs = check_state.graph_outputs.as_ref().unwrap();
@@ -238,6 +384,42 @@ impl Checker {
         Ok(problems)
     }
 
+    /// Checks results from previous runs for crates that cargo didn't rebuild during this run.
+    pub(crate) fn check_cached(&mut self) -> ProblemList {
+        let Some(mut cache) = self.cache.take() else {
+            return ProblemList::default();
+        };
+        let mut problems = ProblemList::default();
+        for (crate_name, entry) in cache.unrefreshed_crates() {
+            if let Some(output) = &entry.build_script_output {
+                problems.merge(self.check_build_script_output(output));
+            }
+            if !entry.unsafe_locations.is_empty()
+                && !self.config.unsafe_permitted_for_crate(crate_name)
+            {
+                problems.push(Problem::DisallowedUnsafe(UnsafeUsage {
+                    crate_name: crate_name.clone(),
+                    locations: entry.unsafe_locations.clone(),
+                }));
+            }
+        }
+        let (links, stale_links) = cache.unrefreshed_links();
+        for output_file in stale_links {
+            problems.push(Problem::new(format!(
+                "Cached analysis of `{}` is out of date and it wasn't relinked. \
+                 Try running without --incremental",
+                output_file.display()
+            )));
+        }
+        for link in links {
+            for api_usage in &link.api_usages {
+                self.permission_used(api_usage, &mut problems);
+            }
+        }
+        self.cache = Some(cache);
+        problems.grouped_by_type_crate_and_api()
+    }
+
     fn check_build_script_output(&self, output: &rpc::BuildScriptOutput) -> ProblemList {
         build_script_checker::check(output, &self.config)
     }
@@ -246,11 +428,14 @@ impl Checker {
         Problem::DisallowedUnsafe(usage.clone()).into()
     }
 
-    pub(crate) fn verify_build_script_permitted(&mut self, package_name: &str) -> ProblemList {
+    pub(crate) fn verify_build_script_permitted(
+        &mut self,
+        package_name: &CrateName,
+    ) -> ProblemList {
         if !self.config.common.explicit_build_scripts {
             return ProblemList::default();
         }
-        let crate_name = CrateName::from(format!("{package_name}.build").as_str());
+        let crate_name = package_name.build_script();
         if let Some(crate_info) = self.crate_infos.get_mut(&crate_name) {
             if crate_info.has_config {
                 return ProblemList::default();
@@ -313,6 +498,17 @@ impl Checker {
     pub(crate) fn permission_used(&mut self, api_usage: &ApiUsages, problems: &mut ProblemList) {
         assert_eq!(api_usage.usages.keys().count(), 1);
         let permission = api_usage.usages.keys().next().unwrap();
+        let observed = self
+            .observed_usages
+            .entry(api_usage.crate_name.clone())
+            .or_default()
+            .entry(permission.clone())
+            .or_default();
+        for usage in api_usage.usages.values().flatten() {
+            observed.insert(format!("{:#}", usage.to_symbol));
+            self.reachable_usages
+                .insert(usage_key(&api_usage.crate_name, permission, usage));
+        }
         let crate_info = &mut self
             .crate_infos
             .entry(api_usage.crate_name.clone())
@@ -324,17 +520,77 @@ impl Checker {
         }
     }
 
+    /// Returns problems for API usages in unreachable code that our config doesn't permit. These
+    /// are just for information, since unreachable code doesn't need permissions. Usages that are
+    /// reachable from some other binary are excluded, since they've already been checked.
+    pub(crate) fn unreachable_problems(&self) -> ProblemList {
+        let mut by_crate: BTreeMap<&CrateName, ApiUsages> = BTreeMap::new();
+        for api_usages in &self.unreachable_usages {
+            let allowed_perms = self
+                .crate_infos
+                .get(&api_usages.crate_name)
+                .map(|crate_info| &crate_info.allowed_perms);
+            for (permission, usages) in &api_usages.usages {
+                if allowed_perms.map_or(false, |allowed| allowed.contains(permission)) {
+                    continue;
+                }
+                let mut usages = usages
+                    .iter()
+                    .filter(|usage| {
+                        !self.reachable_usages.contains(&usage_key(
+                            &api_usages.crate_name,
+                            permission,
+                            usage,
+                        ))
+                    })
+                    .peekable();
+                if usages.peek().is_none() {
+                    continue;
+                }
+                let merged = by_crate
+                    .entry(&api_usages.crate_name)
+                    .or_insert_with(|| ApiUsages {
+                        crate_name: api_usages.crate_name.clone(),
+                        usages: BTreeMap::new(),
+                    });
+                let merged_usages = merged.usages.entry(permission.clone()).or_default();
+                for usage in usages {
+                    if !merged_usages.contains(usage) {
+                        merged_usages.push(usage.clone());
+                    }
+                }
+            }
+        }
+        let mut problems = ProblemList::default();
+        for api_usages in by_crate.into_values() {
+            problems.push(Problem::UnreachableApiUsage(api_usages));
+        }
+        problems
+    }
+
     pub(crate) fn check_unused(&self) -> ProblemList {
         let mut problems = ProblemList::default();
         let crate_names: HashSet<_> = self.crate_index.crate_names().collect();
+        // All versions of a crate share the same config, so a permission is only unused if no
+        // version of the crate uses it.
+        let mut unused_by_name: HashMap<CrateName, HashSet<PermissionName>> = HashMap::new();
         for (crate_name, crate_info) in &self.crate_infos {
             if crate_info.has_config && !crate_names.contains(crate_name) {
                 problems.push(Problem::UnusedPackageConfig(crate_name.clone()));
             }
-            if !crate_info.unused_allowed_perms.is_empty() {
+            if !crate_info.has_config {
+                continue;
+            }
+            unused_by_name
+                .entry(crate_name.unversioned())
+                .and_modify(|unused| unused.retain(|p| crate_info.unused_allowed_perms.contains(p)))
+                .or_insert_with(|| crate_info.unused_allowed_perms.clone());
+        }
+        for (crate_name, unused) in unused_by_name {
+            if !unused.is_empty() {
                 problems.push(Problem::UnusedAllowApi(UnusedAllowApi {
-                    crate_name: crate_name.clone(),
-                    permissions: crate_info.unused_allowed_perms.iter().cloned().collect(),
+                    crate_name,
+                    permissions: unused.into_iter().collect(),
                 }));
             }
         }
@@ -353,12 +609,21 @@ impl Checker {
     pub(crate) fn print_path_to_crate_map(&self) {
         for (path, crates) in &self.path_to_crate {
             for c in crates {
-                println!("{} -> {}", path.display(), c);
+                eprintln!("{} -> {}", path.display(), c);
             }
         }
     }
 }
 
+fn usage_key(crate_name: &CrateName, permission: &PermissionName, usage: &ApiUsage) -> UsageKey {
+    (
+        crate_name.clone(),
+        permission.clone(),
+        usage.from.clone(),
+        usage.source_location.clone(),
+    )
+}
+
 impl SourceLocation {
     // Returns whether this source location is from the rust standard library or precompiled crates
     // that are bundled with the standard library (e.g. hashbrown).
@@ -466,6 +731,7 @@ mod tests {
                     from: Symbol::borrowed(&[]),
                     to: crate::names::split_names("foo:bar").pop().unwrap(),
                     to_symbol: Symbol::borrowed(&[]),
+                    inline_frames: Vec::new(),
                     debug_data: None,
                 }],
             );
@@ -483,4 +749,64 @@ mod tests {
         checker.update_config(config);
         assert!(checker.check_unused().is_empty());
     }
+
+    #[test]
+    fn reachable_in_another_binary() {
+        let config = parse(
+            r#"
+            [api.fs]
+            include = [
+                "std::fs",
+            ]
+        "#,
+        )
+        .unwrap();
+        let mut checker = Checker {
+            crate_index: crate::crate_index::testing::index_with_crate_names(&["foo", "bar"]),
+            ..Checker::default()
+        };
+        checker.update_config(config);
+        let usage = |crate_name: &str, line| {
+            let mut usages = BTreeMap::new();
+            usages.insert(
+                PermissionName::from("fs"),
+                vec![ApiUsage {
+                    source_location: SourceLocation {
+                        filename: format!("{crate_name}/src/lib.rs").into(),
+                        line,
+                        column: None,
+                    },
+                    from: Symbol::borrowed(crate_name.as_bytes()).to_heap(),
+                    to: crate::names::split_names("std::fs::read").pop().unwrap(),
+                    to_symbol: Symbol::borrowed(b"read").to_heap(),
+                    inline_frames: Vec::new(),
+                    debug_data: None,
+                }],
+            );
+            ApiUsages {
+                crate_name: CrateName::from(crate_name),
+                usages,
+            }
+        };
+
+        // The first binary reaches foo's usage on line 1, but not the one on line 2.
+        let mut problems = ProblemList::default();
+        checker.permission_used(&usage("foo", 1), &mut problems);
+        assert_eq!(problems.len(), 1);
+        checker.unreachable_usages.push(usage("foo", 2));
+
+        // The second binary reaches neither of foo's usages, nor bar's.
+        checker.unreachable_usages.push(usage("foo", 1));
+        checker.unreachable_usages.push(usage("foo", 2));
+        checker.unreachable_usages.push(usage("bar", 1));
+
+        let problems = checker.unreachable_problems();
+        assert_eq!(
+            problems.into_iter().cloned().collect::<Vec<_>>(),
+            vec![
+                Problem::UnreachableApiUsage(usage("bar", 1)),
+                Problem::UnreachableApiUsage(usage("foo", 2)),
+            ]
+        );
+    }
 }
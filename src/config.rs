@@ -1,13 +1,18 @@
 use crate::crate_index::CrateIndex;
 use crate::problem::AvailableApi;
+use crate::problem::DisallowedVersion;
 use crate::problem::Problem;
 use crate::problem::ProblemList;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use cargo_metadata::semver::Version;
+use cargo_metadata::semver::VersionReq;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use serde::Serializer;
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::path::Path;
@@ -31,13 +36,25 @@ pub(crate) struct Config {
 
     #[serde(default)]
     pub(crate) sandbox: SandboxConfig,
+
+    #[serde(default)]
+    pub(crate) rustc: RustcConfig,
+
+    #[serde(default, rename = "build")]
+    pub(crate) builds: Vec<BuildConfig>,
 }
 
 /// The name of a crate. Sort of. It's actually somewhere between a package and a crate. It's the
-/// package name except if it's a build script, in which case it's `{package_name}.build`.
-#[derive(Debug, Hash, PartialEq, Eq, Clone, Serialize, Deserialize, PartialOrd, Ord)]
-#[serde(transparent)]
-pub(crate) struct CrateName(pub(crate) Arc<str>);
+/// package name except if it's a build script, in which case it's `{package_name}.build`, or if
+/// it's only built for tests, benches or examples, in which case it's `{package_name}.test`. Crate
+/// names obtained from our dependency tree also have the version of the package, since a tree can
+/// contain multiple versions of a package. Names from our config don't have a version, since they
+/// apply to all versions of the package. When serialised, the version, if any, follows an `@`.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, PartialOrd, Ord)]
+pub(crate) struct CrateName {
+    name: Arc<str>,
+    version: Option<Version>,
+}
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -52,8 +69,46 @@ pub(crate) struct CommonConfig {
 
     #[serde(default)]
     pub(crate) features: Vec<String>,
+
+    /// Additional flags to pass to `cargo build`. e.g. `--all-targets` to also check tests,
+    /// benches and examples.
+    #[serde(default)]
+    pub(crate) build_flags: Vec<String>,
+
+    /// Whether to only require permissions for API usages in code that's reachable from the entry
+    /// points of the binary being checked.
+    #[serde(default)]
+    pub(crate) only_reachable: bool,
+
+    /// Whether to check all the code in each library as it's built, rather than only the code
+    /// that gets linked into a binary.
+    #[serde(default)]
+    pub(crate) library_mode: bool,
+}
+
+/// A configuration in which to build. If multiple are given, then each is built and checked.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BuildConfig {
+    /// Features to enable in addition to those in `common.features`.
+    #[serde(default)]
+    pub(crate) features: Vec<String>,
+
+    #[serde(default)]
+    pub(crate) no_default_features: bool,
+
+    /// The target triple to build for. Defaults to the `--target` command-line argument if given,
+    /// otherwise the host.
+    pub(crate) target: Option<String>,
+
+    /// Flags to pass to `cargo build` in addition to those in `common.build_flags`.
+    #[serde(default)]
+    pub(crate) build_flags: Vec<String>,
 }
 
+/// The flags that are permitted in `build_flags`.
+pub(crate) const BUILD_FLAGS: &[&str] = &["--tests", "--benches", "--examples", "--all-targets"];
+
 #[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct SandboxConfig {
@@ -63,10 +118,68 @@ pub(crate) struct SandboxConfig {
     #[serde(default)]
     pub(crate) allow_read: Vec<String>,
 
+    #[serde(default)]
+    pub(crate) allow_write: Vec<String>,
+
+    /// Environment variables whose values are passed through to the sandbox.
+    #[serde(default)]
+    pub(crate) pass_env: Vec<String>,
+
+    /// Environment variables to set in the sandbox.
+    #[serde(default)]
+    pub(crate) set_env: BTreeMap<String, String>,
+
     #[serde(default)]
     pub(crate) extra_args: Vec<String>,
 
     pub(crate) allow_network: Option<bool>,
+
+    /// Hosts that build scripts can connect to via a filtering proxy when `allow_network` isn't
+    /// set. Each is either `host` or `host:port`.
+    #[serde(default)]
+    pub(crate) allow_hosts: Vec<String>,
+
+    /// The maximum CPU time in seconds that a build script may use.
+    pub(crate) max_cpu_secs: Option<u64>,
+
+    /// The maximum time in seconds that a build script may run for.
+    pub(crate) max_wall_secs: Option<u64>,
+
+    /// The maximum size of each process's address space in MiB.
+    pub(crate) max_memory_mib: Option<u64>,
+
+    /// The maximum number of processes and threads that may exist.
+    pub(crate) max_processes: Option<u64>,
+
+    /// Whether to record which programs build scripts execute, so that they can be checked against
+    /// `allow_exec`.
+    pub(crate) observe_exec: Option<bool>,
+
+    /// Overrides for running test binaries via `cackle test`. Only permitted in the top-level
+    /// sandbox configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) test: Option<Box<SandboxConfig>>,
+
+    /// Overrides for running binaries via `cackle run`. Only permitted in the top-level sandbox
+    /// configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) run: Option<Box<SandboxConfig>>,
+}
+
+impl SandboxConfig {
+    /// Returns whether this config contains overrides for `cackle test` or `cackle run`.
+    pub(crate) fn has_profiles(&self) -> bool {
+        self.test.is_some() || self.run.is_some()
+    }
+}
+
+/// Configuration for how we run rustc.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RustcConfig {
+    /// If set, rustc, and therefore any proc macros that it runs, is run in a sandbox. Options not
+    /// specified are inherited from the top-level sandbox configuration.
+    pub(crate) sandbox: Option<SandboxConfig>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default, Hash)]
@@ -97,9 +210,18 @@ pub(crate) enum SandboxKind {
     Inherit,
     Disabled,
     Bubblewrap,
+    Firejail,
+    Nsjail,
+    Landlock,
 }
 
-pub(crate) const SANDBOX_KINDS: &[SandboxKind] = &[SandboxKind::Disabled, SandboxKind::Bubblewrap];
+pub(crate) const SANDBOX_KINDS: &[SandboxKind] = &[
+    SandboxKind::Disabled,
+    SandboxKind::Bubblewrap,
+    SandboxKind::Firejail,
+    SandboxKind::Nsjail,
+    SandboxKind::Landlock,
+];
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
 #[serde(deny_unknown_fields)]
@@ -110,6 +232,25 @@ pub(crate) struct PackageConfig {
     #[serde(default)]
     pub(crate) allow_build_instructions: Vec<String>,
 
+    /// Libraries that the build script is permitted to link via `rustc-link-lib`. Each is either
+    /// `NAME` or `KIND=NAME`.
+    #[serde(default)]
+    pub(crate) allow_link_libs: Vec<String>,
+
+    /// Directories that the build script is permitted to add via `rustc-link-search`, together with
+    /// their subdirectories. May start with `$OUT_DIR` or `$CARGO_MANIFEST_DIR`.
+    #[serde(default)]
+    pub(crate) allow_link_search: Vec<String>,
+
+    /// Environment variables that the build script is permitted to set via `rustc-env`.
+    #[serde(default)]
+    pub(crate) allow_rustc_env: Vec<String>,
+
+    /// Programs that the build script is permitted to execute. Only checked when `observe_exec` is
+    /// set for the build script's sandbox.
+    #[serde(default)]
+    pub(crate) allow_exec: Vec<String>,
+
     #[serde(default)]
     pub(crate) allow_apis: Vec<PermissionName>,
 
@@ -120,11 +261,27 @@ pub(crate) struct PackageConfig {
     /// which it's flattened out.
     build: Option<Box<PackageConfig>>,
 
+    /// Configuration for this crate's tests, benches and examples, or for the crate itself if it's
+    /// only used via dev-dependencies. Only used during parsing, after which it's flattened out.
+    test: Option<Box<PackageConfig>>,
+
     #[serde()]
     pub(crate) sandbox: Option<SandboxConfig>,
 
     #[serde(default)]
     pub(crate) import: Option<Vec<String>>,
+
+    /// If set, then this config only applies when the package's version matches.
+    #[serde(default)]
+    pub(crate) version: Option<VersionReq>,
+}
+
+impl PackageConfig {
+    fn matches_version(&self, version: &Version) -> bool {
+        self.version
+            .as_ref()
+            .map_or(true, |req| req.matches(version))
+    }
 }
 
 pub(crate) fn parse_file(cackle_path: &Path, crate_index: &CrateIndex) -> Result<Arc<Config>> {
@@ -138,7 +295,8 @@ pub(crate) fn parse_file(cackle_path: &Path, crate_index: &CrateIndex) -> Result
     Ok(Arc::new(config))
 }
 
-fn parse(cackle: &str) -> Result<Config> {
+/// Parses config without loading imports or validating it.
+pub(crate) fn parse(cackle: &str) -> Result<Config> {
     let mut config = toml::from_str(cackle)?;
     merge_built_ins(&mut config)?;
     flatten(&mut config);
@@ -177,7 +335,19 @@ impl Config {
             if imports.is_empty() {
                 continue;
             }
-            let pkg_exports = exported_config_for_package(crate_name, crate_index)?;
+            // If our dependency tree has multiple versions of the package, we import from the
+            // newest version that our config applies to.
+            let Some(versioned_name) = crate_index
+                .versions_of(crate_name)
+                .filter(|name| {
+                    name.version()
+                        .map_or(true, |v| pkg_config.matches_version(v))
+                })
+                .max()
+            else {
+                bail!("Can't import from `{crate_name}`, since it isn't in the dependency tree");
+            };
+            let pkg_exports = exported_config_for_package(versioned_name, crate_index)?;
             for (api_name, api_def) in &pkg_exports.apis {
                 if !imports.iter().any(|imp| imp == api_name.name.as_ref()) {
                     // The user didn't request importing this API, so skip it.
@@ -214,8 +384,7 @@ impl Config {
         for crate_name in crate_index.package_names() {
             // If our config lists any import for this package, even empty, then we skip this.
             if self
-                .packages
-                .get(crate_name)
+                .package_config(crate_name)
                 .map(|config| config.import.is_some())
                 .unwrap_or(false)
             {
@@ -234,6 +403,64 @@ impl Config {
         }
         problems
     }
+
+    /// Returns details of each package in our dependency tree whose version doesn't match the
+    /// version required by the package's config.
+    pub(crate) fn disallowed_versions(&self, crate_index: &CrateIndex) -> Vec<DisallowedVersion> {
+        crate_index
+            .package_names()
+            .filter_map(|crate_name| {
+                let version_req = self
+                    .packages
+                    .get(&crate_name.unversioned())?
+                    .version
+                    .as_ref()?;
+                let version = crate_name.version()?;
+                (!version_req.matches(version)).then(|| DisallowedVersion {
+                    crate_name: crate_name.clone(),
+                    version_req: version_req.clone(),
+                    version: version.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns each configuration that we should build, with features and flags from `common`
+    /// merged in. If none are configured, then we do a single build with just what's in `common`.
+    pub(crate) fn build_configs(&self) -> Vec<BuildConfig> {
+        let builds = if self.builds.is_empty() {
+            vec![BuildConfig::default()]
+        } else {
+            self.builds.clone()
+        };
+        builds
+            .into_iter()
+            .map(|build| BuildConfig {
+                features: [self.common.features.clone(), build.features].concat(),
+                build_flags: [self.common.build_flags.clone(), build.build_flags].concat(),
+                ..build
+            })
+            .collect()
+    }
+
+    /// Returns the config for `crate_name`. If `crate_name` has a version and the config for the
+    /// package has a version requirement that the version doesn't match, then no config applies.
+    /// The version required by a package's config also applies to config for its build script and
+    /// tests.
+    pub(crate) fn package_config(&self, crate_name: &CrateName) -> Option<&PackageConfig> {
+        let pkg_config = self.packages.get(&crate_name.unversioned())?;
+        if let Some(version) = crate_name.version() {
+            let pkg_name = CrateName::from(crate_name.package_name());
+            if !self
+                .packages
+                .get(&pkg_name)
+                .map_or(true, |c| c.matches_version(version))
+            {
+                return None;
+            }
+        }
+        Some(pkg_config)
+    }
 }
 
 fn exported_config_for_package(
@@ -256,6 +483,9 @@ fn flatten(config: &mut Config) {
         if let Some(build_config) = crate_config.build.take() {
             crates_by_name.insert(format!("{name}.build").as_str().into(), *build_config);
         }
+        if let Some(test_config) = crate_config.test.take() {
+            crates_by_name.insert(format!("{name}.test").as_str().into(), *test_config);
+        }
         crates_by_name.insert(name.clone(), crate_config);
     }
     config.packages = crates_by_name;
@@ -283,39 +513,80 @@ impl PermissionName {
 
 impl Config {
     pub(crate) fn unsafe_permitted_for_crate(&self, crate_name: &CrateName) -> bool {
-        self.packages
-            .get(crate_name)
+        self.package_config(crate_name)
             .map(|crate_config| crate_config.allow_unsafe)
             .unwrap_or(false)
     }
 
-    pub(crate) fn sandbox_config_for_build_script(&self, package_name: &str) -> SandboxConfig {
-        self.sandbox_config_for_package(&format!("{package_name}.build").as_str().into())
-    }
-
     /// Returns the configuration for `package_name`, inheriting options from the default sandbox
     /// configuration as appropriate.
     pub(crate) fn sandbox_config_for_package(&self, package_name: &CrateName) -> SandboxConfig {
-        let mut config = self.sandbox.clone();
-        let Some(pkg_sandbox_config) = self
-            .packages
-            .get(package_name)
-            .and_then(|c| c.sandbox.as_ref())
-        else {
+        self.sandbox.with_overrides(
+            self.package_config(package_name)
+                .and_then(|c| c.sandbox.as_ref()),
+        )
+    }
+
+    /// Returns the sandbox configuration for running rustc, or None if rustc shouldn't be run in a
+    /// sandbox.
+    pub(crate) fn sandbox_config_for_rustc(&self) -> Option<SandboxConfig> {
+        let rustc_sandbox_config = self.rustc.sandbox.as_ref()?;
+        Some(self.sandbox.with_overrides(Some(rustc_sandbox_config)))
+    }
+
+    /// Returns the sandbox configuration for running test binaries via `cackle test`.
+    pub(crate) fn sandbox_config_for_test(&self) -> SandboxConfig {
+        self.sandbox.with_overrides(self.sandbox.test.as_deref())
+    }
+
+    /// Returns the sandbox configuration for running binaries via `cackle run`.
+    pub(crate) fn sandbox_config_for_run(&self) -> SandboxConfig {
+        self.sandbox.with_overrides(self.sandbox.run.as_deref())
+    }
+}
+
+impl SandboxConfig {
+    /// Returns a copy of `self` with any options set in `overrides` applied. Overrides for `cackle
+    /// test` and `cackle run` aren't included in the result.
+    fn with_overrides(&self, overrides: Option<&SandboxConfig>) -> SandboxConfig {
+        let mut config = SandboxConfig {
+            test: None,
+            run: None,
+            ..self.clone()
+        };
+        let Some(overrides) = overrides else {
             return config;
         };
-        if pkg_sandbox_config.kind != SandboxKind::Inherit {
-            config.kind = pkg_sandbox_config.kind;
+        if overrides.kind != SandboxKind::Inherit {
+            config.kind = overrides.kind;
         }
         config
             .extra_args
-            .extend(pkg_sandbox_config.extra_args.iter().cloned());
+            .extend(overrides.extra_args.iter().cloned());
         config
             .allow_read
-            .extend(pkg_sandbox_config.allow_read.iter().cloned());
-        if let Some(allow_network) = pkg_sandbox_config.allow_network {
+            .extend(overrides.allow_read.iter().cloned());
+        config
+            .allow_write
+            .extend(overrides.allow_write.iter().cloned());
+        config.pass_env.extend(overrides.pass_env.iter().cloned());
+        config.set_env.extend(
+            overrides
+                .set_env
+                .iter()
+                .map(|(var, value)| (var.clone(), value.clone())),
+        );
+        config
+            .allow_hosts
+            .extend(overrides.allow_hosts.iter().cloned());
+        if let Some(allow_network) = overrides.allow_network {
             config.allow_network = Some(allow_network);
         }
+        config.max_cpu_secs = overrides.max_cpu_secs.or(config.max_cpu_secs);
+        config.max_wall_secs = overrides.max_wall_secs.or(config.max_wall_secs);
+        config.max_memory_mib = overrides.max_memory_mib.or(config.max_memory_mib);
+        config.max_processes = overrides.max_processes.or(config.max_processes);
+        config.observe_exec = overrides.observe_exec.or(config.observe_exec);
         config
     }
 }
@@ -348,24 +619,72 @@ impl AsRef<str> for ApiPath {
 
 impl From<&str> for CrateName {
     fn from(value: &str) -> Self {
-        Self(Arc::from(value))
+        Self {
+            name: Arc::from(value),
+            version: None,
+        }
+    }
+}
+
+impl Default for CrateName {
+    fn default() -> Self {
+        Self::from("")
     }
 }
 
 impl AsRef<str> for CrateName {
+    /// Returns the name without any version.
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.name
     }
 }
 
 impl CrateName {
+    #[cfg(test)]
     pub(crate) fn for_build_script(crate_name: &str) -> Self {
-        Self(Arc::from(format!("{crate_name}.build").as_str()))
+        Self::from(format!("{crate_name}.build").as_str())
+    }
+
+    /// Returns this crate name qualified with `version`.
+    pub(crate) fn with_version(self, version: Version) -> Self {
+        Self {
+            name: self.name,
+            version: Some(version),
+        }
+    }
+
+    /// Returns the crate name for the build script of this crate's package, with the same version.
+    pub(crate) fn build_script(&self) -> Self {
+        Self {
+            name: Arc::from(format!("{}.build", self.package_name()).as_str()),
+            version: self.version.clone(),
+        }
+    }
+
+    /// Returns the crate name for the tests, benches and examples of this crate's package, or for
+    /// the package itself if it's only used via dev-dependencies. Has the same version.
+    pub(crate) fn tests(&self) -> Self {
+        Self {
+            name: Arc::from(format!("{}.test", self.package_name()).as_str()),
+            version: self.version.clone(),
+        }
+    }
+
+    /// Returns the crate name without any version. This is the name used to look up config.
+    pub(crate) fn unversioned(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            version: None,
+        }
+    }
+
+    pub(crate) fn version(&self) -> Option<&Version> {
+        self.version.as_ref()
     }
 
     pub(crate) fn package_name(&self) -> &str {
-        if let Some(dot_index) = self.0.find('.') {
-            &self.0[..dot_index]
+        if let Some(dot_index) = self.name.find('.') {
+            &self.name[..dot_index]
         } else {
             self.as_ref()
         }
@@ -374,7 +693,37 @@ impl CrateName {
 
 impl Display for CrateName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.name.fmt(f)?;
+        if let Some(version) = &self.version {
+            write!(f, "@{version}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for CrateName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, version)) = s.split_once('@') else {
+            return Ok(Self::from(s));
+        };
+        let version = Version::parse(version)
+            .with_context(|| format!("Invalid version in crate name `{s}`"))?;
+        Ok(Self::from(name).with_version(version))
+    }
+}
+
+impl Serialize for CrateName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CrateName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -398,6 +747,7 @@ pub(crate) mod testing {
 #[cfg(test)]
 mod tests {
     use super::testing::parse;
+    use super::CrateName;
     use crate::config::SandboxKind;
     use crate::crate_index::CrateIndex;
     use std::path::PathBuf;
@@ -471,6 +821,67 @@ mod tests {
         assert!(config.packages.contains_key(&"foo.build".into()));
     }
 
+    #[test]
+    fn build_matrix() {
+        let config = parse(
+            r#"
+            features = ["common"]
+
+            [[build]]
+
+            [[build]]
+            features = ["extra"]
+            no_default_features = true
+            target = "x86_64-unknown-linux-musl"
+        "#,
+        )
+        .unwrap();
+        let builds = config.build_configs();
+        assert_eq!(builds.len(), 2);
+        assert_eq!(builds[0].features, vec!["common"]);
+        assert!(!builds[0].no_default_features);
+        assert_eq!(builds[0].target, None);
+        assert_eq!(builds[1].features, vec!["common", "extra"]);
+        assert!(builds[1].no_default_features);
+        assert_eq!(
+            builds[1].target.as_deref(),
+            Some("x86_64-unknown-linux-musl")
+        );
+
+        let config = parse("").unwrap();
+        assert_eq!(config.build_configs(), vec![super::BuildConfig::default()]);
+    }
+
+    #[test]
+    fn crate_test_config() {
+        let config = parse(
+            r#"
+            [pkg.foo.test]
+            allow_unsafe = true
+        "#,
+        )
+        .unwrap();
+        assert!(config.unsafe_permitted_for_crate(&"foo.test".into()));
+        assert!(!config.unsafe_permitted_for_crate(&"foo".into()));
+    }
+
+    #[test]
+    fn build_flags() {
+        let config = parse(
+            r#"
+            build_flags = ["--tests", "--examples"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.common.build_flags, vec!["--tests", "--examples"]);
+        let result = parse(
+            r#"
+            build_flags = ["--release"]
+        "#,
+        );
+        assert!(result.is_err());
+    }
+
     #[test]
     fn sandbox_config_inheritance() {
         let config = parse(
@@ -508,6 +919,143 @@ mod tests {
         assert_eq!(sandbox_b.kind, SandboxKind::Disabled);
     }
 
+    #[test]
+    fn rustc_sandbox() {
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+                allow_read = [
+                    "/foo",
+                ]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sandbox_config_for_rustc(), None);
+
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+                allow_read = [
+                    "/foo",
+                ]
+
+                [rustc.sandbox]
+                allow_read = [
+                    "/bar",
+                ]
+            "#,
+        )
+        .unwrap();
+        let sandbox = config.sandbox_config_for_rustc().unwrap();
+        assert_eq!(sandbox.kind, SandboxKind::Bubblewrap);
+        assert_eq!(sandbox.allow_read, vec!["/foo", "/bar"]);
+    }
+
+    #[test]
+    fn test_and_run_sandbox() {
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+
+                [sandbox.test]
+                allow_network = true
+            "#,
+        )
+        .unwrap();
+        let test_sandbox = config.sandbox_config_for_test();
+        assert_eq!(test_sandbox.kind, SandboxKind::Bubblewrap);
+        assert_eq!(test_sandbox.allow_network, Some(true));
+        assert!(!test_sandbox.has_profiles());
+        assert_eq!(config.sandbox_config_for_run().allow_network, None);
+        assert!(!config
+            .sandbox_config_for_package(&"a.build".into())
+            .has_profiles());
+    }
+
+    #[test]
+    fn build_script_allow_hosts() {
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+                allow_hosts = ["crates.io"]
+
+                [pkg.a.build.sandbox]
+                allow_hosts = ["example.com:443"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .sandbox_config_for_package(&"a.build".into())
+                .allow_hosts,
+            vec!["crates.io", "example.com:443"]
+        );
+    }
+
+    #[test]
+    fn build_script_sandbox_write_and_env() {
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+                pass_env = ["CC"]
+                set_env = { FOO = "1", BAR = "2" }
+
+                [pkg.a.build.sandbox]
+                allow_write = ["/ccache"]
+                pass_env = ["PKG_CONFIG_PATH"]
+                set_env = { FOO = "3" }
+            "#,
+        )
+        .unwrap();
+        let sandbox = config.sandbox_config_for_package(&"a.build".into());
+        assert_eq!(sandbox.allow_write, vec!["/ccache"]);
+        assert_eq!(sandbox.pass_env, vec!["CC", "PKG_CONFIG_PATH"]);
+        assert_eq!(
+            sandbox.set_env,
+            [("BAR", "2"), ("FOO", "3")]
+                .into_iter()
+                .map(|(var, value)| (var.to_owned(), value.to_owned()))
+                .collect()
+        );
+        assert!(config
+            .sandbox_config_for_package(&"b.build".into())
+            .allow_write
+            .is_empty());
+    }
+
+    #[test]
+    fn build_script_resource_limits() {
+        let config = parse(
+            r#"
+                [sandbox]
+                kind = "Bubblewrap"
+                max_cpu_secs = 60
+                max_wall_secs = 120
+
+                [pkg.a.build.sandbox]
+                max_wall_secs = 600
+                max_memory_mib = 2048
+            "#,
+        )
+        .unwrap();
+        let sandbox = config.sandbox_config_for_package(&"a.build".into());
+        assert_eq!(sandbox.max_cpu_secs, Some(60));
+        assert_eq!(sandbox.max_wall_secs, Some(600));
+        assert_eq!(sandbox.max_memory_mib, Some(2048));
+        assert_eq!(sandbox.max_processes, None);
+        assert_eq!(
+            config
+                .sandbox_config_for_package(&"b.build".into())
+                .max_wall_secs,
+            Some(120)
+        );
+    }
+
     #[test]
     fn disallowed_sandbox_override() {
         // A sandbox configuration for a regular package isn't allowed, since we don't run regular
@@ -551,4 +1099,99 @@ mod tests {
         println!("{}", result.as_ref().unwrap_err());
         assert!(result.unwrap_err().to_string().contains("terminate"));
     }
+
+    #[test]
+    fn version_constraints() {
+        let config = parse(
+            r#"
+            [pkg.foo]
+            version = "^1.0.150"
+            allow_unsafe = true
+
+            [pkg.foo.build]
+            allow_build_instructions = ["cargo:rustc-link-lib=*"]
+
+            [pkg.bar]
+            version = "=0.3.1"
+            allow_unsafe = true
+
+            [pkg.baz]
+            allow_unsafe = true
+            "#,
+        )
+        .unwrap();
+        let crate_index = crate::crate_index::testing::index_with_package_versions(&[
+            ("foo", "1.0.200"),
+            ("foo", "2.0.0"),
+            ("bar", "0.3.1"),
+            ("baz", "5.0.0"),
+        ]);
+        let disallowed = config.disallowed_versions(&crate_index);
+        assert_eq!(
+            disallowed
+                .iter()
+                .map(|d| d.crate_name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["foo@2.0.0"]
+        );
+        let permitted = |name: &str| {
+            let crate_name: CrateName = name.parse().unwrap();
+            config.package_config(&crate_name).is_some()
+        };
+        assert!(permitted("foo@1.0.200"));
+        assert!(permitted("foo.build@1.0.200"));
+        assert!(!permitted("foo@2.0.0"));
+        assert!(!permitted("foo.build@2.0.0"));
+        assert!(permitted("foo"));
+        assert!(permitted("bar@0.3.1"));
+        assert!(permitted("baz@5.0.0"));
+    }
+
+    #[test]
+    fn crate_name_round_trip() {
+        for name in ["foo", "foo.build", "foo@1.2.3", "foo.build@1.2.3-beta.1"] {
+            let crate_name: CrateName = name.parse().unwrap();
+            assert_eq!(crate_name.to_string(), name);
+        }
+        let crate_name: CrateName = "foo.build@1.2.3".parse().unwrap();
+        assert_eq!(crate_name.as_ref(), "foo.build");
+        assert_eq!(crate_name.package_name(), "foo");
+        assert_eq!(crate_name.unversioned().to_string(), "foo.build");
+        assert!("foo@1.2".parse::<CrateName>().is_err());
+    }
+
+    #[test]
+    fn versioned_package_name() {
+        let result = parse(
+            r#"
+                [pkg."foo@1.0.0"]
+                allow_unsafe = true
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn disallowed_build_script_version() {
+        let result = parse(
+            r#"
+                [pkg.a.build]
+                version = "1.0"
+            "#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn only_reachable_in_library_mode() {
+        parse("only_reachable = true").unwrap();
+        parse("library_mode = true").unwrap();
+        let result = parse(
+            r#"
+                only_reachable = true
+                library_mode = true
+            "#,
+        );
+        assert!(result.is_err());
+    }
 }
@@ -1,19 +1,24 @@
 //! This module is responsible for applying automatic edits to cackle.toml.
 
+use crate::checker::SourceLocation;
 use crate::config::CrateName;
 use crate::config::PermissionName;
 use crate::config::SandboxKind;
 use crate::problem::ApiUsages;
 use crate::problem::AvailableApi;
+use crate::problem::BuildScriptFailed;
 use crate::problem::Problem;
 use crate::problem::ProblemList;
 use crate::problem::UnusedAllowApi;
+use crate::sandbox::limits::ResourceLimit;
 use anyhow::anyhow;
 use anyhow::Result;
 use std::borrow::Borrow;
 use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::fmt::Display;
 use std::path::Path;
+use std::path::PathBuf;
 use toml_edit::Array;
 use toml_edit::Document;
 use toml_edit::Formatted;
@@ -46,6 +51,13 @@ pub(crate) trait Edit {
     fn resolve_problem_if_edit_is_empty(&self) -> bool {
         true
     }
+
+    /// Applies the edit, then adds comments to the config saying which source locations made the
+    /// edit necessary. Source paths within `root` are shown relative to `root`. Used when
+    /// generating config non-interactively, since nobody looked at each edit as it was applied.
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)
+    }
 }
 
 /// Returns possible fixes for `problem`.
@@ -83,6 +95,7 @@ pub(crate) fn fixes_for_problem(problem: &Problem) -> Vec<Box<dyn Edit>> {
         }
         Problem::BuildScriptFailed(failure) => {
             if failure.output.sandbox_config.kind != SandboxKind::Disabled {
+                edits.append(&mut edits_for_denied_accesses(failure));
                 edits.push(Box::new(DisableSandbox {
                     crate_name: failure.output.crate_name.clone(),
                 }));
@@ -93,20 +106,128 @@ pub(crate) fn fixes_for_problem(problem: &Problem) -> Vec<Box<dyn Edit>> {
                 }
             }
         }
+        Problem::ResourceLimitExceeded(failure) => {
+            if let Some(limit) = failure.output.exceeded_limit {
+                if let Some(value) = limit.value(&failure.output.sandbox_config) {
+                    edits.push(Box::new(RaiseResourceLimit {
+                        crate_name: failure.output.crate_name.clone(),
+                        limit,
+                        value: value.saturating_mul(2),
+                    }));
+                }
+            }
+        }
         Problem::DisallowedBuildInstruction(failure) => {
             edits.append(&mut edits_for_build_instruction(failure));
         }
+        Problem::DisallowedNetworkAccess(access) => {
+            for host in [
+                format!("{}:{}", access.host, access.port),
+                access.host.clone(),
+            ] {
+                edits.push(Box::new(SandboxAllowHost {
+                    crate_name: access.crate_name.clone(),
+                    host,
+                    attempted: format!("{}:{}", access.host, access.port),
+                }));
+            }
+            edits.push(Box::new(SandboxAllowNetwork {
+                crate_name: access.crate_name.clone(),
+            }));
+        }
+        Problem::DisallowedExec(exec) => {
+            let file_name = exec
+                .program
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            for program in file_name
+                .into_iter()
+                .chain(std::iter::once(exec.program.display().to_string()))
+            {
+                edits.push(Box::new(AllowExec {
+                    crate_name: exec.crate_name.clone(),
+                    program,
+                    executed: exec.program.clone(),
+                }));
+            }
+        }
         Problem::DisallowedUnsafe(failure) => edits.push(Box::new(AllowUnsafe {
             crate_name: failure.crate_name.clone(),
+            locations: failure.locations.clone(),
         })),
         Problem::UnusedAllowApi(failure) => edits.push(Box::new(RemoveUnusedAllowApis {
             unused: failure.clone(),
         })),
+        Problem::DisallowedVersion(failure) => {
+            for op in ["^", "="] {
+                edits.push(Box::new(SetVersionReq {
+                    crate_name: failure.crate_name.clone(),
+                    version_req: format!("{op}{}", failure.version),
+                }));
+            }
+        }
         _ => {}
     }
     edits
 }
 
+/// Returns edits that grant the build script access to the paths that the sandbox denied. For
+/// reads, we offer to grant access to either the paths or the directories containing them. For
+/// writes, the paths often don't exist yet, so we offer to grant access to the directories
+/// containing them.
+fn edits_for_denied_accesses(failure: &BuildScriptFailed) -> Vec<Box<dyn Edit>> {
+    let accesses = &failure.output.denied_file_accesses;
+    let paths = |write: bool| -> BTreeSet<&Path> {
+        accesses
+            .iter()
+            .filter(|access| access.write == write)
+            .map(|access| access.path.as_path())
+            .collect()
+    };
+    let allow = |paths: &BTreeSet<&Path>, write: bool| -> Box<dyn Edit> {
+        Box::new(SandboxAllowPaths {
+            crate_name: failure.output.crate_name.clone(),
+            paths: paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            write,
+        })
+    };
+    let mut edits: Vec<Box<dyn Edit>> = Vec::new();
+    let reads = paths(false);
+    if !reads.is_empty() {
+        edits.push(allow(&reads, false));
+        let read_dirs = parents(&reads);
+        if read_dirs != reads {
+            edits.push(allow(&read_dirs, false));
+        }
+    }
+    let writes = paths(true);
+    if !writes.is_empty() {
+        edits.push(allow(&parents(&writes), true));
+    }
+    edits
+}
+
+fn parents<'a>(paths: &BTreeSet<&'a Path>) -> BTreeSet<&'a Path> {
+    paths.iter().filter_map(|path| path.parent()).collect()
+}
+
+/// Returns the fix to apply for `problem` when generating config non-interactively. We only apply
+/// fixes that grant what was actually observed. In particular, if a build script fails, we don't
+/// know whether the sandbox was the cause, so we leave that for the user. Similarly, we don't raise
+/// resource limits, since a build script that never finishes would exceed any limit. Beyond
+/// granting, we import APIs that are available to be imported from the standard library or from a
+/// dependency. Importing restricts rather than grants, but any usages of the imported APIs then get
+/// granted.
+pub(crate) fn automatic_fix_for_problem(problem: &Problem) -> Option<Box<dyn Edit>> {
+    match problem {
+        Problem::BuildScriptFailed(_) | Problem::ResourceLimitExceeded(_) => None,
+        _ => fixes_for_problem(problem).into_iter().next(),
+    }
+}
+
 impl ConfigEditor {
     pub(crate) fn from_file(filename: &Path) -> Result<Self> {
         let toml = std::fs::read_to_string(filename).unwrap_or_default();
@@ -204,6 +325,9 @@ impl ConfigEditor {
             SandboxKind::Inherit => "Inherit",
             SandboxKind::Disabled => "Disabled",
             SandboxKind::Bubblewrap => "Bubblewrap",
+            SandboxKind::Firejail => "Firejail",
+            SandboxKind::Nsjail => "Nsjail",
+            SandboxKind::Landlock => "Landlock",
         };
         self.table(["sandbox"].into_iter())?
             .insert("kind", toml_edit::value(sandbox_kind));
@@ -219,12 +343,23 @@ fn edits_for_build_instruction(
     failure: &crate::problem::DisallowedBuildInstruction,
 ) -> Vec<Box<dyn Edit>> {
     let mut out: Vec<Box<dyn Edit>> = Vec::new();
+    if let Some(suggested) = &failure.suggested_rules {
+        for rule in &suggested.rules {
+            out.push(Box::new(AddInstructionRule {
+                crate_name: failure.crate_name.clone(),
+                config_key: suggested.config_key,
+                rule: rule.clone(),
+                emitted: failure.instruction.clone(),
+            }));
+        }
+    }
     let mut instruction = failure.instruction.as_str();
     let mut suffix = "";
     loop {
         out.push(Box::new(AllowBuildInstruction {
             crate_name: failure.crate_name.clone(),
             instruction: format!("{instruction}{suffix}"),
+            emitted: failure.instruction.clone(),
         }));
         suffix = "*";
         let mut separators = "=-:";
@@ -323,8 +458,10 @@ impl Edit for SelectSandbox {
     fn help(&self) -> Cow<'static, str> {
         "Select what kind of sandbox you'd like to use. At the moment the sandbox is only used \
          for running build scripts (build.rs). Hopefully eventually we'll also run proc-macros \
-         in the sandbox. To use Bubblewrap, it must be installed. On Debian-based systems you can \
-         `sudo apt install bubblewrap`"
+         in the sandbox. To use Bubblewrap, Firejail or Nsjail, it must be installed. On \
+         Debian-based systems you can `sudo apt install bubblewrap` or `sudo apt install \
+         firejail`. Landlock doesn't need anything installed, but requires Linux 5.13 or later \
+         and provides weaker isolation"
             .into()
     }
 
@@ -542,6 +679,27 @@ impl Edit for AllowApiUsage {
         let keys: Vec<_> = self.usage.usages.keys().map(|perm| &perm.name).collect();
         add_to_array(table, "allow_apis", &keys)
     }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        let table = editor.pkg_table(&self.usage.crate_name)?;
+        for (perm, usages) in &self.usage.usages {
+            let Some(usage) = usages.iter().min_by_key(|usage| &usage.source_location) else {
+                continue;
+            };
+            let mut comment = format!(
+                "{} -> {} at {}",
+                usage.from,
+                usage.to_symbol,
+                relative_location(&usage.source_location, root)
+            );
+            if usages.len() > 1 {
+                comment.push_str(&format!(" (and {} more)", usages.len() - 1));
+            }
+            comment_array_value(table, "allow_apis", &perm.name, &comment)?;
+        }
+        Ok(())
+    }
 }
 
 struct RemoveUnusedAllowApis {
@@ -588,6 +746,41 @@ fn get_or_create_array<'table>(
     Ok(array)
 }
 
+/// Adds `comment` on the line before `value` in the array `array_name`.
+fn comment_array_value(
+    table: &mut toml_edit::Table,
+    array_name: &str,
+    value: &str,
+    comment: &str,
+) -> Result<()> {
+    let array = get_or_create_array(table, array_name)?;
+    if let Some(item) = array.iter_mut().find(|item| item.as_str() == Some(value)) {
+        item.decor_mut()
+            .set_prefix(format!("\n    # {comment}\n    "));
+    }
+    Ok(())
+}
+
+/// Adds `comment` on the line before `key`.
+fn comment_key(table: &mut toml_edit::Table, key: &str, comment: &str) {
+    if let Some(decor) = table.key_decor_mut(key) {
+        decor.set_prefix(format!("# {comment}\n"));
+    }
+}
+
+/// Formats `location` for use in a comment, with its path relative to `root` if it's within `root`.
+fn relative_location(location: &SourceLocation, root: &Path) -> String {
+    let location = SourceLocation {
+        filename: location
+            .filename
+            .strip_prefix(root)
+            .unwrap_or(&location.filename)
+            .to_owned(),
+        ..location.clone()
+    };
+    location.to_string()
+}
+
 fn create_string(value: String) -> Value {
     Value::String(Formatted::new(value)).decorated("\n    ", "")
 }
@@ -612,11 +805,23 @@ impl Edit for AllowProcMacro {
         table["allow_proc_macro"] = toml_edit::value(true);
         Ok(())
     }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        comment_key(
+            editor.pkg_table(&self.crate_name)?,
+            "allow_proc_macro",
+            "crate type is proc-macro",
+        );
+        Ok(())
+    }
 }
 
 struct AllowBuildInstruction {
     crate_name: CrateName,
     instruction: String,
+    /// The instruction that the build script emitted.
+    emitted: String,
 }
 
 impl Edit for AllowBuildInstruction {
@@ -638,6 +843,93 @@ impl Edit for AllowBuildInstruction {
         let table = editor.pkg_table(&self.crate_name)?;
         add_to_array(table, "allow_build_instructions", &[&self.instruction])
     }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        comment_array_value(
+            editor.pkg_table(&self.crate_name)?,
+            "allow_build_instructions",
+            &self.instruction,
+            &format!("build script emitted `{}`", self.emitted),
+        )
+    }
+}
+
+struct AddInstructionRule {
+    crate_name: CrateName,
+    config_key: &'static str,
+    rule: String,
+    /// The instruction that the build script emitted.
+    emitted: String,
+}
+
+impl Edit for AddInstructionRule {
+    fn title(&self) -> String {
+        format!(
+            "Add `{}` to `{}` for `{}`",
+            self.rule, self.config_key, self.crate_name
+        )
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        "Allow this crate's build.rs to emit instructions permitted by the specified rule. Rules for \
+         particular kinds of instruction only permit what's relevant for that kind of instruction, \
+         e.g. which library is linked, so are generally preferable to patterns in \
+         `allow_build_instructions`."
+            .into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_table(&self.crate_name)?;
+        add_to_array(table, self.config_key, &[&self.rule])
+    }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        comment_array_value(
+            editor.pkg_table(&self.crate_name)?,
+            self.config_key,
+            &self.rule,
+            &format!("build script emitted `{}`", self.emitted),
+        )
+    }
+}
+
+struct AllowExec {
+    crate_name: CrateName,
+    program: String,
+    /// The path of the program that the build script executed.
+    executed: PathBuf,
+}
+
+impl Edit for AllowExec {
+    fn title(&self) -> String {
+        format!(
+            "Allow build script for `{}` to execute `{}`",
+            self.crate_name, self.program
+        )
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        "Allow this crate's build.rs to execute the specified program. A name without a `/` permits \
+         any program with that name, wherever it's located."
+            .into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_table(&self.crate_name)?;
+        add_to_array(table, "allow_exec", &[&self.program])
+    }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        comment_array_value(
+            editor.pkg_table(&self.crate_name)?,
+            "allow_exec",
+            &self.program,
+            &format!("build script executed `{}`", self.executed.display()),
+        )
+    }
 }
 
 struct DisableSandbox {
@@ -665,6 +957,7 @@ impl Edit for DisableSandbox {
 
 struct AllowUnsafe {
     crate_name: CrateName,
+    locations: Vec<SourceLocation>,
 }
 
 impl Edit for AllowUnsafe {
@@ -686,6 +979,23 @@ impl Edit for AllowUnsafe {
         table["allow_unsafe"] = toml_edit::value(true);
         Ok(())
     }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        let Some(location) = self.locations.iter().min() else {
+            return Ok(());
+        };
+        let mut comment = format!("unsafe at {}", relative_location(location, root));
+        if self.locations.len() > 1 {
+            comment.push_str(&format!(" (and {} more)", self.locations.len() - 1));
+        }
+        comment_key(
+            editor.pkg_table(&self.crate_name)?,
+            "allow_unsafe",
+            &comment,
+        );
+        Ok(())
+    }
 }
 
 struct SandboxAllowNetwork {
@@ -710,6 +1020,150 @@ impl Edit for SandboxAllowNetwork {
     }
 }
 
+struct RaiseResourceLimit {
+    crate_name: CrateName,
+    limit: ResourceLimit,
+    value: u64,
+}
+
+impl Edit for RaiseResourceLimit {
+    fn title(&self) -> String {
+        format!(
+            "Raise {} to {} for `{}`",
+            self.limit.config_key(),
+            self.value,
+            self.crate_name
+        )
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        "Raise this limit for this crate's build script (build.rs). This might be necessary if the \
+         build script legitimately does a lot of work, e.g. compiling a large C library."
+            .into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_sandbox_table(&self.crate_name)?;
+        table[self.limit.config_key()] = toml_edit::value(i64::try_from(self.value)?);
+        Ok(())
+    }
+}
+
+struct SandboxAllowPaths {
+    crate_name: CrateName,
+    paths: Vec<String>,
+    write: bool,
+}
+
+impl SandboxAllowPaths {
+    fn verb(&self) -> &'static str {
+        if self.write {
+            "write"
+        } else {
+            "read"
+        }
+    }
+}
+
+impl Edit for SandboxAllowPaths {
+    fn title(&self) -> String {
+        let verb = if self.write { "writing" } else { "reading" };
+        match self.paths.as_slice() {
+            [path] => format!(
+                "Permit {verb} `{path}` from sandbox for `{}`",
+                self.crate_name
+            ),
+            paths => format!(
+                "Permit {verb} {} paths from sandbox for `{}`",
+                paths.len(),
+                self.crate_name
+            ),
+        }
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        let mut help = format!(
+            "Allow this crate's build script (build.rs) to {} the following paths, which it was \
+             denied access to:",
+            self.verb()
+        );
+        for path in &self.paths {
+            help.push_str("\n  ");
+            help.push_str(path);
+        }
+        help.into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_sandbox_table(&self.crate_name)?;
+        add_to_array(table, &format!("allow_{}", self.verb()), &self.paths)
+    }
+}
+
+struct SandboxAllowHost {
+    crate_name: CrateName,
+    host: String,
+    /// The host and port that the build script tried to connect to.
+    attempted: String,
+}
+
+impl Edit for SandboxAllowHost {
+    fn title(&self) -> String {
+        format!(
+            "Permit `{}` to connect to `{}` from sandbox",
+            self.crate_name, self.host
+        )
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        "Allow this crate's build script (build.rs) to connect to this host. Connections go via a \
+         proxy that only permits connections to allowed hosts."
+            .into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_sandbox_table(&self.crate_name)?;
+        add_to_array(table, "allow_hosts", &[&self.host])
+    }
+
+    fn apply_with_comments(&self, editor: &mut ConfigEditor, _root: &Path) -> Result<()> {
+        self.apply(editor)?;
+        comment_array_value(
+            editor.pkg_sandbox_table(&self.crate_name)?,
+            "allow_hosts",
+            &self.host,
+            &format!("build script connected to `{}`", self.attempted),
+        )
+    }
+}
+
+struct SetVersionReq {
+    crate_name: CrateName,
+    version_req: String,
+}
+
+impl Edit for SetVersionReq {
+    fn title(&self) -> String {
+        format!(
+            "Apply config for `{}` to versions `{}`",
+            self.crate_name, self.version_req
+        )
+    }
+
+    fn help(&self) -> Cow<'static, str> {
+        "Change the versions of this package that its config applies to. You should only select \
+         this once you've reviewed the changes between the versions that the config previously \
+         applied to and the version now in use."
+            .into()
+    }
+
+    fn apply(&self, editor: &mut ConfigEditor) -> Result<()> {
+        let table = editor.pkg_table(&self.crate_name)?;
+        table["version"] = toml_edit::value(self.version_req.as_str());
+        Ok(())
+    }
+}
+
 impl Display for dyn Edit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.title())
@@ -721,6 +1175,7 @@ mod tests {
     use super::ConfigEditor;
     use super::Edit;
     use super::InlineStdApi;
+    use crate::checker::ApiUsage;
     use crate::checker::SourceLocation;
     use crate::config::Config;
     use crate::config::CrateName;
@@ -731,7 +1186,11 @@ mod tests {
     use crate::problem::DisallowedBuildInstruction;
     use crate::problem::Problem;
     use crate::proxy::rpc::BuildScriptOutput;
+    use crate::sandbox::file_trace::FileAccess;
+    use crate::sandbox::limits::ResourceLimit;
+    use crate::symbol::Symbol;
     use indoc::indoc;
+    use std::path::Path;
     use std::path::PathBuf;
     use std::sync::Arc;
 
@@ -792,6 +1251,7 @@ mod tests {
         let problem = Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
             crate_name: CrateName::for_build_script("crab1"),
             instruction: "cargo:rustc-env=SOME_VAR=/home/some-path".to_owned(),
+            suggested_rules: None,
         });
         check(
             "",
@@ -806,6 +1266,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fix_disallowed_link_lib() {
+        let problem = Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
+            crate_name: CrateName::for_build_script("crab1"),
+            instruction: "cargo:rustc-link-lib=static=foo".to_owned(),
+            suggested_rules: Some(crate::problem::SuggestedRules {
+                config_key: "allow_link_libs",
+                rules: vec!["static=foo".to_owned(), "foo".to_owned()],
+            }),
+        });
+        check(
+            "",
+            &[(0, problem.clone())],
+            indoc! {r#"
+                [pkg.crab1.build]
+                allow_link_libs = [
+                    "static=foo",
+                ]
+            "#,
+            },
+        );
+        check(
+            "",
+            &[(2, problem)],
+            indoc! {r#"
+                [pkg.crab1.build]
+                allow_build_instructions = [
+                    "cargo:rustc-link-lib=static=foo",
+                ]
+            "#,
+            },
+        );
+    }
+
     #[test]
     fn fix_missing_api_existing_config() {
         check(
@@ -888,16 +1382,13 @@ mod tests {
         let failure = Problem::BuildScriptFailed(crate::problem::BuildScriptFailed {
             output: BuildScriptOutput {
                 exit_code: 1,
-                stdout: Vec::new(),
-                stderr: Vec::new(),
                 crate_name: CrateName::for_build_script("crab1"),
-                sandbox_config: SandboxConfig {
+                sandbox_config: Box::new(SandboxConfig {
                     kind: crate::config::SandboxKind::Bubblewrap,
-                    allow_read: vec![],
-                    extra_args: vec![],
                     allow_network: None,
-                },
-                build_script: PathBuf::new(),
+                    ..SandboxConfig::default()
+                }),
+                ..BuildScriptOutput::default()
             },
         });
         check(
@@ -920,6 +1411,153 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resource_limit_exceeded() {
+        let failure = Problem::ResourceLimitExceeded(crate::problem::BuildScriptFailed {
+            output: BuildScriptOutput {
+                exit_code: 124,
+                crate_name: CrateName::for_build_script("crab1"),
+                sandbox_config: Box::new(SandboxConfig {
+                    kind: crate::config::SandboxKind::Bubblewrap,
+                    max_wall_secs: Some(300),
+                    ..SandboxConfig::default()
+                }),
+                exceeded_limit: Some(ResourceLimit::WallTime),
+                ..BuildScriptOutput::default()
+            },
+        });
+        check(
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                max_wall_secs = 300
+            "#,
+            },
+            &[(0, failure.clone())],
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                max_wall_secs = 600
+            "#,
+            },
+        );
+        assert!(super::automatic_fix_for_problem(&failure).is_none());
+    }
+
+    #[test]
+    fn build_script_denied_read() {
+        let access = |path: &str, write| FileAccess {
+            path: PathBuf::from(path),
+            write,
+        };
+        let failure = Problem::BuildScriptFailed(crate::problem::BuildScriptFailed {
+            output: BuildScriptOutput {
+                exit_code: 1,
+                crate_name: CrateName::for_build_script("crab1"),
+                sandbox_config: Box::new(SandboxConfig {
+                    kind: crate::config::SandboxKind::Bubblewrap,
+                    ..SandboxConfig::default()
+                }),
+                denied_file_accesses: vec![
+                    access("/opt/foo/a.h", false),
+                    access("/opt/foo/b.h", false),
+                    access("/var/cache/foo/x", true),
+                ],
+                ..BuildScriptOutput::default()
+            },
+        });
+        check(
+            "",
+            &[(0, failure.clone())],
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                allow_read = [
+                    "/opt/foo/a.h",
+                    "/opt/foo/b.h",
+                ]
+            "#,
+            },
+        );
+        check(
+            "",
+            &[(1, failure.clone())],
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                allow_read = [
+                    "/opt/foo",
+                ]
+            "#,
+            },
+        );
+        check(
+            "",
+            &[(2, failure)],
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                allow_write = [
+                    "/var/cache/foo",
+                ]
+            "#,
+            },
+        );
+    }
+
+    #[test]
+    fn disallowed_network_access() {
+        let problem = |host: &str| {
+            Problem::DisallowedNetworkAccess(crate::problem::DisallowedNetworkAccess {
+                crate_name: "crab1.build".into(),
+                host: host.to_owned(),
+                port: 443,
+            })
+        };
+        check(
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                allow_hosts = [
+                    "crates.io",
+                ]
+            "#},
+            &[(0, problem("example.com")), (1, problem("a.example.com"))],
+            indoc! {r#"
+                [pkg.crab1.build.sandbox]
+                allow_hosts = [
+                    "a.example.com",
+                    "crates.io",
+                    "example.com:443",
+                ]
+            "#},
+        );
+    }
+
+    #[test]
+    fn disallowed_exec() {
+        let problem = |program: &str| {
+            Problem::DisallowedExec(crate::problem::DisallowedExec {
+                crate_name: "crab1.build".into(),
+                program: PathBuf::from(program),
+            })
+        };
+        check(
+            indoc! {r#"
+                [pkg.crab1.build]
+                allow_exec = [
+                    "cc",
+                ]
+            "#},
+            &[
+                (0, problem("/usr/bin/pkg-config")),
+                (1, problem("/opt/bin/protoc")),
+            ],
+            indoc! {r#"
+                [pkg.crab1.build]
+                allow_exec = [
+                    "/opt/bin/protoc",
+                    "cc",
+                    "pkg-config",
+                ]
+            "#},
+        );
+    }
+
     #[test]
     fn unused_allow_api() {
         let failure = Problem::UnusedAllowApi(crate::problem::UnusedAllowApi {
@@ -946,6 +1584,120 @@ mod tests {
         );
     }
 
+    #[test]
+    fn allow_api_usage_with_comments() {
+        let location = |line| SourceLocation {
+            filename: "/work/crab1/src/lib.rs".into(),
+            line,
+            column: Some(5),
+        };
+        let usage = |line| ApiUsage {
+            source_location: location(line),
+            from: Symbol::borrowed(b"crab1::read").to_heap(),
+            to: crate::names::split_names("std::fs::read").pop().unwrap(),
+            to_symbol: Symbol::borrowed(b"std::fs::read").to_heap(),
+            inline_frames: Vec::new(),
+            debug_data: None,
+        };
+        let problem = Problem::DisallowedApiUsage(ApiUsages {
+            crate_name: "crab1".into(),
+            usages: [(PermissionName::new("fs"), vec![usage(20), usage(10)])]
+                .into_iter()
+                .collect(),
+        });
+        let mut editor = ConfigEditor::initial();
+        fixes_for_problem(&problem)[0]
+            .apply_with_comments(&mut editor, Path::new("/work"))
+            .unwrap();
+        assert_eq!(
+            editor.to_toml(),
+            indoc! {r#"
+                [pkg.crab1]
+                allow_apis = [
+                    # crab1::read -> std::fs::read at crab1/src/lib.rs [10:5] (and 1 more)
+                    "fs",
+                ]
+            "#}
+        );
+    }
+
+    #[test]
+    fn build_script_grants_with_comments() {
+        let problems = [
+            Problem::IsProcMacro("crab1".into()),
+            Problem::DisallowedBuildInstruction(DisallowedBuildInstruction {
+                crate_name: "crab1.build".into(),
+                instruction: "cargo:rustc-link-lib=static=foo".to_owned(),
+                suggested_rules: Some(crate::problem::SuggestedRules {
+                    config_key: "allow_link_libs",
+                    rules: vec!["static=foo".to_owned()],
+                }),
+            }),
+            Problem::DisallowedExec(crate::problem::DisallowedExec {
+                crate_name: "crab1.build".into(),
+                program: PathBuf::from("/usr/bin/cc"),
+            }),
+            Problem::DisallowedNetworkAccess(crate::problem::DisallowedNetworkAccess {
+                crate_name: "crab1.build".into(),
+                host: "example.com".to_owned(),
+                port: 443,
+            }),
+        ];
+        let mut editor = ConfigEditor::initial();
+        for problem in &problems {
+            super::automatic_fix_for_problem(problem)
+                .unwrap()
+                .apply_with_comments(&mut editor, Path::new("/work"))
+                .unwrap();
+        }
+        assert_eq!(
+            editor.to_toml(),
+            indoc! {r#"
+                [pkg.crab1]
+                # crate type is proc-macro
+                allow_proc_macro = true
+
+                [pkg.crab1.build]
+                allow_link_libs = [
+                    # build script emitted `cargo:rustc-link-lib=static=foo`
+                    "static=foo",
+                ]
+                allow_exec = [
+                    # build script executed `/usr/bin/cc`
+                    "cc",
+                ]
+
+                [pkg.crab1.build.sandbox]
+                allow_hosts = [
+                    # build script connected to `example.com:443`
+                    "example.com:443",
+                ]
+            "#}
+        );
+    }
+
+    #[test]
+    fn fix_disallowed_version() {
+        let problem = Problem::DisallowedVersion(crate::problem::DisallowedVersion {
+            crate_name: "crab1".into(),
+            version_req: "^1.0.150".parse().unwrap(),
+            version: "2.0.1".parse().unwrap(),
+        });
+        check(
+            indoc! {r#"
+                [pkg.crab1]
+                version = "^1.0.150"
+                allow_unsafe = true
+            "#},
+            &[(1, problem)],
+            indoc! {r#"
+                [pkg.crab1]
+                version = "=2.0.1"
+                allow_unsafe = true
+            "#},
+        );
+    }
+
     fn apply_edit_and_parse(toml: &str, edit: &InlineStdApi) -> Arc<Config> {
         let mut editor = ConfigEditor::from_toml_string(toml).unwrap();
         edit.apply(&mut editor).unwrap();
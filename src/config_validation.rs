@@ -1,6 +1,8 @@
 use crate::config::Config;
 use crate::config::CrateName;
 use crate::config::PermissionName;
+use crate::config::SandboxConfig;
+use crate::config::BUILD_FLAGS;
 use crate::config::MAX_VERSION;
 use std::collections::HashSet;
 use std::fmt::Display;
@@ -18,7 +20,16 @@ enum Problem {
     UnknownPermission(PermissionName),
     DuplicateAllowedApi(PermissionName),
     DisallowedSandboxConfig(CrateName),
+    /// Overrides for `cackle test` or `cackle run` in a sandbox config other than the top-level
+    /// one. Contains the location of the sandbox config.
+    DisallowedSandboxProfile(String),
+    DisallowedVersionReq(CrateName),
+    UnsupportedBuildFlag(String),
+    VersionedPackageName(CrateName),
     UnsupportedVersion(i64),
+    /// `only_reachable` can't be combined with `library_mode`, since reachability isn't known for
+    /// code in libraries.
+    ReachableInLibraryMode,
 }
 
 pub(crate) fn validate(config: &Config, config_path: &Path) -> Result<(), InvalidConfig> {
@@ -26,6 +37,28 @@ pub(crate) fn validate(config: &Config, config_path: &Path) -> Result<(), Invali
     if config.common.version < 1 || config.common.version > MAX_VERSION {
         problems.push(Problem::UnsupportedVersion(config.common.version));
     }
+    if config.common.only_reachable && config.common.library_mode {
+        problems.push(Problem::ReachableInLibraryMode);
+    }
+    let build_flags = config
+        .builds
+        .iter()
+        .flat_map(|build| &build.build_flags)
+        .chain(&config.common.build_flags);
+    for flag in build_flags {
+        if !BUILD_FLAGS.contains(&flag.as_str()) {
+            problems.push(Problem::UnsupportedBuildFlag(flag.clone()));
+        }
+    }
+    for (location, sandbox) in [
+        ("sandbox.test", config.sandbox.test.as_deref()),
+        ("sandbox.run", config.sandbox.run.as_deref()),
+        ("rustc.sandbox", config.rustc.sandbox.as_ref()),
+    ] {
+        if sandbox.map_or(false, SandboxConfig::has_profiles) {
+            problems.push(Problem::DisallowedSandboxProfile(location.to_owned()));
+        }
+    }
     let permission_names: HashSet<_> = config.apis.keys().collect();
     for (name, crate_config) in &config.packages {
         let mut used = HashSet::new();
@@ -37,8 +70,20 @@ pub(crate) fn validate(config: &Config, config_path: &Path) -> Result<(), Invali
                 problems.push(Problem::DuplicateAllowedApi(permission_name.clone()))
             }
         }
-        if crate_config.sandbox.is_some() && !name.as_ref().ends_with(".build") {
-            problems.push(Problem::DisallowedSandboxConfig(name.clone()))
+        if let Some(sandbox) = &crate_config.sandbox {
+            if !name.as_ref().ends_with(".build") {
+                problems.push(Problem::DisallowedSandboxConfig(name.clone()))
+            } else if sandbox.has_profiles() {
+                problems.push(Problem::DisallowedSandboxProfile(format!(
+                    "pkg.{name}.sandbox"
+                )))
+            }
+        }
+        if crate_config.version.is_some() && name.as_ref() != name.package_name() {
+            problems.push(Problem::DisallowedVersionReq(name.clone()))
+        }
+        if name.version().is_some() {
+            problems.push(Problem::VersionedPackageName(name.clone()))
         }
     }
     if problems.is_empty() {
@@ -67,6 +112,29 @@ impl Display for InvalidConfig {
                     f,
                     "  Sandbox config for regular package `{crate_name}` isn't permitted"
                 )?,
+                Problem::DisallowedSandboxProfile(location) => write!(
+                    f,
+                    "  Sandbox config `{location}` can't contain `test` or `run`. These are only \
+                     permitted in the top-level `sandbox`"
+                )?,
+                Problem::DisallowedVersionReq(crate_name) => write!(
+                    f,
+                    "  Version for `{crate_name}` isn't permitted. Set it on the package instead"
+                )?,
+                Problem::UnsupportedBuildFlag(flag) => write!(
+                    f,
+                    "  Unsupported build flag `{flag}`. Supported flags are: {}",
+                    BUILD_FLAGS.join(", ")
+                )?,
+                Problem::VersionedPackageName(crate_name) => write!(
+                    f,
+                    "  Package name `{crate_name}` shouldn't include a version. Set `version` on \
+                     the package instead"
+                )?,
+                Problem::ReachableInLibraryMode => write!(
+                    f,
+                    "  `only_reachable` can't be used together with `library_mode`"
+                )?,
             }
         }
         Ok(())
@@ -1,8 +1,14 @@
 //! This module extracts various bits of information from cargo metadata, such as which paths belong
 //! to which crates, which are proc macros etc.
 
+use anyhow::Context;
 use anyhow::Result;
 use cargo_metadata::camino::Utf8PathBuf;
+use cargo_metadata::semver::Version;
+use cargo_metadata::DependencyKind;
+use cargo_metadata::PackageId;
+use serde::Deserialize;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::path::Path;
@@ -16,6 +22,41 @@ pub(crate) struct CrateIndex {
     pub(crate) proc_macros: HashSet<CrateName>,
     name_to_dir: HashMap<CrateName, Utf8PathBuf>,
     dir_to_name: HashMap<PathBuf, CrateName>,
+    package_infos: HashMap<CrateName, PackageInfo>,
+    pub(crate) test_crates: TestCrates,
+}
+
+/// Information needed to determine whether a crate is only built for tests, benches or examples.
+/// Such crates get the name `{package_name}.test`, so that they can be given separate permissions.
+/// Subprocesses don't run `cargo metadata`, so we pass this to them via an environment variable.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub(crate) struct TestCrates {
+    /// Packages that are only depended upon via dev-dependencies.
+    packages: HashSet<CrateName>,
+
+    /// The root source files of test, bench and example targets.
+    sources: HashSet<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PackageInfo {
+    pub(crate) version: Version,
+    /// The checksum from Cargo.lock. Only present for packages from a registry.
+    pub(crate) checksum: Option<String>,
+}
+
+/// The parts of Cargo.lock that we care about.
+#[derive(Deserialize, Default)]
+pub(crate) struct CargoLock {
+    #[serde(default, rename = "package")]
+    pub(crate) packages: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LockedPackage {
+    pub(crate) name: String,
+    pub(crate) version: String,
+    pub(crate) checksum: Option<String>,
 }
 
 impl CrateIndex {
@@ -23,26 +64,65 @@ impl CrateIndex {
         let metadata = cargo_metadata::MetadataCommand::new()
             .manifest_path(dir.join("Cargo.toml"))
             .exec()?;
+        let cargo_lock = CargoLock::load(metadata.workspace_root.join("Cargo.lock").as_std_path())?;
         let mut mapping = Self::default();
+        let non_dev_packages = non_dev_packages(&metadata);
         for package in metadata.packages {
-            let crate_name = CrateName::from(package.name.as_str());
+            let crate_name =
+                CrateName::from(package.name.as_str()).with_version(package.version.clone());
+            let is_dev_only = !non_dev_packages.contains(&package.id);
+            if is_dev_only {
+                mapping.test_crates.packages.insert(crate_name.clone());
+            }
+            let checksum = cargo_lock
+                .packages
+                .iter()
+                .find(|locked| {
+                    locked.name == package.name && locked.version == package.version.to_string()
+                })
+                .and_then(|locked| locked.checksum.clone());
+            mapping.package_infos.insert(
+                crate_name.clone(),
+                PackageInfo {
+                    version: package.version.clone(),
+                    checksum,
+                },
+            );
             for target in package.targets {
                 if target.name.starts_with("build-script-") {
-                    mapping
-                        .crate_names
-                        .insert(CrateName::for_build_script(&package.name));
+                    mapping.crate_names.insert(crate_name.build_script());
                 };
                 if target.kind.iter().any(|kind| kind == "proc-macro") {
                     mapping.proc_macros.insert(crate_name.clone());
                 }
+                if target
+                    .kind
+                    .iter()
+                    .any(|kind| ["test", "bench", "example"].contains(&kind.as_str()))
+                {
+                    let src_path = target.src_path.into_std_path_buf();
+                    mapping
+                        .test_crates
+                        .sources
+                        .insert(src_path.canonicalize().unwrap_or(src_path));
+                    mapping.crate_names.insert(crate_name.tests());
+                }
+            }
+            if is_dev_only {
+                mapping.crate_names.insert(crate_name.tests());
             }
             if let Some(dir) = package.manifest_path.parent() {
                 mapping
                     .name_to_dir
                     .insert(crate_name.clone(), dir.to_path_buf());
+                let dir_crate_name = if is_dev_only {
+                    crate_name.tests()
+                } else {
+                    crate_name.clone()
+                };
                 mapping
                     .dir_to_name
-                    .insert(dir.as_std_path().to_owned(), crate_name.clone());
+                    .insert(dir.as_std_path().to_owned(), dir_crate_name);
             }
             mapping.crate_names.insert(crate_name);
         }
@@ -57,10 +137,25 @@ impl CrateIndex {
         self.name_to_dir.keys()
     }
 
+    pub(crate) fn package_info(&self, crate_name: &CrateName) -> Option<&PackageInfo> {
+        self.package_infos.get(crate_name)
+    }
+
     pub(crate) fn crate_names(&self) -> impl Iterator<Item = &CrateName> {
         self.crate_names.iter()
     }
 
+    /// Returns each version of `crate_name` in our dependency tree. Any version on `crate_name` is
+    /// ignored.
+    pub(crate) fn versions_of<'a>(
+        &'a self,
+        crate_name: &'a CrateName,
+    ) -> impl Iterator<Item = &'a CrateName> + 'a {
+        self.crate_names
+            .iter()
+            .filter(|name| name.as_ref() == crate_name.as_ref())
+    }
+
     /// Returns the name of the crate that contains the specified path, if any. This is used as a
     /// fallback if we can't locate a source file in the deps emitted by rustc. This can happen for
     /// example in the case of crates that compile C code, since the C code won't be in the deps
@@ -80,20 +175,147 @@ impl CrateIndex {
     }
 }
 
+/// Returns the IDs of packages that are reachable from the workspace members without going via a
+/// dev-dependency.
+fn non_dev_packages(metadata: &cargo_metadata::Metadata) -> HashSet<PackageId> {
+    let Some(resolve) = &metadata.resolve else {
+        // Without dependency resolution, we can't tell, so we treat all packages as regular packages.
+        return metadata.packages.iter().map(|p| p.id.clone()).collect();
+    };
+    let nodes: HashMap<&PackageId, &cargo_metadata::Node> =
+        resolve.nodes.iter().map(|node| (&node.id, node)).collect();
+    let mut reached: HashSet<PackageId> = metadata.workspace_members.iter().cloned().collect();
+    let mut to_visit: Vec<&PackageId> = metadata.workspace_members.iter().collect();
+    while let Some(id) = to_visit.pop() {
+        let Some(node) = nodes.get(id) else {
+            continue;
+        };
+        for dep in &node.deps {
+            let is_non_dev = dep
+                .dep_kinds
+                .iter()
+                .any(|info| info.kind != DependencyKind::Development);
+            if is_non_dev && reached.insert(dep.pkg.clone()) {
+                to_visit.push(&dep.pkg);
+            }
+        }
+    }
+    reached
+}
+
+impl TestCrates {
+    const ENV: &str = "CACKLE_TEST_CRATES";
+
+    pub(crate) fn to_env(&self) -> Result<(&'static str, String)> {
+        Ok((Self::ENV, serde_json::to_string(self)?))
+    }
+
+    pub(crate) fn from_env() -> Result<Self> {
+        let Ok(json) = std::env::var(Self::ENV) else {
+            return Ok(Self::default());
+        };
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", Self::ENV))
+    }
+
+    /// Returns whether the crate that rustc is compiling with `rustc_args`, which belongs to
+    /// `package`, is only built for tests, benches or examples. A library's unit tests are built
+    /// from the same root source file as the library itself, so we also check for `--test`.
+    pub(crate) fn is_test_crate(&self, package: &CrateName, rustc_args: &[String]) -> bool {
+        rustc_args.iter().any(|arg| arg == "--test")
+            || self.packages.contains(package)
+            || root_source_path(rustc_args).map_or(false, |path| self.sources.contains(&path))
+    }
+}
+
+/// Returns the root source file of the crate being compiled by rustc with `rustc_args`.
+fn root_source_path(rustc_args: &[String]) -> Option<PathBuf> {
+    rustc_args
+        .iter()
+        .find(|arg| arg.ends_with(".rs"))
+        .and_then(|path| Path::new(path).canonicalize().ok())
+}
+
+impl CargoLock {
+    /// Loads `path`. If it doesn't exist, then an empty lock is returned.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Failed to parse `{}`", path.display()))
+    }
+
+    pub(crate) fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod testing {
     use super::CrateIndex;
+    use super::PackageInfo;
     use crate::config::CrateName;
     use std::sync::Arc;
 
     pub(crate) fn index_with_crate_names(crate_names: &[&str]) -> Arc<CrateIndex> {
         let crate_names = crate_names
             .iter()
-            .map(|name| CrateName(Arc::from(*name)))
+            .map(|name| CrateName::from(*name))
             .collect();
         Arc::new(CrateIndex {
             crate_names,
             ..CrateIndex::default()
         })
     }
+
+    /// Returns an index containing packages with the supplied names and versions.
+    pub(crate) fn index_with_package_versions(packages: &[(&str, &str)]) -> Arc<CrateIndex> {
+        let mut index = CrateIndex::default();
+        for (name, version) in packages {
+            let crate_name = CrateName::from(*name).with_version(version.parse().unwrap());
+            index.crate_names.insert(crate_name.clone());
+            index
+                .name_to_dir
+                .insert(crate_name.clone(), Default::default());
+            index.package_infos.insert(
+                crate_name.clone(),
+                PackageInfo {
+                    version: crate_name.version().unwrap().clone(),
+                    checksum: None,
+                },
+            );
+        }
+        Arc::new(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestCrates;
+    use crate::config::CrateName;
+
+    #[test]
+    fn test_crates() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib_rs = dir.path().join("lib.rs");
+        let test_rs = dir.path().join("test.rs");
+        std::fs::write(&lib_rs, "").unwrap();
+        std::fs::write(&test_rs, "").unwrap();
+        let test_crates = TestCrates {
+            packages: [CrateName::from("dev_dep")].into_iter().collect(),
+            sources: [test_rs.canonicalize().unwrap()].into_iter().collect(),
+        };
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| (*arg).to_owned()).collect() };
+        let lib = lib_rs.display().to_string();
+        let test = test_rs.display().to_string();
+        let crab1 = CrateName::from("crab1");
+
+        assert!(!test_crates.is_test_crate(&crab1, &args(&["--crate-name", "crab1", &lib])));
+        assert!(test_crates.is_test_crate(&crab1, &args(&["--crate-name", "crab1", &test])));
+        assert!(test_crates.is_test_crate(&CrateName::from("dev_dep"), &args(&[&lib])));
+        // A library's unit tests.
+        assert!(test_crates.is_test_crate(&crab1, &args(&["--test", &lib])));
+    }
 }
@@ -1,3 +1,4 @@
+use crate::config::CrateName;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
@@ -10,14 +11,14 @@ use std::path::PathBuf;
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub(crate) struct LinkInfo {
     pub(crate) is_build_script: bool,
-    pub(crate) package_name: String,
+    pub(crate) package_name: CrateName,
     pub(crate) object_paths: Vec<PathBuf>,
     pub(crate) output_file: PathBuf,
 }
 
 impl LinkInfo {
     pub(crate) fn from_env() -> Result<Self> {
-        let package_name = std::env::var("CARGO_PKG_NAME").context("CARGO_PKG_NAME not set")?;
+        let package_name = crate::proxy::subprocess::package_crate_name()?;
         let crate_name = std::env::var("CARGO_CRATE_NAME").context("CARGO_CRATE_NAME not set")?;
         let object_paths = std::env::args()
             .skip(1)
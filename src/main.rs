@@ -3,8 +3,11 @@
 
 #![forbid(unsafe_code)]
 
+mod binary_runner;
 mod build_script_checker;
 mod bytes;
+mod cache;
+mod cackle_lock;
 mod checker;
 mod colour;
 mod config;
@@ -18,30 +21,40 @@ pub(crate) mod link_info;
 mod logging;
 mod names;
 mod outcome;
+mod permission_diff;
 pub(crate) mod problem;
 pub(crate) mod problem_store;
 mod proxy;
+mod report;
 mod sandbox;
 mod summary;
 pub(crate) mod symbol;
 mod symbol_graph;
 mod ui;
 mod unsafe_checker;
+mod why;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use cackle_lock::CackleLock;
 use checker::Checker;
 use clap::Parser;
 use clap::Subcommand;
+use config_editor::ConfigEditor;
 use crate_index::CrateIndex;
 use events::AppEvent;
 use log::info;
 use outcome::ExitCode;
 use outcome::Outcome;
+use permission_diff::DiffArgs;
 use problem::Problem;
 use problem_store::ProblemStoreRef;
 use proxy::rpc::Request;
+use report::OutputFormat;
+use report::Reporter;
+use std::collections::BTreeSet;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::mpsc::Receiver;
@@ -51,6 +64,7 @@ use std::sync::Mutex;
 use std::thread::JoinHandle;
 use summary::SummaryOptions;
 use symbol_graph::ScanOutputs;
+use why::WhyArgs;
 
 #[derive(Parser, Debug, Clone, Default)]
 #[clap(version, about)]
@@ -65,7 +79,7 @@ struct Args {
     #[clap(short, long)]
     cackle_path: Option<PathBuf>,
 
-    /// Print the mapping from paths to crate names. Useful for debugging.
+    /// Print the mapping from paths to crate names to stderr. Useful for debugging.
     #[clap(long)]
     print_path_to_crate_map: bool,
 
@@ -83,10 +97,28 @@ struct Args {
     #[clap(long, alias = "color", default_value = "auto")]
     colour: colour::Colour,
 
+    /// Reuse results from previous runs for crates that haven't changed, rather than cleaning and
+    /// rebuilding everything.
+    #[clap(long)]
+    incremental: bool,
+
+    /// Record the APIs used by each package in cackle.lock.
+    #[clap(long, conflicts_with = "locked")]
+    write_lock: bool,
+
+    /// Fail if the APIs used by any package differ from those recorded in cackle.lock, even if the
+    /// config permits them, or if any package's version or checksum differs.
+    #[clap(long)]
+    locked: bool,
+
     /// Don't print anything on success.
     #[clap(long)]
     quiet: bool,
 
+    /// Format in which to report problems and the final summary.
+    #[clap(long, default_value = "text")]
+    output_format: OutputFormat,
+
     /// Override the target used when compiling. e.g. specify "x86_64-apple-darwin" to compile for
     /// x86 Mac. Note that build scripts and procedural macros will still be compiled for the host
     /// target.
@@ -101,6 +133,12 @@ struct Args {
     #[clap(long)]
     print_timing: bool,
 
+    /// Number of threads to use when scanning object files. Each thread keeps what it has parsed
+    /// from the debug info of the binary being scanned, so fewer threads use less memory. Defaults
+    /// to the number of CPUs.
+    #[clap(long)]
+    threads: Option<usize>,
+
     /// Print additional information that's probably only useful for debugging.
     #[clap(long)]
     debug: bool,
@@ -126,6 +164,17 @@ enum Command {
     Ui(UiArgs),
     /// Print summary of permissions used.
     Summary(SummaryOptions),
+    /// Create a cackle.toml that permits everything that's currently used.
+    Init(InitArgs),
+    /// Print how the permissions granted differ between two versions of the config.
+    Diff(DiffArgs),
+    /// Check, then run tests in a sandbox.
+    Test(TestArgs),
+    /// Check, then run a binary in a sandbox.
+    Run(RunArgs),
+    /// Build, then print how a package reaches an API as chains of references from the package's
+    /// code to the API.
+    Why(WhyArgs),
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -135,11 +184,42 @@ struct UiArgs {
     ui: ui::Kind,
 }
 
+#[derive(Parser, Debug, Clone)]
+struct TestArgs {
+    /// Arguments to pass to each test binary.
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct RunArgs {
+    /// Name of the binary to run. Required if more than one binary is built.
+    #[clap(long)]
+    bin: Option<String>,
+
+    /// Arguments to pass to the binary.
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct InitArgs {
+    /// Comma-separated std APIs to restrict in the generated config.
+    #[clap(long, value_delimiter = ',', default_value = "fs,net,process")]
+    import_std: Vec<String>,
+}
+
 fn main() -> Result<()> {
+    sandbox::run_helper_if_requested()?;
     proxy::subprocess::handle_wrapped_binaries()?;
 
     let mut args = Args::parse();
     args.colour = args.colour.detect();
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
     if let Some(log_file) = &args.log_file {
         logging::init(log_file, args.log_level)?;
     }
@@ -160,6 +240,9 @@ struct Cackle {
     event_sender: Sender<AppEvent>,
     ui_join_handle: JoinHandle<Result<()>>,
     crate_index: Arc<CrateIndex>,
+    reporter: Reporter,
+    /// Executables built by cargo. Only populated for commands that run them.
+    executables: Vec<binary_runner::Executable>,
 }
 
 impl Cackle {
@@ -174,7 +257,10 @@ impl Cackle {
             .canonicalize()
             .with_context(|| format!("Failed to read directory `{}`", root_path.display()))?;
 
-        proxy::clean(&root_path, &args)?;
+        // When running incrementally, we decide what to clean once we've loaded our config.
+        if !args.incremental && args.command.builds() {
+            proxy::clean(&root_path, &args)?;
+        }
 
         let config_path = args
             .cackle_path
@@ -194,12 +280,14 @@ impl Cackle {
         }
         let (event_sender, event_receiver) = std::sync::mpsc::channel();
         let problem_store = crate::problem_store::create(event_sender.clone());
+        let reporter = Reporter::new(args.output_format, root_path.clone());
         let ui_join_handle = ui::start_ui(
             &args,
             &config_path,
             problem_store.clone(),
             event_receiver,
             abort_sender,
+            reporter.clone(),
         )?;
         Ok(Self {
             problem_store,
@@ -211,6 +299,8 @@ impl Cackle {
             event_sender,
             ui_join_handle,
             crate_index,
+            reporter,
+            executables: Vec::new(),
         })
     }
 
@@ -220,8 +310,14 @@ impl Cackle {
         if let Command::Summary(options) = &self.args.command {
             return self.print_summary(options);
         }
+        if let Command::Diff(diff_args) = &self.args.command {
+            return self.print_permission_diff(diff_args);
+        }
         let mut error = None;
-        let exit_code = match self.run(abort_recv) {
+        // We save the cache even if we failed, since anything that cargo built will be reused.
+        let run_result = self.run(abort_recv);
+        let save_result = self.checker.lock().unwrap().save_cache();
+        let exit_code = match run_result.and_then(|exit_code| save_result.map(|_| exit_code)) {
             Err(e) => {
                 error = Some(e);
                 outcome::FAILURE
@@ -233,18 +329,52 @@ impl Cackle {
             println!("UI error: {error}");
             return outcome::FAILURE;
         }
-        // Now that the UI (if any) has shut down, print any errors.
-        if let Some(error) = error {
-            println!("{error:#}");
-        }
+        // Now that the UI (if any) has shut down, report any errors and if we were successful, a
+        // summary of what permissions are used.
+        let report = || -> Result<()> {
+            if let Some(error) = error {
+                self.reporter.error(&error)?;
+            }
 
-        if self.args.print_path_to_crate_map {
-            self.checker.lock().unwrap().print_path_to_crate_map();
+            if self.args.why_args().is_none() {
+                let unreachable_problems = self.checker.lock().unwrap().unreachable_problems();
+                for problem in &unreachable_problems {
+                    self.reporter.problem(problem.severity(), problem)?;
+                }
+            }
+            if self.args.print_path_to_crate_map {
+                self.checker.lock().unwrap().print_path_to_crate_map();
+            }
+            if exit_code == outcome::SUCCESS {
+                if let Command::Why(why_args) = &self.args.command {
+                    print_call_paths(&self.checker.lock().unwrap(), why_args);
+                    return Ok(());
+                }
+                let checker = self.checker.lock().unwrap();
+                let summary = summary::Summary::new(&self.crate_index, &checker.config);
+                self.reporter.summary(summary)?;
+            }
+            self.reporter.finish()
+        };
+        if let Err(error) = report() {
+            println!("Failed to write report: {error:#}");
+            return outcome::FAILURE;
         }
-        if exit_code == outcome::SUCCESS {
-            let checker = self.checker.lock().unwrap();
-            let summary = summary::Summary::new(&self.crate_index, &checker.config);
-            println!("{summary}");
+        if exit_code == outcome::SUCCESS && self.args.command.runs_binaries() {
+            let config = self.checker.lock().unwrap().config.clone();
+            return match run_binaries(
+                &self.args.command,
+                &config,
+                std::mem::take(&mut self.executables),
+                &self.root_path,
+                &self.target_dir,
+            ) {
+                Ok(exit_code) => exit_code,
+                Err(error) => {
+                    println!("{error:#}");
+                    outcome::FAILURE
+                }
+            };
         }
         exit_code
     }
@@ -256,7 +386,38 @@ impl Cackle {
             return outcome::FAILURE;
         }
         let summary = summary::Summary::new(&self.crate_index, &checker.config);
-        summary.print(options);
+        if self.args.output_format == OutputFormat::Text {
+            summary.print(options);
+        } else if let Err(error) = self
+            .reporter
+            .summary(summary)
+            .and_then(|_| self.reporter.finish())
+        {
+            println!("Failed to write report: {error:#}");
+            return outcome::FAILURE;
+        }
+        outcome::SUCCESS
+    }
+
+    fn print_permission_diff(&self, diff_args: &DiffArgs) -> ExitCode {
+        let diff = match diff_args.diff(&self.config_path, &self.root_path, &self.crate_index) {
+            Ok(diff) => diff,
+            Err(error) => {
+                println!("{error:#}");
+                return outcome::FAILURE;
+            }
+        };
+        if self.args.output_format == OutputFormat::Text {
+            print!("{diff}");
+        } else {
+            match serde_json::to_string_pretty(&diff) {
+                Ok(json) => println!("{json}"),
+                Err(error) => {
+                    println!("Failed to write report: {error:#}");
+                    return outcome::FAILURE;
+                }
+            }
+        }
         outcome::SUCCESS
     }
 
@@ -266,6 +427,15 @@ impl Cackle {
             return Ok(outcome::FAILURE);
         }
         self.checker.lock().unwrap().load_config()?;
+        if let Some(why_args) = self.args.why_args() {
+            let api = config::PermissionName::new(&why_args.api);
+            if !self.checker.lock().unwrap().config.apis.contains_key(&api) {
+                bail!("Unknown API `{}`", why_args.api);
+            }
+        }
+        if self.args.incremental {
+            self.checker.lock().unwrap().init_cache(&self.root_path)?;
+        }
 
         let mut initial_outcome = self.new_request_handler(None).handle_request()?;
         let config_path = crate::config::flattened_config_path(&self.target_dir);
@@ -287,26 +457,63 @@ impl Cackle {
 
         let root_path = self.root_path.clone();
         let args = self.args.clone();
-        let build_result = if initial_outcome == Outcome::Continue {
-            proxy::invoke_cargo_build(
-                &root_path,
-                &config_path,
-                &config,
-                &args,
-                abort_recv,
-                |request| self.new_request_handler(Some(request)),
-            )
-        } else {
-            // We've already detected problems before running cargo, don't run cargo.
-            Ok(())
-        };
+        // If we've already detected problems before running cargo, don't run cargo. Otherwise, we
+        // build each configuration in turn. Problems found in each build are reported as they're
+        // found, but unused permissions are only reported once all builds have been checked.
+        if initial_outcome == Outcome::Continue {
+            for mut build in config.build_configs() {
+                build.build_flags.extend(
+                    self.args
+                        .command
+                        .build_flags()
+                        .iter()
+                        .map(|f| f.to_string()),
+                );
+                info!("Building configuration {build:?}");
+                let build_result = proxy::invoke_cargo_build(
+                    &root_path,
+                    &config_path,
+                    &build,
+                    &crate_index,
+                    &args,
+                    &abort_recv,
+                    |request| self.new_request_handler(Some(request)),
+                );
+
+                if self.problem_store.lock().has_aborted {
+                    return Ok(outcome::FAILURE);
+                }
+
+                // We only check if the build failed if there were no ACL check errors.
+                let cargo_stdout = build_result?;
+                if self.args.command.runs_binaries() {
+                    self.executables
+                        .extend(binary_runner::executables_from_cargo_output(&cargo_stdout)?);
+                }
+            }
+        }
 
         if self.problem_store.lock().has_aborted {
             return Ok(outcome::FAILURE);
         }
 
-        // We only check if the build failed if there were no ACL check errors.
-        build_result?;
+        // When explaining, we only needed to build everything so as to find call paths.
+        if self.args.why_args().is_some() {
+            return Ok(outcome::SUCCESS);
+        }
+
+        // Check anything that cargo didn't rebuild against our current config. If we fixed problems
+        // by changing our config, we need to check again.
+        loop {
+            let cached_problems = self.checker.lock().unwrap().check_cached();
+            if cached_problems.is_empty() {
+                break;
+            }
+            if self.problem_store.fix_problems(cached_problems) != Outcome::Continue {
+                return Ok(outcome::FAILURE);
+            }
+            self.checker.lock().unwrap().load_config()?;
+        }
 
         let unused_problems = self.checker.lock().unwrap().check_unused();
         let resolution = self.problem_store.fix_problems(unused_problems);
@@ -314,6 +521,10 @@ impl Cackle {
             return Ok(outcome::FAILURE);
         }
 
+        if self.args.locked || self.args.write_lock {
+            self.check_or_write_lock()?;
+        }
+
         if !self.args.quiet {
             // TODO: Figure out how we want to report success.
 
@@ -329,16 +540,45 @@ impl Cackle {
         Ok(outcome::SUCCESS)
     }
 
+    /// Compares the API usages that we observed against cackle.lock, or if `--write-lock` was
+    /// given, writes them to cackle.lock.
+    fn check_or_write_lock(&self) -> Result<()> {
+        let lock_path = cackle_lock::lock_path(&self.config_path);
+        let observed = CackleLock::new(
+            &self.crate_index,
+            &self.checker.lock().unwrap().observed_usages,
+        );
+        if self.args.write_lock {
+            return observed.write(&lock_path);
+        }
+        let lock = CackleLock::load(&lock_path)?;
+        let mut differences = lock.package_differences(&observed);
+        differences.extend(lock.usage_differences(&observed));
+        if !differences.is_empty() {
+            bail!(
+                "Packages or API usages differ from those in `{}`:\n{}\nRerun with --write-lock to \
+                 update it",
+                lock_path.display(),
+                differences.join("\n")
+            );
+        }
+        Ok(())
+    }
+
     fn new_request_handler(&self, request: Option<Request>) -> RequestHandler {
         RequestHandler {
             check_state: CheckState::default(),
             checker: self.checker.clone(),
             problem_store: self.problem_store.clone(),
             request,
+            explain_only: self.args.why_args().is_some(),
         }
     }
 
     fn maybe_create_config(&mut self) -> Result<Outcome> {
+        if let Command::Init(init_args) = &self.args.command {
+            create_initial_config(&self.config_path, init_args)?;
+        }
         if !self.config_path.exists() {
             return Ok(self
                 .problem_store
@@ -348,14 +588,109 @@ impl Cackle {
     }
 }
 
+impl Command {
+    /// Returns whether this command builds the crate being analysed.
+    fn builds(&self) -> bool {
+        !matches!(self, Command::Summary(..) | Command::Diff(..))
+    }
+
+    /// Returns whether this command runs binaries once they've been built and checked.
+    fn runs_binaries(&self) -> bool {
+        matches!(self, Command::Test(..) | Command::Run(..))
+    }
+
+    /// Returns flags that this command needs to pass to `cargo build` in addition to any from the
+    /// config.
+    fn build_flags(&self) -> &'static [&'static str] {
+        match self {
+            Command::Test(..) => &["--tests", binary_runner::MESSAGE_FORMAT_FLAG],
+            Command::Run(..) => &[binary_runner::MESSAGE_FORMAT_FLAG],
+            _ => &[],
+        }
+    }
+}
+
 impl Args {
     fn ui_kind(&self) -> ui::Kind {
         match &self.command {
             Command::Check => ui::Kind::None,
             Command::Ui(ui_args) => ui_args.ui,
             Command::Summary(..) => ui::Kind::None,
+            Command::Init(..) => ui::Kind::AutoFix,
+            Command::Diff(..) => ui::Kind::None,
+            Command::Test(..) | Command::Run(..) => ui::Kind::None,
+            Command::Why(..) => ui::Kind::None,
+        }
+    }
+
+    fn why_args(&self) -> Option<&WhyArgs> {
+        match &self.command {
+            Command::Why(why_args) => Some(why_args),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the test binaries or main binary from `executables`, as requested by `command`.
+fn run_binaries(
+    command: &Command,
+    config: &config::Config,
+    executables: Vec<binary_runner::Executable>,
+    root_path: &Path,
+    target_dir: &Path,
+) -> Result<ExitCode> {
+    let (executables, sandbox_config, args) = match command {
+        Command::Test(test_args) => (
+            binary_runner::tests(executables),
+            config.sandbox_config_for_test(),
+            &test_args.args,
+        ),
+        Command::Run(run_args) => (
+            vec![binary_runner::main_binary(
+                executables,
+                run_args.bin.as_deref(),
+            )?],
+            config.sandbox_config_for_run(),
+            &run_args.args,
+        ),
+        _ => return Ok(outcome::SUCCESS),
+    };
+    let tmp_dir = target_dir.join(proxy::cargo::PROFILE_NAME).join("tmp");
+    binary_runner::run(&executables, &sandbox_config, root_path, &tmp_dir, args)
+}
+
+/// Prints the paths found by `cackle why`.
+fn print_call_paths(checker: &Checker, why_args: &WhyArgs) {
+    // The same path may have been found in more than one binary.
+    let call_paths: BTreeSet<_> = checker.call_paths.iter().collect();
+    if call_paths.is_empty() {
+        println!(
+            "No path found from `{}` to the `{}` API",
+            why_args.package, why_args.api
+        );
+        return;
+    }
+    for (index, call_path) in call_paths.into_iter().enumerate() {
+        if index > 0 {
+            println!();
         }
+        print!("{call_path}");
+    }
+}
+
+/// Writes a config that restricts the requested std APIs but doesn't yet grant any permissions.
+/// The permissions get added as problems are found.
+fn create_initial_config(config_path: &Path, init_args: &InitArgs) -> Result<()> {
+    if config_path.exists() {
+        bail!("`{}` already exists", config_path.display());
+    }
+    let mut editor = ConfigEditor::initial();
+    editor.set_version(config::MAX_VERSION)?;
+    editor.set_sandbox_kind(sandbox::available_kind())?;
+    for api in &init_args.import_std {
+        editor.toggle_std_import(api)?;
     }
+    editor.write(config_path)
 }
 
 #[derive(Default)]
@@ -368,16 +703,22 @@ struct RequestHandler {
     checker: Arc<Mutex<Checker>>,
     problem_store: ProblemStoreRef,
     request: Option<proxy::rpc::Request>,
+    /// Whether we're only building so as to explain how a package uses an API. Problems are
+    /// ignored, except for those that stop the build from completing.
+    explain_only: bool,
 }
 
 impl RequestHandler {
     fn handle_request(&mut self) -> Result<Outcome> {
         loop {
-            let problems = self
+            let mut problems = self
                 .checker
                 .lock()
                 .unwrap()
                 .problems(&self.request, &mut self.check_state)?;
+            if self.explain_only {
+                problems = problems.only_retryable();
+            }
             let return_on_retry = problems.should_send_retry_to_subprocess();
             if problems.is_empty() {
                 return Ok(Outcome::Continue);
@@ -0,0 +1,408 @@
+//! Compares the permissions granted by two versions of the config. This is intended to help with
+//! reviewing changes such as dependency updates, where what matters is how the permissions granted
+//! to the dependency tree have changed.
+
+use crate::config;
+use crate::config::Config;
+use crate::config::CrateName;
+use crate::config::SandboxConfig;
+use crate::config::SandboxKind;
+use crate::crate_index::CargoLock;
+use crate::crate_index::CrateIndex;
+use crate::summary::Summary;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Parser, Debug, Clone)]
+pub(crate) struct DiffArgs {
+    /// The old config. Either a path to a cackle.toml, or `git:REV` to use cackle.toml and
+    /// Cargo.lock from the git revision REV.
+    old: String,
+
+    /// The new config, in the same form as the old config. Defaults to the current config.
+    new: Option<String>,
+}
+
+/// How the permissions granted to the dependency tree differ between two configs.
+#[derive(Serialize, Default, Debug, PartialEq, Eq)]
+pub(crate) struct PermissionDiff {
+    /// Changes to the default sandbox configuration, which applies to all build scripts.
+    sandbox: PermissionChanges,
+    packages: BTreeMap<CrateName, PackageDiff>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct PackageDiff {
+    status: PackageStatus,
+    #[serde(flatten)]
+    changes: PermissionChanges,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PackageStatus {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq, Eq)]
+struct PermissionChanges {
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+/// The permissions granted by one version of the config.
+struct Permissions {
+    sandbox: BTreeSet<String>,
+    packages: BTreeMap<CrateName, BTreeSet<String>>,
+}
+
+impl DiffArgs {
+    pub(crate) fn diff(
+        &self,
+        config_path: &Path,
+        root_path: &Path,
+        crate_index: &CrateIndex,
+    ) -> Result<PermissionDiff> {
+        let old = Permissions::load(&self.old, config_path, root_path, crate_index)?;
+        let new = match &self.new {
+            Some(new) => Permissions::load(new, config_path, root_path, crate_index)?,
+            None => {
+                let config = read_config(config_path)?;
+                Permissions::new(&config, crate_index.package_names())
+            }
+        };
+        Ok(PermissionDiff::new(&old, &new))
+    }
+}
+
+impl Permissions {
+    /// Loads permissions from `spec`, which is either `git:REV` or a path to a config file.
+    fn load(
+        spec: &str,
+        config_path: &Path,
+        root_path: &Path,
+        crate_index: &CrateIndex,
+    ) -> Result<Self> {
+        let Some(rev) = spec.strip_prefix("git:") else {
+            let config = read_config(Path::new(spec))?;
+            return Ok(Self::new(&config, crate_index.package_names()));
+        };
+        let config_toml = git_show(rev, config_path)?;
+        let config = config::parse(&config_toml)
+            .with_context(|| format!("Failed to parse {} from {rev}", config_path.display()))?;
+        let cargo_lock = CargoLock::parse(&git_show(rev, &root_path.join("Cargo.lock"))?)
+            .with_context(|| format!("Failed to parse Cargo.lock from {rev}"))?;
+        let package_names: BTreeSet<CrateName> = cargo_lock
+            .packages
+            .iter()
+            .map(|pkg| {
+                let name = CrateName::from(pkg.name.as_str());
+                match pkg.version.parse() {
+                    Ok(version) => name.with_version(version),
+                    Err(_) => name,
+                }
+            })
+            .collect();
+        Ok(Self::new(&config, package_names.iter()))
+    }
+
+    fn new<'a>(config: &Config, package_names: impl Iterator<Item = &'a CrateName>) -> Self {
+        // Packages are compared by name, so if there are multiple versions of a package, we combine
+        // the permissions granted to each version.
+        let mut packages: BTreeMap<CrateName, BTreeSet<String>> = BTreeMap::new();
+        let summary = Summary::for_packages(package_names, config);
+        for (pkg_name, pkg_permissions) in summary.permissions_by_package() {
+            let permissions = packages.entry(pkg_name.unversioned()).or_default();
+            permissions.extend(pkg_permissions.iter().cloned());
+            if let Some(build_config) = config.package_config(&pkg_name.build_script()) {
+                for instruction in &build_config.allow_build_instructions {
+                    permissions.insert(format!("instruction:{instruction}[build]"));
+                }
+                for lib in &build_config.allow_link_libs {
+                    permissions.insert(format!("link_lib:{lib}[build]"));
+                }
+                for dir in &build_config.allow_link_search {
+                    permissions.insert(format!("link_search:{dir}[build]"));
+                }
+                for var in &build_config.allow_rustc_env {
+                    permissions.insert(format!("rustc_env:{var}[build]"));
+                }
+                for program in &build_config.allow_exec {
+                    permissions.insert(format!("exec:{program}[build]"));
+                }
+                if let Some(sandbox) = &build_config.sandbox {
+                    for relaxation in sandbox_relaxations(sandbox) {
+                        permissions.insert(format!("{relaxation}[build]"));
+                    }
+                }
+            }
+        }
+        Self {
+            sandbox: sandbox_relaxations(&config.sandbox).collect(),
+            packages,
+        }
+    }
+}
+
+/// Returns the ways in which `sandbox` permits more than the default sandbox would.
+fn sandbox_relaxations(sandbox: &SandboxConfig) -> impl Iterator<Item = String> + '_ {
+    let disabled = (sandbox.kind == SandboxKind::Disabled).then(|| "sandbox:disabled".to_owned());
+    let network = (sandbox.allow_network == Some(true)).then(|| "sandbox:network".to_owned());
+    disabled
+        .into_iter()
+        .chain(network)
+        .chain(
+            sandbox
+                .allow_read
+                .iter()
+                .map(|path| format!("sandbox:read={path}")),
+        )
+        .chain(
+            sandbox
+                .extra_args
+                .iter()
+                .map(|arg| format!("sandbox:arg={arg}")),
+        )
+}
+
+impl PermissionDiff {
+    fn new(old: &Permissions, new: &Permissions) -> Self {
+        let empty = BTreeSet::new();
+        let mut packages = BTreeMap::new();
+        let pkg_names: BTreeSet<&CrateName> =
+            old.packages.keys().chain(new.packages.keys()).collect();
+        for pkg_name in pkg_names {
+            let (status, old_perms, new_perms) =
+                match (old.packages.get(pkg_name), new.packages.get(pkg_name)) {
+                    (Some(old_perms), Some(new_perms)) => {
+                        (PackageStatus::Changed, old_perms, new_perms)
+                    }
+                    (None, Some(new_perms)) => (PackageStatus::Added, &empty, new_perms),
+                    (Some(old_perms), None) => (PackageStatus::Removed, old_perms, &empty),
+                    (None, None) => continue,
+                };
+            let changes = PermissionChanges::new(old_perms, new_perms);
+            if status == PackageStatus::Changed && changes.is_empty() {
+                continue;
+            }
+            packages.insert(pkg_name.clone(), PackageDiff { status, changes });
+        }
+        Self {
+            sandbox: PermissionChanges::new(&old.sandbox, &new.sandbox),
+            packages,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sandbox.is_empty() && self.packages.is_empty()
+    }
+}
+
+impl PermissionChanges {
+    fn new(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Self {
+        Self {
+            added: new.difference(old).cloned().collect(),
+            removed: old.difference(new).cloned().collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn read_config(path: &Path) -> Result<Config> {
+    let toml = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read `{}`", path.display()))?;
+    config::parse(&toml).with_context(|| format!("Failed to parse `{}`", path.display()))
+}
+
+/// Returns the contents of `path` as of git revision `rev`.
+fn git_show(rev: &str, path: &Path) -> Result<String> {
+    let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+        bail!("Invalid path `{}`", path.display());
+    };
+    let output = Command::new("git")
+        .current_dir(dir)
+        .arg("show")
+        .arg(format!("{rev}:./{}", filename.to_string_lossy()))
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to read `{}` from git revision `{rev}`: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+impl Display for PermissionDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No permission changes");
+        }
+        if !self.sandbox.is_empty() {
+            writeln!(f, "[sandbox]")?;
+            write!(f, "{}", self.sandbox)?;
+        }
+        for (pkg_name, pkg_diff) in &self.packages {
+            match pkg_diff.status {
+                PackageStatus::Added => writeln!(f, "{} (new package)", pkg_name.as_ref().green())?,
+                PackageStatus::Removed => {
+                    writeln!(f, "{} (removed package)", pkg_name.as_ref().red())?
+                }
+                PackageStatus::Changed => writeln!(f, "{pkg_name}")?,
+            }
+            write!(f, "{}", pkg_diff.changes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PermissionChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for perm in &self.added {
+            writeln!(f, "  {}", format!("+{perm}").green())?;
+        }
+        for perm in &self.removed {
+            writeln!(f, "  {}", format!("-{perm}").red())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PackageStatus;
+    use super::PermissionDiff;
+    use super::Permissions;
+    use crate::config::CrateName;
+
+    fn permissions(config: &str, package_names: &[&str]) -> Permissions {
+        let config = crate::config::testing::parse(config).unwrap();
+        let package_names: Vec<CrateName> =
+            package_names.iter().map(|name| (*name).into()).collect();
+        Permissions::new(&config, package_names.iter())
+    }
+
+    #[test]
+    fn diff_permissions() {
+        let old = permissions(
+            r#"
+                [api.fs]
+                include = ["std::fs"]
+
+                [api.net]
+                include = ["std::net"]
+
+                [pkg.crab1]
+                allow_apis = ["fs"]
+
+                [pkg.crab2]
+                allow_unsafe = true
+
+                [pkg.crab3]
+                allow_apis = ["net"]
+            "#,
+            &["crab1", "crab2", "crab3"],
+        );
+        let new = permissions(
+            r#"
+                [sandbox]
+                kind = "Disabled"
+
+                [api.fs]
+                include = ["std::fs"]
+
+                [api.net]
+                include = ["std::net"]
+
+                [pkg.crab1]
+                allow_apis = ["net"]
+
+                [pkg.crab1.build]
+                allow_build_instructions = ["cargo:rustc-link-lib=*"]
+                allow_exec = ["cc"]
+
+                [pkg.crab1.build.sandbox]
+                allow_network = true
+
+                [pkg.crab2]
+                allow_unsafe = true
+
+                [pkg.crab4]
+                allow_proc_macro = true
+            "#,
+            &["crab1", "crab2", "crab4"],
+        );
+        let diff = PermissionDiff::new(&old, &new);
+        assert_eq!(diff.sandbox.added, vec!["sandbox:disabled"]);
+        assert!(diff.sandbox.removed.is_empty());
+        let changes: Vec<(&str, PackageStatus, &[String], &[String])> = diff
+            .packages
+            .iter()
+            .map(|(name, pkg_diff)| {
+                (
+                    name.as_ref(),
+                    pkg_diff.status,
+                    pkg_diff.changes.added.as_slice(),
+                    pkg_diff.changes.removed.as_slice(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "crab1",
+                    PackageStatus::Changed,
+                    &[
+                        "exec:cc[build]".to_owned(),
+                        "instruction:cargo:rustc-link-lib=*[build]".to_owned(),
+                        "net".to_owned(),
+                        "sandbox:network[build]".to_owned()
+                    ][..],
+                    &["fs".to_owned()][..]
+                ),
+                (
+                    "crab3",
+                    PackageStatus::Removed,
+                    &[][..],
+                    &["net".to_owned()][..]
+                ),
+                (
+                    "crab4",
+                    PackageStatus::Added,
+                    &["proc_macro".to_owned()][..],
+                    &[][..]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_changes() {
+        let config = r#"
+            [pkg.crab1]
+            allow_unsafe = true
+        "#;
+        let diff = PermissionDiff::new(
+            &permissions(config, &["crab1"]),
+            &permissions(config, &["crab1"]),
+        );
+        assert!(diff.is_empty());
+    }
+}
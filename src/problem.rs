@@ -9,6 +9,11 @@ use crate::config::PermissionName;
 use crate::proxy::rpc::BuildScriptOutput;
 use crate::proxy::rpc::UnsafeUsage;
 use crate::symbol::Symbol;
+use cargo_metadata::semver::Version;
+use cargo_metadata::semver::VersionReq;
+use serde::ser::SerializeStruct;
+use serde::Deserialize;
+use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::BTreeMap;
@@ -23,7 +28,8 @@ pub(crate) struct ProblemList {
 }
 
 #[must_use]
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "kind", content = "details")]
 pub(crate) enum Problem {
     Message(String),
     MissingConfiguration(PathBuf),
@@ -31,13 +37,21 @@ pub(crate) enum Problem {
     DisallowedUnsafe(UnsafeUsage),
     IsProcMacro(CrateName),
     DisallowedApiUsage(ApiUsages),
+    /// A crate uses APIs that it isn't permitted to use, but only in code that can't be reached
+    /// from the entry points of any binary that we checked.
+    UnreachableApiUsage(ApiUsages),
     BuildScriptFailed(BuildScriptFailed),
+    /// A build script was stopped because it exceeded one of its sandbox's resource limits.
+    ResourceLimitExceeded(BuildScriptFailed),
     DisallowedBuildInstruction(DisallowedBuildInstruction),
+    DisallowedNetworkAccess(DisallowedNetworkAccess),
+    DisallowedExec(DisallowedExec),
     UnusedPackageConfig(CrateName),
     UnusedAllowApi(UnusedAllowApi),
     SelectSandbox,
     ImportStdApi(PermissionName),
     AvailableApi(AvailableApi),
+    DisallowedVersion(DisallowedVersion),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -51,31 +65,65 @@ pub(crate) struct BuildScriptFailed {
     pub(crate) output: BuildScriptOutput,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct ApiUsages {
     pub(crate) crate_name: CrateName,
     pub(crate) usages: BTreeMap<PermissionName, Vec<ApiUsage>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub(crate) struct UnusedAllowApi {
     pub(crate) crate_name: CrateName,
     pub(crate) permissions: Vec<PermissionName>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub(crate) struct DisallowedBuildInstruction {
     pub(crate) crate_name: CrateName,
     pub(crate) instruction: String,
+    /// Rules that would permit the instruction, if it's a kind of instruction that can be permitted
+    /// by something more specific than a pattern.
+    pub(crate) suggested_rules: Option<SuggestedRules>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Rules that could be added to a package's config in order to permit something, from most to
+/// least specific.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub(crate) struct SuggestedRules {
+    pub(crate) config_key: &'static str,
+    pub(crate) rules: Vec<String>,
+}
+
+/// A build script attempted to connect to a host that it isn't permitted to connect to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub(crate) struct DisallowedNetworkAccess {
+    pub(crate) crate_name: CrateName,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+/// A build script executed a program that it isn't permitted to execute.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub(crate) struct DisallowedExec {
+    pub(crate) crate_name: CrateName,
+    pub(crate) program: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub(crate) struct AvailableApi {
     pub(crate) crate_name: CrateName,
     pub(crate) api: PermissionName,
     pub(crate) config: PermConfig,
 }
 
+/// The version of a package doesn't match the version required by its config.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub(crate) struct DisallowedVersion {
+    pub(crate) crate_name: CrateName,
+    pub(crate) version_req: VersionReq,
+    pub(crate) version: Version,
+}
+
 impl ProblemList {
     pub(crate) fn push<T: Into<Problem>>(&mut self, problem: T) {
         self.problems.push(problem.into());
@@ -110,10 +158,19 @@ impl ProblemList {
             .all(Problem::should_send_retry_to_subprocess)
     }
 
+    /// Returns just those problems that stop something in a subprocess from completing, such that
+    /// the subprocess needs to retry once they're fixed.
+    #[must_use]
+    pub(crate) fn only_retryable(mut self) -> ProblemList {
+        self.problems
+            .retain(Problem::should_send_retry_to_subprocess);
+        self
+    }
+
     /// Combines all disallowed API usages for a crate.
     #[must_use]
     pub(crate) fn grouped_by_type_and_crate(self) -> ProblemList {
-        self.grouped_by(|usage| usage.crate_name.as_ref().to_owned())
+        self.grouped_by(|usage| usage.crate_name.to_string())
     }
 
     /// Combines all disallowed API usages for a crate and API.
@@ -121,7 +178,7 @@ impl ProblemList {
     pub(crate) fn grouped_by_type_crate_and_api(self) -> ProblemList {
         self.grouped_by(|usage| match usage.usages.first_key_value() {
             Some((key, _)) => format!("{}-{key}", usage.crate_name),
-            None => usage.crate_name.as_ref().to_owned(),
+            None => usage.crate_name.to_string(),
         })
     }
 
@@ -176,8 +233,10 @@ impl<'a> IntoIterator for &'a ProblemList {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum Severity {
+    Info,
     Warning,
     Error,
 }
@@ -189,6 +248,7 @@ impl Problem {
 
     pub(crate) fn severity(&self) -> Severity {
         match self {
+            Problem::UnreachableApiUsage(..) => Severity::Info,
             Problem::UnusedAllowApi(..)
             | Problem::UnusedPackageConfig(..)
             | Problem::AvailableApi(..) => Severity::Warning,
@@ -204,7 +264,10 @@ impl Problem {
     fn should_send_retry_to_subprocess(&self) -> bool {
         matches!(
             self,
-            &Problem::BuildScriptFailed(..) | &Problem::DisallowedUnsafe(..)
+            &Problem::BuildScriptFailed(..)
+                | &Problem::ResourceLimitExceeded(..)
+                | &Problem::DisallowedNetworkAccess(..)
+                | &Problem::DisallowedUnsafe(..)
         )
     }
 
@@ -258,7 +321,7 @@ impl Display for Problem {
                     f,
                     "Package {} has a build script, but config file doesn't have [pkg.{}]",
                     crate_name.package_name(),
-                    crate_name
+                    crate_name.as_ref()
                 )?;
             }
             Problem::IsProcMacro(pkg_name) => write!(
@@ -266,7 +329,25 @@ impl Display for Problem {
                 "Package `{pkg_name}` is a proc macro but doesn't set allow_proc_macro"
             )?,
             Problem::DisallowedApiUsage(info) => info.fmt(f)?,
+            Problem::UnreachableApiUsage(info) => {
+                let apis: Vec<String> = info.usages.keys().map(|api| format!("`{api}`")).collect();
+                write!(
+                    f,
+                    "Crate `{}` uses {} {} only in unreachable code",
+                    info.crate_name,
+                    if apis.len() == 1 { "API" } else { "APIs" },
+                    apis.join(", ")
+                )?;
+                if f.alternate() {
+                    writeln!(f)?;
+                    for (perm_name, usages) in &info.usages {
+                        writeln!(f, "  {perm_name}:")?;
+                        display_usages(f, usages)?;
+                    }
+                }
+            }
             Problem::BuildScriptFailed(info) => info.fmt(f)?,
+            Problem::ResourceLimitExceeded(info) => info.fmt(f)?,
             Problem::DisallowedBuildInstruction(info) => {
                 write!(
                     f,
@@ -275,6 +356,23 @@ impl Display for Problem {
                     info.instruction
                 )?;
             }
+            Problem::DisallowedNetworkAccess(info) => {
+                write!(
+                    f,
+                    "{}'s build script attempted to connect to `{}:{}`, which isn't in `allow_hosts`",
+                    info.crate_name.package_name(),
+                    info.host,
+                    info.port
+                )?;
+            }
+            Problem::DisallowedExec(info) => {
+                write!(
+                    f,
+                    "{}'s build script executed `{}`, which isn't in `allow_exec`",
+                    info.crate_name.package_name(),
+                    info.program.display()
+                )?;
+            }
             Problem::UnusedPackageConfig(pkg_name) => {
                 write!(
                     f,
@@ -294,6 +392,15 @@ impl Display for Problem {
                     info.crate_name, info.api
                 )?;
             }
+            Problem::DisallowedVersion(info) => {
+                write!(
+                    f,
+                    "Package `{}` is version {}, but its config only applies to versions `{}`",
+                    info.crate_name.as_ref(),
+                    info.version,
+                    info.version_req
+                )?;
+            }
         }
         Ok(())
     }
@@ -350,11 +457,22 @@ impl Display for UnusedAllowApi {
 
 impl Display for BuildScriptFailed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Build script for package `{}` failed",
-            self.output.crate_name
-        )?;
+        if let Some(limit) = self.output.exceeded_limit {
+            write!(
+                f,
+                "Build script for package `{}` exceeded its {limit}",
+                self.output.crate_name
+            )?;
+            if let Some(value) = limit.value(&self.output.sandbox_config) {
+                write!(f, " (`{} = {value}`)", limit.config_key())?;
+            }
+        } else {
+            write!(
+                f,
+                "Build script for package `{}` failed",
+                self.output.crate_name
+            )?;
+        }
         if f.alternate() {
             write!(
                 f,
@@ -362,11 +480,18 @@ impl Display for BuildScriptFailed {
                 String::from_utf8_lossy(&self.output.stderr),
                 String::from_utf8_lossy(&self.output.stdout)
             )?;
+            if !self.output.denied_file_accesses.is_empty() {
+                writeln!(f, "Sandbox probably denied access to:")?;
+                for access in &self.output.denied_file_accesses {
+                    let kind = if access.write { "write" } else { "read" };
+                    writeln!(f, "  {kind}: {}", access.path.display())?;
+                }
+            }
             if let Ok(Some(sandbox)) = crate::sandbox::from_config(&self.output.sandbox_config) {
                 writeln!(
                     f,
                     "Sandbox config:\n{}",
-                    sandbox.display_to_run(&self.output.build_script)
+                    sandbox.display_to_run(&self.output.build_script, &[])
                 )?;
             }
         }
@@ -374,6 +499,23 @@ impl Display for BuildScriptFailed {
     }
 }
 
+/// We serialise build script output ourselves rather than deriving so that stdout and stderr come
+/// out as strings rather than arrays of bytes.
+impl Serialize for BuildScriptFailed {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let output = &self.output;
+        let mut s = serializer.serialize_struct("BuildScriptFailed", 7)?;
+        s.serialize_field("crate_name", &output.crate_name)?;
+        s.serialize_field("build_script", &output.build_script)?;
+        s.serialize_field("exit_code", &output.exit_code)?;
+        s.serialize_field("stdout", &String::from_utf8_lossy(&output.stdout))?;
+        s.serialize_field("stderr", &String::from_utf8_lossy(&output.stderr))?;
+        s.serialize_field("denied_file_accesses", &output.denied_file_accesses)?;
+        s.serialize_field("exceeded_limit", &output.exceeded_limit)?;
+        s.end()
+    }
+}
+
 fn display_usages(
     f: &mut std::fmt::Formatter,
     usages: &Vec<ApiUsage>,
@@ -527,6 +669,7 @@ mod tests {
             from: Symbol::borrowed(from.as_bytes()).to_heap(),
             to: crate::names::split_names("foo:bar").pop().unwrap(),
             to_symbol: Symbol::borrowed(to.as_bytes()).to_heap(),
+            inline_frames: Vec::new(),
             debug_data: None,
         }
     }
@@ -20,7 +20,8 @@
 //! * We can run them inside a sandbox if the config says to do so.
 //! * We can capture their output and check for any directives to cargo that haven't been permitted.
 
-use crate::config::Config;
+use crate::config::BuildConfig;
+use crate::crate_index::CrateIndex;
 use crate::outcome::ExitCode;
 use crate::outcome::Outcome;
 use crate::Args;
@@ -44,6 +45,7 @@ use self::rpc::Request;
 pub(crate) mod cargo;
 pub(crate) mod errors;
 pub(crate) mod rpc;
+pub(crate) mod rustc_args;
 pub(crate) mod subprocess;
 
 const SOCKET_ENV: &str = "CACKLE_SOCKET_PATH";
@@ -57,22 +59,34 @@ pub(crate) struct CargoBuildFailure {
 }
 
 pub(crate) fn clean(dir: &Path, args: &Args) -> Result<()> {
-    // For now, we always clean before we build. It might be possible to not do this, but we'd need
-    // to carefully track changes to things we care about, like cackle.toml.
+    // Unless we're running incrementally, we always clean before we build. When running
+    // incrementally, we rely on our cache to tell us about crates that cargo doesn't rebuild.
     run_command(&mut cargo::command("clean", dir, args))?;
     Ok(())
 }
 
-/// Invokes `cargo build` in the specified directory with us acting as proxy versions of rustc and
-/// the linker. If calling this, you must call handle_wrapped_binaries from the start of main.
+/// Cleans just the build outputs for `package_name`, forcing it to be rebuilt.
+pub(crate) fn clean_package(dir: &Path, args: &Args, package_name: &str) -> Result<()> {
+    run_command(
+        cargo::command("clean", dir, args)
+            .arg("-p")
+            .arg(package_name),
+    )?;
+    Ok(())
+}
+
+/// Invokes `cargo build` in the specified directory for the build configuration `build`, with us
+/// acting as proxy versions of rustc and the linker. If calling this, you must call
+/// handle_wrapped_binaries from the start of main. On success, returns what cargo wrote to stdout.
 pub(crate) fn invoke_cargo_build(
     dir: &Path,
     config_path: &Path,
-    config: &Config,
+    build: &BuildConfig,
+    crate_index: &CrateIndex,
     args: &Args,
-    abort_recv: Receiver<()>,
+    abort_recv: &Receiver<()>,
     request_creator: impl Fn(Request) -> RequestHandler,
-) -> Result<()> {
+) -> Result<Vec<u8>> {
     if !std::env::var(SOCKET_ENV).unwrap_or_default().is_empty() {
         panic!("{SOCKET_ENV} is already set. Missing call to handle_wrapped_binarie?");
     }
@@ -86,14 +100,20 @@ pub(crate) fn invoke_cargo_build(
         .with_context(|| format!("Failed to create Unix socket `{}`", ipc_path.display()))?;
 
     let mut command = cargo::command("build", dir, args);
-    if let Some(target) = &args.target {
+    if let Some(target) = build.target.as_ref().or(args.target.as_ref()) {
         command.arg("--target").arg(target);
     }
-    if !config.common.features.is_empty() {
+    if !build.features.is_empty() {
         command.arg("--features");
-        command.arg(config.common.features.join(","));
+        command.arg(build.features.join(","));
+    }
+    if build.no_default_features {
+        command.arg("--no-default-features");
     }
+    command.args(&build.build_flags);
+    let (test_crates_env, test_crates) = crate_index.test_crates.to_env()?;
     command
+        .env(test_crates_env, test_crates)
         .env(SOCKET_ENV, &ipc_path)
         .env(CONFIG_PATH_ENV, config_path)
         .env("RUSTC_WRAPPER", cackle_exe()?);
@@ -131,7 +151,7 @@ pub(crate) fn invoke_cargo_build(
             if status.code() != Some(0) {
                 return Err(CargoBuildFailure { stdout, stderr }.into());
             }
-            break;
+            return Ok(stdout);
         }
         if let Ok(error) = error_recv.try_recv() {
             return Err(error);
@@ -159,8 +179,6 @@ pub(crate) fn invoke_cargo_build(
             std::thread::sleep(Duration::from_millis(10));
         }
     }
-
-    Ok(())
 }
 
 fn start_output_collecting_thread(
@@ -202,7 +220,13 @@ fn cackle_exe() -> Result<PathBuf> {
 
 impl Display for CargoBuildFailure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", String::from_utf8_lossy(&self.stdout))?;
+        // If we asked cargo for JSON messages, then those will be on stdout. They're not useful to
+        // the user, so we skip them.
+        for line in String::from_utf8_lossy(&self.stdout).lines() {
+            if !line.starts_with('{') {
+                writeln!(f, "{line}")?;
+            }
+        }
         write!(f, "{}", String::from_utf8_lossy(&self.stderr))?;
         Ok(())
     }
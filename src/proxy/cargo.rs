@@ -29,7 +29,9 @@ pub(crate) fn command(base_command: &str, dir: &Path, args: &Args) -> Command {
     command
         .arg("--config")
         .arg(format!("profile.{PROFILE_NAME}.opt-level=0"));
-    // We currently always clean before we build, so incremental compilation would just be a waste.
+    // Unless running incrementally, we clean before we build, so incremental compilation would just
+    // be a waste. When running incrementally, crates that we need to recheck get cleaned, so would
+    // gain nothing from it either.
     command
         .arg("--config")
         .arg(format!("profile.{PROFILE_NAME}.incremental=false"));
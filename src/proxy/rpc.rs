@@ -5,6 +5,9 @@ use crate::config::CrateName;
 use crate::config::SandboxConfig;
 use crate::link_info::LinkInfo;
 use crate::outcome::Outcome;
+use crate::sandbox::file_trace::FileAccess;
+use crate::sandbox::limits::ResourceLimit;
+use crate::sandbox::net_proxy::NetworkAttempt;
 use anyhow::Context;
 use anyhow::Result;
 use serde::de::DeserializeOwned;
@@ -65,6 +68,12 @@ impl RpcClient {
         read_from_stream(&mut ipc)
     }
 
+    pub(crate) fn library_built(&self, info: LibraryInfo) -> Result<Outcome> {
+        let mut ipc = self.connect()?;
+        write_to_stream(&Request::LibraryBuilt(info), &mut ipc)?;
+        read_from_stream(&mut ipc)
+    }
+
     /// Creates a new connection to the socket. We only send a single request/response on each
     /// connection because it makes things simpler. In general a single request/response is all we
     /// need anyway.
@@ -86,16 +95,35 @@ pub(crate) enum Request {
     BuildScriptComplete(BuildScriptOutput),
     RustcStarted(CrateName),
     RustcComplete(RustcOutput),
+    LibraryBuilt(LibraryInfo),
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash, Default)]
 pub(crate) struct BuildScriptOutput {
     pub(crate) exit_code: i32,
     pub(crate) stdout: Vec<u8>,
     pub(crate) stderr: Vec<u8>,
     pub(crate) crate_name: CrateName,
-    pub(crate) sandbox_config: SandboxConfig,
+    pub(crate) sandbox_config: Box<SandboxConfig>,
     pub(crate) build_script: PathBuf,
+    /// Connections that the build script attempted to make via the network proxy.
+    #[serde(default)]
+    pub(crate) network_attempts: Vec<NetworkAttempt>,
+    /// If the build script failed, file accesses that were probably denied by the sandbox.
+    #[serde(default)]
+    pub(crate) denied_file_accesses: Vec<FileAccess>,
+    /// The resource limit, if any, that the build script was stopped for exceeding.
+    #[serde(default)]
+    pub(crate) exceeded_limit: Option<ResourceLimit>,
+    /// Programs that the build script executed, if its sandbox was configured to observe this.
+    #[serde(default)]
+    pub(crate) executed_programs: Vec<PathBuf>,
+    /// The build script's `OUT_DIR`.
+    #[serde(default)]
+    pub(crate) out_dir: PathBuf,
+    /// The directory containing the package's `Cargo.toml`.
+    #[serde(default)]
+    pub(crate) manifest_dir: PathBuf,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash)]
@@ -104,6 +132,13 @@ pub(crate) struct RustcOutput {
     pub(crate) source_paths: Vec<PathBuf>,
 }
 
+/// Information about an rlib that rustc has just written.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash)]
+pub(crate) struct LibraryInfo {
+    pub(crate) crate_name: CrateName,
+    pub(crate) rlib_path: PathBuf,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash)]
 pub(crate) struct UnsafeUsage {
     pub(crate) crate_name: CrateName,
@@ -130,25 +165,6 @@ pub(crate) fn read_from_stream<T: DeserializeOwned>(stream: &mut impl Read) -> R
     serde_json::from_str(serialized).with_context(|| format!("Invalid message `{serialized}`"))
 }
 
-impl BuildScriptOutput {
-    pub(crate) fn new(
-        value: &std::process::Output,
-        package_name: CrateName,
-        exit_status: &std::process::ExitStatus,
-        sandbox_config: SandboxConfig,
-        build_script: PathBuf,
-    ) -> Self {
-        Self {
-            exit_code: exit_status.code().unwrap_or(-1),
-            stdout: value.stdout.clone(),
-            stderr: value.stderr.clone(),
-            crate_name: package_name,
-            sandbox_config,
-            build_script,
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,151 @@
+//! Extracts what we need to know from the arguments that cargo passes to rustc.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct RustcArgs {
+    crate_name: Option<String>,
+    crate_types: Vec<String>,
+    out_dir: Option<PathBuf>,
+    /// The value of `-C extra-filename=`, which cargo uses to make output filenames unique.
+    extra_filename: String,
+}
+
+impl RustcArgs {
+    pub(crate) fn parse<S: AsRef<OsStr>>(args: impl IntoIterator<Item = S>) -> Self {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref().to_str().unwrap_or_default();
+            if !matches!(arg, "--crate-name" | "--crate-type" | "--out-dir" | "-C") {
+                continue;
+            }
+            let Some(value) = args.next() else {
+                break;
+            };
+            let value = value.as_ref();
+            let value_str = value.to_str().unwrap_or_default();
+            match arg {
+                "--crate-name" => parsed.crate_name = Some(value_str.to_owned()),
+                "--crate-type" => parsed
+                    .crate_types
+                    .extend(value_str.split(',').map(ToOwned::to_owned)),
+                "--out-dir" => parsed.out_dir = Some(PathBuf::from(value)),
+                _ => {
+                    if let Some(extra) = value_str.strip_prefix("extra-filename=") {
+                        parsed.extra_filename = extra.to_owned();
+                    }
+                }
+            }
+        }
+        parsed
+    }
+
+    /// Returns the directory into which rustc will write its outputs, as specified by `--out-dir`.
+    pub(crate) fn out_dir(&self) -> Result<&Path> {
+        self.out_dir
+            .as_deref()
+            .ok_or_else(|| anyhow!("rustc was invoked without --out-dir"))
+    }
+
+    /// Returns the path of the rlib that rustc will write, if it's been asked to write one.
+    pub(crate) fn rlib_path(&self) -> Option<PathBuf> {
+        if !self
+            .crate_types
+            .iter()
+            .any(|crate_type| crate_type == "lib" || crate_type == "rlib")
+        {
+            return None;
+        }
+        Some(self.out_dir.as_ref()?.join(format!(
+            "lib{}{}.rlib",
+            self.crate_name.as_ref()?,
+            self.extra_filename
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustcArgs;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rlib_path() {
+        let args = RustcArgs::parse([
+            "--crate-name",
+            "foo",
+            "--edition=2021",
+            "src/lib.rs",
+            "--crate-type",
+            "lib",
+            "--emit=dep-info,metadata,link",
+            "-C",
+            "debuginfo=2",
+            "-C",
+            "extra-filename=-0188200cb614ae3d",
+            "--out-dir",
+            "/some/directory/target/debug/deps",
+        ]);
+        assert_eq!(
+            args.out_dir().unwrap(),
+            Path::new("/some/directory/target/debug/deps")
+        );
+        assert_eq!(
+            args.rlib_path(),
+            Some(PathBuf::from(
+                "/some/directory/target/debug/deps/libfoo-0188200cb614ae3d.rlib"
+            ))
+        );
+    }
+
+    #[test]
+    fn not_an_rlib() {
+        let args = RustcArgs::parse([
+            "--crate-name",
+            "foo",
+            "--crate-type",
+            "bin",
+            "--out-dir",
+            "/target/debug/deps",
+        ]);
+        assert_eq!(args.rlib_path(), None);
+        let args = RustcArgs::parse([
+            "--crate-name",
+            "foo",
+            "--crate-type",
+            "proc-macro",
+            "--out-dir",
+            "/target/debug/deps",
+        ]);
+        assert_eq!(args.rlib_path(), None);
+    }
+
+    #[test]
+    fn multiple_crate_types() {
+        let args = RustcArgs::parse([
+            "--crate-name",
+            "foo",
+            "--crate-type",
+            "cdylib,rlib",
+            "--out-dir",
+            "/target/debug/deps",
+        ]);
+        assert_eq!(
+            args.rlib_path(),
+            Some(PathBuf::from("/target/debug/deps/libfoo.rlib"))
+        );
+    }
+
+    #[test]
+    fn missing_out_dir() {
+        let args = RustcArgs::parse(["--crate-name", "foo", "--crate-type", "lib"]);
+        assert!(args.out_dir().is_err());
+        assert_eq!(args.rlib_path(), None);
+    }
+}
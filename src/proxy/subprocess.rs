@@ -4,17 +4,27 @@
 use super::cackle_exe;
 use super::errors::get_disallowed_unsafe_locations;
 use super::rpc::BuildScriptOutput;
+use super::rpc::LibraryInfo;
 use super::rpc::RustcOutput;
 use super::run_command;
+use super::rustc_args::RustcArgs;
 use super::ExitCode;
 use super::CONFIG_PATH_ENV;
 use crate::checker::SourceLocation;
 use crate::config::Config;
 use crate::config::CrateName;
+use crate::config::SandboxConfig;
 use crate::crate_index::CrateIndex;
+use crate::crate_index::TestCrates;
 use crate::link_info::LinkInfo;
 use crate::outcome::Outcome;
 use crate::proxy::rpc::RpcClient;
+use crate::sandbox::exec_trace;
+use crate::sandbox::file_trace;
+use crate::sandbox::file_trace::FileAccess;
+use crate::sandbox::limits;
+use crate::sandbox::net_proxy::NetProxy;
+use crate::sandbox::Sandbox;
 use crate::unsafe_checker;
 use anyhow::anyhow;
 use anyhow::bail;
@@ -27,6 +37,7 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Checks if we're acting as a wrapper for rustc or the linker. If we are, then we do whatever work
 /// we need to do, then invoke the binary that we're wrapping and then exit - i.e. we don't return.
@@ -44,7 +55,11 @@ pub(crate) fn handle_wrapped_binaries() -> Result<()> {
     if let Some(orig_build_script) = proxied_build_rs_bin_path(&binary_name) {
         // We're wrapping a build script.
         exit_status = proxy_build_script(orig_build_script, &rpc_client)?;
-    } else if args.peek().map(|arg| arg == "rustc").unwrap_or(false) {
+    } else if args
+        .peek()
+        .map(|arg| arg == "rustc" || arg.ends_with("/rustc"))
+        .unwrap_or(false)
+    {
         // We're wrapping rustc.
         exit_status = proxy_rustc(&rpc_client)?;
     } else if let Ok(link_info) = LinkInfo::from_env() {
@@ -106,42 +121,165 @@ fn orig_build_rs_bin_path(path: &Path) -> PathBuf {
 fn proxy_build_script(orig_build_script: PathBuf, rpc_client: &RpcClient) -> Result<ExitCode> {
     loop {
         let config = get_config_from_env()?;
-        let package_name = get_env("CARGO_PKG_NAME")?;
-        let sandbox_config = config.sandbox_config_for_build_script(&package_name);
+        let crate_name = package_crate_name()?.build_script();
+        let sandbox_config = config.sandbox_config_for_package(&crate_name);
         let Some(mut sandbox) = crate::sandbox::from_config(&sandbox_config)? else {
             // Config says to run without a sandbox.
             return Ok(Command::new(&orig_build_script).status()?.into());
         };
+        let manifest_dir = PathBuf::from(get_env("CARGO_MANIFEST_DIR")?);
+        let out_dir = PathBuf::from(get_env("OUT_DIR")?);
         // Allow read access to the crate's root source directory.
-        sandbox.ro_bind(Path::new(&get_env("CARGO_MANIFEST_DIR")?));
+        sandbox.ro_bind(&manifest_dir);
         sandbox.ro_bind(target_subdir(&orig_build_script)?);
         // Allow write access to OUT_DIR.
-        sandbox.writable_bind(Path::new(&get_env("OUT_DIR")?));
+        sandbox.writable_bind(&out_dir);
+        let tmp_dir =
+            target_subdir(&orig_build_script)?.join(format!("tmp-{}", std::process::id()));
+        std::fs::create_dir_all(&tmp_dir)
+            .with_context(|| format!("Failed to create directory `{}`", tmp_dir.display()))?;
+        sandbox.private_tmp(&tmp_dir);
         sandbox.pass_cargo_env();
+        let net_proxy = start_net_proxy(sandbox.as_mut(), &sandbox_config, &orig_build_script)?;
 
-        let output = sandbox.run(&orig_build_script)?;
-        let rpc_response = rpc_client.build_script_complete(BuildScriptOutput::new(
-            &output,
-            CrateName::for_build_script(&package_name),
-            &output.status,
-            sandbox_config,
-            orig_build_script.clone(),
-        ))?;
+        let start = Instant::now();
+        let (output, executed_programs) = if sandbox_config.observe_exec.unwrap_or(false) {
+            run_observing_exec(sandbox.as_mut(), &orig_build_script)?
+        } else {
+            (sandbox.run(&orig_build_script, &[])?, Vec::new())
+        };
+        let exceeded_limit = limits::exceeded_limit(&sandbox_config, &output, start.elapsed());
+        // There's no point tracing a build script that was stopped for exceeding a limit, since
+        // running it again would most likely just exceed the limit again.
+        let denied_file_accesses = if output.status.success() || exceeded_limit.is_some() {
+            Vec::new()
+        } else {
+            trace_denied_file_accesses(sandbox.as_mut(), &orig_build_script)?
+        };
+        let _ = std::fs::remove_dir_all(&tmp_dir);
+        let network_attempts = net_proxy
+            .map(|net_proxy| net_proxy.attempts())
+            .unwrap_or_default();
+        let network_blocked = network_attempts.iter().any(|attempt| !attempt.allowed);
+        let rpc_response = rpc_client.build_script_complete(BuildScriptOutput {
+            exit_code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            crate_name,
+            sandbox_config: Box::new(sandbox_config),
+            build_script: orig_build_script.clone(),
+            network_attempts,
+            denied_file_accesses,
+            exceeded_limit,
+            executed_programs,
+            out_dir,
+            manifest_dir,
+        })?;
         match rpc_response {
             Outcome::Continue => {
-                if output.status.code() == Some(0) {
+                if output.status.code() == Some(0) && !network_blocked {
                     std::io::stderr().lock().write_all(&output.stderr)?;
                     std::io::stdout().lock().write_all(&output.stdout)?;
                     return Ok(output.status.into());
                 }
-                // If the build script failed and we were asked to proceed, then fall through and
-                // retry the build script with a hopefully changed config.
+                // If the build script failed or was blocked from accessing the network and we were
+                // asked to proceed, then fall through and retry the build script with a hopefully
+                // changed config.
             }
             Outcome::GiveUp => std::process::exit(-1),
         }
     }
 }
 
+/// Starts a network proxy for a build script that doesn't have unrestricted network access. Returns
+/// `None` if the build script has unrestricted network access, or if the sandbox doesn't support
+/// routing network access via the proxy and no hosts are allowed.
+fn start_net_proxy(
+    sandbox: &mut dyn Sandbox,
+    sandbox_config: &SandboxConfig,
+    build_script: &Path,
+) -> Result<Option<NetProxy>> {
+    if sandbox_config.allow_network.unwrap_or(false) {
+        return Ok(None);
+    }
+    let socket_path =
+        target_subdir(build_script)?.join(format!("net-{}.socket", std::process::id()));
+    let net_proxy = NetProxy::start(socket_path, &sandbox_config.allow_hosts)?;
+    if !sandbox.proxy_network(net_proxy.socket_path())? {
+        if !sandbox_config.allow_hosts.is_empty() {
+            bail!(
+                "Sandbox kind {:?} doesn't support `allow_hosts` on this system",
+                sandbox_config.kind
+            );
+        }
+        return Ok(None);
+    }
+    Ok(Some(net_proxy))
+}
+
+/// Runs the build script under `strace`, recording which programs it executes.
+fn run_observing_exec(
+    sandbox: &mut dyn Sandbox,
+    build_script: &Path,
+) -> Result<(std::process::Output, Vec<PathBuf>)> {
+    let strace = file_trace::strace_path()
+        .ok_or_else(|| anyhow!("`observe_exec` requires `strace` to be installed"))?;
+    let (output, trace) = run_traced(sandbox, &strace, exec_trace::EXEC_FILTER, build_script)?;
+    let executed = exec_trace::executed_programs(
+        &trace,
+        build_script,
+        &std::env::current_dir().context("Failed to get current directory")?,
+    );
+    Ok((output, executed))
+}
+
+/// Runs the build script again, this time under `strace`, in order to find out what file accesses
+/// the sandbox denied. Returns nothing if `strace` isn't installed.
+fn trace_denied_file_accesses(
+    sandbox: &mut dyn Sandbox,
+    build_script: &Path,
+) -> Result<Vec<FileAccess>> {
+    let Some(strace) = file_trace::strace_path() else {
+        return Ok(Vec::new());
+    };
+    let (_, trace) = run_traced(sandbox, &strace, "file", build_script)?;
+    Ok(file_trace::denied_accesses(
+        &trace,
+        &std::env::current_dir().context("Failed to get current directory")?,
+    ))
+}
+
+/// Runs the build script in the sandbox under `strace`, tracing the system calls selected by
+/// `filter`. Returns the output of the build script and the trace.
+fn run_traced(
+    sandbox: &mut dyn Sandbox,
+    strace: &Path,
+    filter: &str,
+    build_script: &Path,
+) -> Result<(std::process::Output, String)> {
+    let trace_dir = target_subdir(build_script)?.join(format!("trace-{}", std::process::id()));
+    std::fs::create_dir_all(&trace_dir)
+        .with_context(|| format!("Failed to create directory `{}`", trace_dir.display()))?;
+    sandbox.writable_bind(&trace_dir);
+    let trace_file = trace_dir.join("trace");
+    let output = sandbox.run(
+        strace,
+        &file_trace::strace_args(&trace_file, filter, build_script),
+    )?;
+    let trace = std::fs::read_to_string(&trace_file).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&trace_dir);
+    Ok((output, trace))
+}
+
+/// Returns the name of the package that cargo is building, qualified with its version.
+pub(crate) fn package_crate_name() -> Result<CrateName> {
+    let version = get_env("CARGO_PKG_VERSION")?;
+    let version = version
+        .parse()
+        .with_context(|| format!("Invalid CARGO_PKG_VERSION `{version}`"))?;
+    Ok(CrateName::from(get_env("CARGO_PKG_NAME")?.as_str()).with_version(version))
+}
+
 /// Given some path in our target/profile directory, returns the profile directory. This is always
 /// "cackle", since we specify what profile to use.
 fn target_subdir(build_script_path: &Path) -> Result<&Path> {
@@ -163,15 +301,15 @@ fn target_subdir(build_script_path: &Path) -> Result<&Path> {
 }
 
 fn proxy_rustc(rpc_client: &RpcClient) -> Result<ExitCode> {
-    let Ok(pkg_name) = std::env::var("CARGO_PKG_NAME") else {
+    if std::env::var("CARGO_PKG_NAME").is_err() {
         // If CARGO_PKG_NAME isn't set, then cargo is probably just invoking rustc to query
         // version information etc, just run it.
         return Ok(Command::new("rustc")
             .args(std::env::args().skip(2))
             .status()?
             .into());
-    };
-    let mut runner = RustcRunner::new(&pkg_name)?;
+    }
+    let mut runner = RustcRunner::new(package_crate_name()?)?;
     rpc_client.rustc_started(&runner.crate_name)?;
     loop {
         match runner.run(rpc_client)? {
@@ -201,14 +339,18 @@ enum RustcRunStatus {
 }
 
 impl RustcRunner {
-    fn new(pkg_name: &str) -> Result<Self> {
+    fn new(pkg_name: CrateName) -> Result<Self> {
         let is_build_script = std::env::var("CARGO_CRATE_NAME")
             .map(|v| v.starts_with("build_script_"))
             .unwrap_or(false);
         let crate_name = if is_build_script {
-            CrateName::for_build_script(pkg_name)
+            pkg_name.build_script()
+        } else if TestCrates::from_env()?
+            .is_test_crate(&pkg_name, &std::env::args().skip(2).collect::<Vec<_>>())
+        {
+            pkg_name.tests()
         } else {
-            CrateName::from(pkg_name)
+            pkg_name
         };
         let linking_requested = Self::linking_requested();
         Ok(Self {
@@ -227,7 +369,7 @@ impl RustcRunner {
         let config = get_config_from_env()?;
         let unsafe_permitted = config.unsafe_permitted_for_crate(&self.crate_name);
         let mut command = self.get_command(allow_linking, unsafe_permitted)?;
-        let output = command.output()?;
+        let output = run_rustc(&mut command, &config)?;
         let mut unsafe_locations = Vec::new();
 
         if output.status.code() == Some(0) {
@@ -266,6 +408,18 @@ impl RustcRunner {
             }
         }
 
+        if output.status.code() == Some(0) && allow_linking && config.common.library_mode {
+            if let Some(rlib_path) = RustcArgs::parse(command.get_args()).rlib_path() {
+                let response = rpc_client.library_built(LibraryInfo {
+                    crate_name: self.crate_name.clone(),
+                    rlib_path,
+                })?;
+                if response == Outcome::GiveUp {
+                    return Ok(RustcRunStatus::GiveUp);
+                }
+            }
+        }
+
         Ok(RustcRunStatus::Done(output))
     }
 
@@ -331,6 +485,48 @@ impl RustcRunner {
     }
 }
 
+/// Runs `command`, which invokes rustc, in a sandbox if the config says to. Proc macros run inside
+/// rustc, so this is what sandboxes them.
+fn run_rustc(command: &mut Command, config: &Config) -> Result<std::process::Output> {
+    let sandbox = match config.sandbox_config_for_rustc() {
+        Some(sandbox_config) => crate::sandbox::from_config(&sandbox_config)?,
+        None => None,
+    };
+    let Some(mut sandbox) = sandbox else {
+        return Ok(command.output()?);
+    };
+    let out_dir = RustcArgs::parse(command.get_args()).out_dir()?.to_owned();
+    sandbox.ro_bind(Path::new(&get_env("CARGO_MANIFEST_DIR")?));
+    // Allow read access to the target directory, which contains our dependencies, as well as the
+    // socket that the linker uses to communicate with our parent process. When cross compiling,
+    // proc macros are in a different subdirectory to `out_dir`, so we need the whole directory.
+    let socket_path = PathBuf::from(get_env(super::SOCKET_ENV)?);
+    if let Some(target_dir) = socket_path.parent() {
+        sandbox.ro_bind(target_dir);
+    }
+    sandbox.writable_bind(&out_dir);
+    // Rustc and the linker create temporary files, but the sandbox might not provide a writable
+    // temporary directory, so we have them use the output directory.
+    sandbox.set_env(OsStr::new("TMPDIR"), out_dir.as_os_str());
+    // We're the linker, so we need to be able to run ourselves.
+    sandbox.ro_bind(&cackle_exe()?);
+    for (var, value) in std::env::vars_os() {
+        if var.to_str().map_or(false, |var| var.starts_with("CACKLE_")) {
+            sandbox.set_env(&var, &value);
+        }
+    }
+    for (var, value) in command.get_envs() {
+        if let Some(value) = value {
+            sandbox.set_env(var, value);
+        }
+    }
+    sandbox.pass_cargo_env();
+    sandbox.pass_env("RUSTUP_HOME");
+    sandbox.pass_env("RUSTUP_TOOLCHAIN");
+    let args: Vec<OsString> = command.get_args().map(ToOwned::to_owned).collect();
+    sandbox.run(Path::new(command.get_program()), &args)
+}
+
 /// Searches for the unsafe keyword in the specified paths.
 fn find_unsafe_in_sources(paths: &[PathBuf]) -> Result<Vec<SourceLocation>> {
     let mut locations = Vec::new();
@@ -0,0 +1,205 @@
+//! Reporting of problems, errors and the final summary. By default we print human-readable text,
+//! but we can also produce machine-readable output for consumption by other tools.
+
+use crate::problem::Problem;
+use crate::problem::Severity;
+use crate::summary::Summary;
+use anyhow::Result;
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+mod sarif;
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// A single JSON document, written once checking has finished.
+    Json,
+    /// One JSON object per line, written as soon as each item is available.
+    JsonLines,
+    /// A SARIF 2.1.0 log, written once checking has finished.
+    Sarif,
+}
+
+/// Outputs problems, errors and summaries in the selected format. Cloning gives another handle to
+/// the same report.
+#[derive(Clone, Default)]
+pub(crate) struct Reporter {
+    format: OutputFormat,
+    report: Arc<Mutex<Report>>,
+    /// The directory containing the crate being analysed. Source paths are reported relative to
+    /// this directory where the output format supports it.
+    root_path: PathBuf,
+}
+
+/// Everything that we report. Only populated when the output format is `Json` or `Sarif`, since in
+/// other formats we write things out as they arrive.
+#[derive(Serialize, Default)]
+struct Report {
+    problems: Vec<ReportedProblem>,
+    errors: Vec<String>,
+    summary: Option<Summary>,
+}
+
+#[derive(Serialize)]
+struct ReportedProblem {
+    severity: Severity,
+    problem: Problem,
+}
+
+/// A single line of output when the output format is `JsonLines`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    Problem {
+        severity: Severity,
+        problem: &'a Problem,
+    },
+    Error {
+        message: &'a str,
+    },
+    Summary(&'a Summary),
+}
+
+impl Reporter {
+    pub(crate) fn new(format: OutputFormat, root_path: PathBuf) -> Self {
+        Self {
+            format,
+            report: Default::default(),
+            root_path,
+        }
+    }
+
+    pub(crate) fn problem(&self, severity: Severity, problem: &Problem) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => match severity {
+                Severity::Info => println!("{} {problem:#}", "INFO:".blue()),
+                Severity::Warning => println!("{} {problem:#}", "WARNING:".yellow()),
+                Severity::Error => println!("{} {problem:#}", "ERROR:".red()),
+            },
+            OutputFormat::Json | OutputFormat::Sarif => {
+                self.report.lock().unwrap().problems.push(ReportedProblem {
+                    severity,
+                    problem: problem.clone(),
+                })
+            }
+            OutputFormat::JsonLines => print_line(&Record::Problem { severity, problem })?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn error(&self, error: &anyhow::Error) -> Result<()> {
+        let message = format!("{error:#}");
+        match self.format {
+            OutputFormat::Text => println!("{message}"),
+            OutputFormat::Json | OutputFormat::Sarif => {
+                self.report.lock().unwrap().errors.push(message)
+            }
+            OutputFormat::JsonLines => print_line(&Record::Error { message: &message })?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn summary(&self, summary: Summary) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => println!("{summary}"),
+            OutputFormat::Json => self.report.lock().unwrap().summary = Some(summary),
+            OutputFormat::JsonLines => print_line(&Record::Summary(&summary))?,
+            // SARIF has no natural place for a summary of permissions.
+            OutputFormat::Sarif => {}
+        }
+        Ok(())
+    }
+
+    /// Writes out anything that we've been holding onto. Should be called once, after everything
+    /// else has been reported.
+    pub(crate) fn finish(&self) -> Result<()> {
+        let report = self.report.lock().unwrap();
+        match self.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&*report)?),
+            OutputFormat::Sarif => {
+                let sarif = sarif::to_sarif(&report.problems, &report.errors, &self.root_path);
+                println!("{}", serde_json::to_string_pretty(&sarif)?);
+            }
+            OutputFormat::Text | OutputFormat::JsonLines => {}
+        }
+        Ok(())
+    }
+}
+
+fn print_line(record: &Record) -> Result<()> {
+    println!("{}", serde_json::to_string(record)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Record;
+    use crate::checker::ApiUsage;
+    use crate::checker::SourceLocation;
+    use crate::problem::ApiUsages;
+    use crate::problem::Problem;
+    use crate::problem::Severity;
+    use crate::symbol::Symbol;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn api_usage_record() {
+        let mut usages = BTreeMap::new();
+        usages.insert(
+            "fs".into(),
+            vec![ApiUsage {
+                source_location: SourceLocation {
+                    filename: "src/lib.rs".into(),
+                    line: 10,
+                    column: Some(5),
+                },
+                from: Symbol::borrowed(b"_ZN5crab13foo17h0123456789abcdefE").to_heap(),
+                to: crate::names::split_names("std::fs::read").pop().unwrap(),
+                to_symbol: Symbol::borrowed(b"std::fs::read").to_heap(),
+                inline_frames: Vec::new(),
+                debug_data: None,
+            }],
+        );
+        let problem = Problem::DisallowedApiUsage(ApiUsages {
+            crate_name: "crab1".into(),
+            usages,
+        });
+        let record = Record::Problem {
+            severity: Severity::Error,
+            problem: &problem,
+        };
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            json!({
+                "type": "problem",
+                "severity": "error",
+                "problem": {
+                    "kind": "DisallowedApiUsage",
+                    "details": {
+                        "crate_name": "crab1",
+                        "usages": {
+                            "fs": [{
+                                "source_location": {
+                                    "filename": "src/lib.rs",
+                                    "line": 10,
+                                    "column": 5,
+                                },
+                                "from": "crab1::foo",
+                                "to": "std::fs::read",
+                                "to_symbol": "std::fs::read",
+                            }]
+                        }
+                    }
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Conversion of problems to SARIF 2.1.0 (Static Analysis Results Interchange Format), so that they
+//! can be viewed with tools that display code-scanning alerts.
+
+use super::ReportedProblem;
+use crate::checker::SourceLocation;
+use crate::config::CrateName;
+use crate::problem::Problem;
+use crate::problem::Severity;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::os::unix::prelude::OsStrExt;
+use std::path::Path;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Builds a SARIF log containing a single run. Source paths within `root` are reported relative to
+/// the `SRCROOT` base URI.
+pub(super) fn to_sarif(problems: &[ReportedProblem], errors: &[String], root: &Path) -> Value {
+    let mut rule_ids = BTreeSet::new();
+    let mut results = Vec::new();
+    for ReportedProblem { severity, problem } in problems {
+        let rule_id = rule_id(problem);
+        rule_ids.insert(rule_id);
+        let level = match severity {
+            Severity::Info => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let mut add_result = |message: String, locations: Vec<Value>| {
+            results.push(json!({
+                "ruleId": rule_id,
+                "level": level,
+                "message": {"text": message},
+                "locations": locations,
+            }));
+        };
+        match problem {
+            Problem::DisallowedApiUsage(usages) | Problem::UnreachableApiUsage(usages) => {
+                let qualifier = if matches!(problem, Problem::UnreachableApiUsage(..)) {
+                    " in unreachable code"
+                } else {
+                    ""
+                };
+                for (permission, usages_for_perm) in &usages.usages {
+                    for usage in usages_for_perm {
+                        add_result(
+                            format!(
+                                "Crate `{}` uses API `{permission}` via `{}` from `{}`{qualifier}",
+                                usages.crate_name, usage.to, usage.from
+                            ),
+                            vec![physical_location(&usage.source_location, root)],
+                        );
+                    }
+                }
+            }
+            Problem::DisallowedUnsafe(usage) => add_result(
+                problem.to_string(),
+                usage
+                    .locations
+                    .iter()
+                    .map(|location| physical_location(location, root))
+                    .collect(),
+            ),
+            Problem::IsProcMacro(crate_name) | Problem::UsesBuildScript(crate_name) => {
+                add_result(problem.to_string(), vec![logical_location(crate_name)])
+            }
+            Problem::DisallowedVersion(info) => add_result(
+                problem.to_string(),
+                vec![logical_location(&info.crate_name)],
+            ),
+            Problem::DisallowedBuildInstruction(info) => add_result(
+                problem.to_string(),
+                vec![logical_location(&info.crate_name)],
+            ),
+            Problem::DisallowedNetworkAccess(info) => add_result(
+                problem.to_string(),
+                vec![logical_location(&info.crate_name)],
+            ),
+            Problem::DisallowedExec(info) => add_result(
+                problem.to_string(),
+                vec![logical_location(&info.crate_name)],
+            ),
+            _ => add_result(problem.to_string(), vec![]),
+        }
+    }
+    let rules: Vec<Value> = rule_ids
+        .into_iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "shortDescription": {"text": rule_description(id)},
+            })
+        })
+        .collect();
+    let notifications: Vec<Value> = errors
+        .iter()
+        .map(|error| json!({"level": "error", "message": {"text": error}}))
+        .collect();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "cackle",
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules,
+                }
+            },
+            "originalUriBaseIds": {
+                "SRCROOT": {"uri": directory_uri(root)},
+            },
+            "invocations": [{
+                "executionSuccessful": errors.is_empty(),
+                "toolExecutionNotifications": notifications,
+            }],
+            "results": results,
+        }]
+    })
+}
+
+fn physical_location(location: &SourceLocation, root: &Path) -> Value {
+    let artifact = match location.filename.strip_prefix(root) {
+        Ok(relative) => json!({"uri": encode_path(relative), "uriBaseId": "SRCROOT"}),
+        Err(_) => json!({"uri": file_uri(&location.filename)}),
+    };
+    let mut region = json!({"startLine": location.line});
+    if let Some(column) = location.column {
+        region["startColumn"] = column.into();
+    }
+    json!({
+        "physicalLocation": {
+            "artifactLocation": artifact,
+            "region": region,
+        }
+    })
+}
+
+/// Problems that relate to a whole package rather than to particular source lines are reported
+/// against a logical location naming the package.
+fn logical_location(crate_name: &CrateName) -> Value {
+    json!({
+        "logicalLocations": [{
+            "name": crate_name.package_name(),
+            "fullyQualifiedName": crate_name.to_string(),
+            "kind": "module",
+        }]
+    })
+}
+
+fn file_uri(path: &Path) -> String {
+    format!("file://{}", encode_path(path))
+}
+
+/// Percent-encodes `path` for use in a URI as per RFC 3986. Other than `/`, which separates path
+/// segments, only unreserved characters are left as is.
+fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+fn directory_uri(path: &Path) -> String {
+    let mut uri = file_uri(path);
+    if !uri.ends_with('/') {
+        uri.push('/');
+    }
+    uri
+}
+
+fn rule_id(problem: &Problem) -> &'static str {
+    match problem {
+        Problem::Message(..) => "Message",
+        Problem::MissingConfiguration(..) => "MissingConfiguration",
+        Problem::UsesBuildScript(..) => "UsesBuildScript",
+        Problem::DisallowedUnsafe(..) => "DisallowedUnsafe",
+        Problem::IsProcMacro(..) => "IsProcMacro",
+        Problem::DisallowedApiUsage(..) => "DisallowedApiUsage",
+        Problem::UnreachableApiUsage(..) => "UnreachableApiUsage",
+        Problem::BuildScriptFailed(..) => "BuildScriptFailed",
+        Problem::ResourceLimitExceeded(..) => "ResourceLimitExceeded",
+        Problem::DisallowedBuildInstruction(..) => "DisallowedBuildInstruction",
+        Problem::DisallowedNetworkAccess(..) => "DisallowedNetworkAccess",
+        Problem::DisallowedExec(..) => "DisallowedExec",
+        Problem::UnusedPackageConfig(..) => "UnusedPackageConfig",
+        Problem::UnusedAllowApi(..) => "UnusedAllowApi",
+        Problem::SelectSandbox => "SelectSandbox",
+        Problem::ImportStdApi(..) => "ImportStdApi",
+        Problem::AvailableApi(..) => "AvailableApi",
+        Problem::DisallowedVersion(..) => "DisallowedVersion",
+    }
+}
+
+fn rule_description(rule_id: &str) -> &'static str {
+    match rule_id {
+        "DisallowedApiUsage" => "Package uses an API that it isn't permitted to use",
+        "UnreachableApiUsage" => {
+            "Package uses an API that it isn't permitted to use, but only in unreachable code"
+        }
+        "DisallowedUnsafe" => "Package uses unsafe code but doesn't have `allow_unsafe = true`",
+        "DisallowedBuildInstruction" => "Build script emitted an instruction that isn't permitted",
+        "IsProcMacro" => "Package is a proc macro but doesn't have `allow_proc_macro = true`",
+        "UsesBuildScript" => "Package has a build script that isn't mentioned in the config",
+        "BuildScriptFailed" => "Build script failed",
+        "ResourceLimitExceeded" => "Build script exceeded a resource limit of its sandbox",
+        "DisallowedNetworkAccess" => {
+            "Build script attempted to connect to a host that isn't permitted"
+        }
+        "DisallowedExec" => "Build script executed a program that isn't permitted",
+        "UnusedPackageConfig" => "Config supplied for a package that isn't in the dependency tree",
+        "UnusedAllowApi" => "Config permits APIs that aren't used",
+        "MissingConfiguration" => "Config file not found",
+        "AvailableApi" => "Package exports an API definition that isn't imported",
+        "DisallowedVersion" => "Package version doesn't match the version required by its config",
+        _ => "Other problem",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_sarif;
+    use crate::checker::SourceLocation;
+    use crate::problem::Problem;
+    use crate::problem::Severity;
+    use crate::proxy::rpc::UnsafeUsage;
+    use crate::report::ReportedProblem;
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    fn unsafe_and_proc_macro() {
+        let problems = vec![
+            ReportedProblem {
+                severity: Severity::Error,
+                problem: Problem::DisallowedUnsafe(UnsafeUsage {
+                    crate_name: "crab1".into(),
+                    locations: vec![SourceLocation {
+                        filename: "/work/crab1/src/lib.rs".into(),
+                        line: 7,
+                        column: Some(3),
+                    }],
+                }),
+            },
+            ReportedProblem {
+                severity: Severity::Error,
+                problem: Problem::IsProcMacro("pmacro1".into()),
+            },
+        ];
+        let sarif = to_sarif(&problems, &[], Path::new("/work"));
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(
+            run["tool"]["driver"]["rules"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|rule| rule["id"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["DisallowedUnsafe", "IsProcMacro"]
+        );
+        assert_eq!(
+            run["results"][0]["locations"][0],
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": {"uri": "crab1/src/lib.rs", "uriBaseId": "SRCROOT"},
+                    "region": {"startLine": 7, "startColumn": 3},
+                }
+            })
+        );
+        assert_eq!(run["results"][1]["ruleId"], "IsProcMacro");
+        assert_eq!(
+            run["results"][1]["locations"][0]["logicalLocations"][0]["name"],
+            "pmacro1"
+        );
+    }
+
+    #[test]
+    fn encode_paths() {
+        let problems = vec![ReportedProblem {
+            severity: Severity::Error,
+            problem: Problem::DisallowedUnsafe(UnsafeUsage {
+                crate_name: "crab1".into(),
+                locations: vec![
+                    SourceLocation {
+                        filename: "/my work/crab#1/src/lib.rs".into(),
+                        line: 1,
+                        column: None,
+                    },
+                    SourceLocation {
+                        filename: "/other/100%/ünï.rs".into(),
+                        line: 2,
+                        column: None,
+                    },
+                ],
+            }),
+        }];
+        let sarif = to_sarif(&problems, &[], Path::new("/my work"));
+        let run = &sarif["runs"][0];
+        assert_eq!(
+            run["originalUriBaseIds"]["SRCROOT"]["uri"],
+            "file:///my%20work/"
+        );
+        let locations = &run["results"][0]["locations"];
+        assert_eq!(
+            locations[0]["physicalLocation"]["artifactLocation"]["uri"],
+            "crab%231/src/lib.rs"
+        );
+        assert_eq!(
+            locations[1]["physicalLocation"]["artifactLocation"]["uri"],
+            "file:///other/100%25/%C3%BCn%C3%AF.rs"
+        );
+    }
+}
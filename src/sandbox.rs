@@ -4,20 +4,54 @@ use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::fmt::Display;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::process::ExitStatus;
 
 mod bubblewrap;
+pub(crate) mod exec_trace;
+pub(crate) mod file_trace;
+mod firejail;
+mod landlock;
+pub(crate) mod limits;
+pub(crate) mod net_proxy;
+mod nsjail;
 
 pub(crate) trait Sandbox {
-    /// Runs `binary` inside the sandbox.
-    fn run(&self, binary: &Path) -> Result<std::process::Output>;
+    /// Returns a command that runs `binary` with `args` inside the sandbox, with `dir` as its
+    /// current directory.
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command>;
+
+    /// Runs `binary` with `args` inside the sandbox in our current directory and returns its
+    /// output.
+    fn run(&self, binary: &Path, args: &[OsString]) -> Result<std::process::Output> {
+        let dir = std::env::current_dir().context("Failed to get current directory")?;
+        let mut command = self.command(binary, args, &dir)?;
+        command
+            .output()
+            .with_context(|| failed_to_run_message(&command))
+    }
+
+    /// Runs `binary` with `args` inside the sandbox in `dir`. Unlike `run`, the binary shares our
+    /// stdin, stdout and stderr, so it can be interactive and its output is shown as it's produced.
+    fn run_interactive(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<ExitStatus> {
+        let mut command = self.command(binary, args, dir)?;
+        command
+            .status()
+            .with_context(|| failed_to_run_message(&command))
+    }
 
     /// Bind a tmpfs at `dir`.
     fn tmpfs(&mut self, dir: &Path);
 
+    /// Provide `dir` as a writable temporary directory in place of the `/tmp` that we hid with
+    /// `tmpfs`. Only needed by sandboxes that hide directories rather than mounting an empty tmpfs
+    /// over them.
+    fn private_tmp(&mut self, _dir: &Path) {}
+
     /// Set the environment variable `var` to `value`.
     fn set_env(&mut self, var: &OsStr, value: &OsStr);
 
@@ -30,6 +64,10 @@ pub(crate) trait Sandbox {
     /// Allow unrestricted network access.
     fn allow_network(&mut self);
 
+    /// Route network access via the filtering proxy listening on `socket_path`. Returns false if
+    /// this kind of sandbox can't do this, in which case network access remains denied.
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool>;
+
     /// Append a sandbox-specific argument.
     fn raw_arg(&mut self, arg: &OsStr);
 
@@ -52,17 +90,30 @@ pub(crate) trait Sandbox {
 
     /// Returns an object that when displayed serves to tell the user what the sandbox would do.
     /// e.g. the command that would be run with all flags.
-    fn display_to_run(&self, binary: &Path) -> Box<dyn Display>;
+    fn display_to_run(&self, binary: &Path, args: &[OsString]) -> Box<dyn Display> {
+        let dir = std::env::current_dir().unwrap_or_default();
+        match self.command(binary, args, &dir) {
+            Ok(command) => Box::new(CommandDisplay { command }),
+            Err(error) => Box::new(format!("{error:#}")),
+        }
+    }
+}
+
+fn failed_to_run_message(command: &Command) -> String {
+    format!(
+        "Failed to run sandbox command: {}",
+        Path::new(command.get_program()).display()
+    )
 }
 
 pub(crate) fn from_config(config: &SandboxConfig) -> Result<Option<Box<dyn Sandbox>>> {
-    let mut sandbox = match &config.kind {
+    let mut sandbox: Box<dyn Sandbox> = match &config.kind {
         SandboxKind::Disabled | SandboxKind::Inherit => return Ok(None),
         SandboxKind::Bubblewrap => Box::<bubblewrap::Bubblewrap>::default(),
+        SandboxKind::Firejail => Box::<firejail::Firejail>::default(),
+        SandboxKind::Nsjail => Box::<nsjail::Nsjail>::default(),
+        SandboxKind::Landlock => Box::<landlock::Landlock>::default(),
     };
-    for dir in &config.allow_read {
-        sandbox.ro_bind(Path::new(dir));
-    }
     let home = PathBuf::from(std::env::var("HOME").context("Couldn't get HOME env var")?);
     // We allow access to the root of the filesystem, but only selected parts of the user's home
     // directory. The home directory is where sensitive stuff is most likely to live. e.g. access
@@ -79,9 +130,23 @@ pub(crate) fn from_config(config: &SandboxConfig) -> Result<Option<Box<dyn Sandb
     sandbox.ro_bind(&cargo_home.join("git"));
     sandbox.ro_bind(&cargo_home.join("registry"));
     sandbox.ro_bind(&home.join(".rustup"));
+    // These are bound after the directories that we hide, so that they can be used to expose
+    // things inside those directories.
+    for dir in &config.allow_read {
+        sandbox.ro_bind(Path::new(dir));
+    }
+    for dir in &config.allow_write {
+        sandbox.writable_bind(Path::new(dir));
+    }
     sandbox.set_env(OsStr::new("USER"), OsStr::new("user"));
     sandbox.pass_env("PATH");
     sandbox.pass_env("HOME");
+    for var in &config.pass_env {
+        sandbox.pass_env(var);
+    }
+    for (var, value) in &config.set_env {
+        sandbox.set_env(OsStr::new(var), OsStr::new(value));
+    }
     for arg in &config.extra_args {
         sandbox.raw_arg(OsStr::new(arg));
     }
@@ -93,24 +158,60 @@ pub(crate) fn from_config(config: &SandboxConfig) -> Result<Option<Box<dyn Sandb
         // permitted prevents DNS lookups on some systems.
         sandbox.tmpfs(Path::new("/run"));
     }
-    Ok(Some(sandbox))
+    Ok(Some(limits::Limited::wrap(sandbox, config)))
 }
 
+/// Returns the kind of sandbox that we'd recommend using on this system. Sandboxes that run the
+/// build script in separate namespaces are preferred over Landlock, since they provide stronger
+/// isolation.
 pub(crate) fn available_kind() -> SandboxKind {
     if bubblewrap::has_bwrap() {
         SandboxKind::Bubblewrap
+    } else if firejail::has_firejail() {
+        SandboxKind::Firejail
+    } else if nsjail::has_nsjail() {
+        SandboxKind::Nsjail
+    } else if landlock::is_supported() {
+        SandboxKind::Landlock
     } else {
         SandboxKind::Disabled
     }
 }
 
 pub(crate) fn verify_kind(kind: SandboxKind) -> Result<()> {
-    if kind == SandboxKind::Bubblewrap && Command::new("bwrap").arg("--version").output().is_err() {
-        bail!("Failed to run `bwrap`, perhaps it needs to be installed? On systems with apt you can `sudo apt install bubblewrap`");
+    match kind {
+        SandboxKind::Inherit | SandboxKind::Disabled => {}
+        SandboxKind::Bubblewrap => {
+            if Command::new("bwrap").arg("--version").output().is_err() {
+                bail!("Failed to run `bwrap`, perhaps it needs to be installed? On systems with apt you can `sudo apt install bubblewrap`");
+            }
+        }
+        SandboxKind::Firejail => {
+            if !firejail::has_firejail() {
+                bail!("Failed to run `firejail`, perhaps it needs to be installed? On systems with apt you can `sudo apt install firejail`");
+            }
+        }
+        SandboxKind::Nsjail => {
+            if !nsjail::has_nsjail() {
+                bail!("Failed to run `nsjail`, perhaps it needs to be installed? See https://github.com/google/nsjail");
+            }
+        }
+        SandboxKind::Landlock => {
+            if !landlock::is_supported() {
+                bail!("Landlock isn't available. It requires Linux 5.13 or later with Landlock enabled");
+            }
+        }
     }
     Ok(())
 }
 
+/// If we were invoked by a sandbox in order to run a binary, e.g. by the Landlock sandbox or as a
+/// network relay, then runs that binary and doesn't return. Otherwise just returns.
+pub(crate) fn run_helper_if_requested() -> Result<()> {
+    landlock::exec_if_requested()?;
+    net_proxy::relay_if_requested()
+}
+
 fn is_cargo_env(var: &str) -> bool {
     // We set this when we call cargo. We don't want it passed through to build scripts.
     if var == "RUSTC_WRAPPER" {
@@ -128,3 +229,25 @@ fn is_cargo_env(var: &str) -> bool {
     ];
     PREFIXES.iter().any(|prefix| var.starts_with(prefix)) || ONE_OFFS.contains(&var)
 }
+
+/// Displays a command together with its arguments.
+struct CommandDisplay {
+    command: Command,
+}
+
+impl Display for CommandDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.command.get_program().to_string_lossy())?;
+        for arg in self.command.get_args() {
+            let arg = arg.to_string_lossy();
+            if arg.contains(' ') {
+                // Use debug print, since that gives us quotes.
+                write!(f, " {:?}", arg)?;
+            } else {
+                // Print without quotes, since it probably isn't necessary.
+                write!(f, " {arg}")?
+            }
+        }
+        Ok(())
+    }
+}
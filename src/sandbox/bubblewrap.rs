@@ -1,23 +1,26 @@
 use super::Sandbox;
-use anyhow::Context;
 use anyhow::Result;
 use std::ffi::OsStr;
 use std::ffi::OsString;
-use std::fmt::Display;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 
 #[derive(Default)]
 pub(super) struct Bubblewrap {
     args: Vec<OsString>,
+    /// If set, the network relay, which runs the binary.
+    relay: Option<PathBuf>,
 }
 
 impl Bubblewrap {
     fn arg<S: AsRef<OsStr>>(&mut self, arg: S) {
         self.args.push(arg.as_ref().to_owned());
     }
+}
 
-    fn command(&self, binary: &Path) -> Command {
+impl Sandbox for Bubblewrap {
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command> {
         let mut command = Command::new("bwrap");
         command
             .args(["--unshare-all"])
@@ -29,12 +32,14 @@ impl Bubblewrap {
             .args(&self.args)
             .args(["--dev", "/dev"])
             .args(["--proc", "/proc"])
-            .arg(binary);
-        command
+            .arg("--chdir")
+            .arg(dir)
+            .args(&self.relay)
+            .arg(binary)
+            .args(args);
+        Ok(command)
     }
-}
 
-impl Sandbox for Bubblewrap {
     fn raw_arg(&mut self, arg: &OsStr) {
         self.args.push(arg.to_owned());
     }
@@ -66,20 +71,9 @@ impl Sandbox for Bubblewrap {
         self.arg("--share-net");
     }
 
-    fn run(&self, binary: &Path) -> Result<std::process::Output> {
-        let mut command = self.command(binary);
-        command.output().with_context(|| {
-            format!(
-                "Failed to run sandbox command: {}",
-                Path::new(command.get_program()).display()
-            )
-        })
-    }
-
-    fn display_to_run(&self, binary: &Path) -> Box<dyn Display> {
-        Box::new(CommandDisplay {
-            command: self.command(binary),
-        })
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool> {
+        self.relay = Some(super::net_proxy::bind_relay(self, socket_path)?);
+        Ok(true)
     }
 }
 
@@ -90,24 +84,3 @@ pub(crate) fn has_bwrap() -> bool {
         .map(|output| output.status.success())
         .unwrap_or(false)
 }
-
-struct CommandDisplay {
-    command: Command,
-}
-
-impl Display for CommandDisplay {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.command.get_program().to_string_lossy())?;
-        for arg in self.command.get_args() {
-            let arg = arg.to_string_lossy();
-            if arg.contains(' ') {
-                // Use debug print, since that gives us quotes.
-                write!(f, " {:?}", arg)?;
-            } else {
-                // Print without quotes, since it probably isn't necessary.
-                write!(f, " {arg}")?
-            }
-        }
-        Ok(())
-    }
-}
@@ -0,0 +1,222 @@
+//! Support for observing which programs a build script executes. We run the build script under
+//! `strace` inside the sandbox, tracing only calls that execute programs, then check what was
+//! executed against what the package is permitted to execute.
+
+use super::file_trace;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The `strace` filter for system calls that execute programs. We also need the system calls that
+/// create processes or change directory, so that we can resolve relative paths.
+pub(crate) const EXEC_FILTER: &str = "process,chdir,fchdir";
+
+/// System calls that execute a program.
+const EXEC_SYSCALLS: &[&str] = &["execve", "execveat"];
+
+/// System calls that create a process and return its PID.
+const FORK_SYSCALLS: &[&str] = &["clone", "clone3", "fork", "vfork"];
+
+/// A successful system call that's relevant to working out what programs were executed.
+#[derive(Debug, PartialEq, Eq)]
+enum Call {
+    Exec(String),
+    Chdir(String),
+    /// A change of directory to a file descriptor, which we can't resolve.
+    Fchdir,
+    Fork(u32),
+}
+
+/// Parses the output of `strace` and returns the programs that were successfully executed, other
+/// than `binary`, which is what `strace` was asked to run. The first process in the trace is
+/// assumed to have started in `cwd`. Relative paths are resolved against the directory that the
+/// executing process was in. If that's unknown, e.g. because it changed directory via a file
+/// descriptor, then the path is returned unresolved.
+pub(crate) fn executed_programs(trace: &str, binary: &Path, cwd: &Path) -> Vec<PathBuf> {
+    let calls: Vec<(u32, Call)> = file_trace::system_calls_by_pid(trace)
+        .iter()
+        .filter_map(|(pid, call)| Some((pid.parse().ok()?, parse_call(call)?)))
+        .collect();
+    // A child process can appear in the trace before the call that created it has returned in the
+    // parent, so we need to know each process's parent up front.
+    let parents: HashMap<u32, u32> = calls
+        .iter()
+        .filter_map(|(pid, call)| match call {
+            Call::Fork(child) => Some((*child, *pid)),
+            _ => None,
+        })
+        .collect();
+    let mut cwds: HashMap<u32, Option<PathBuf>> = HashMap::new();
+    if let Some((pid, _)) = calls.first() {
+        cwds.insert(*pid, Some(cwd.to_owned()));
+    }
+    let mut programs = BTreeSet::new();
+    for (pid, call) in &calls {
+        let pid_cwd = process_cwd(*pid, &mut cwds, &parents);
+        match call {
+            Call::Exec(program) => {
+                programs.insert(resolve(pid_cwd.as_deref(), program));
+            }
+            Call::Chdir(dir) => {
+                let dir = Path::new(dir);
+                let new_cwd = if dir.is_absolute() {
+                    Some(dir.to_owned())
+                } else {
+                    pid_cwd.map(|pid_cwd| pid_cwd.join(dir))
+                };
+                cwds.insert(*pid, new_cwd);
+            }
+            Call::Fchdir => {
+                cwds.insert(*pid, None);
+            }
+            Call::Fork(child) => {
+                cwds.entry(*child).or_insert(pid_cwd);
+            }
+        }
+    }
+    programs.remove(binary);
+    programs.into_iter().collect()
+}
+
+/// Returns the current directory of `pid`, if known. A process that we haven't seen before inherits
+/// the current directory of its parent. Since the parent is still in the system call that created
+/// the child, it can't have changed directory since.
+fn process_cwd(
+    pid: u32,
+    cwds: &mut HashMap<u32, Option<PathBuf>>,
+    parents: &HashMap<u32, u32>,
+) -> Option<PathBuf> {
+    if let Some(cwd) = cwds.get(&pid) {
+        return cwd.clone();
+    }
+    let cwd = parents
+        .get(&pid)
+        .and_then(|parent| process_cwd(*parent, cwds, parents));
+    cwds.insert(pid, cwd.clone());
+    cwd
+}
+
+fn resolve(cwd: Option<&Path>, path: &str) -> PathBuf {
+    match cwd {
+        Some(cwd) => cwd.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Parses a single system call from a trace, returning it if it's one we're interested in and it
+/// succeeded.
+fn parse_call(call: &str) -> Option<Call> {
+    let (syscall, rest) = call.split_once('(')?;
+    let (args, result) = rest.rsplit_once(") = ")?;
+    let result = result.split(' ').next()?;
+    if FORK_SYSCALLS.contains(&syscall) {
+        return Some(Call::Fork(result.parse().ok()?));
+    }
+    if result != "0" {
+        return None;
+    }
+    if syscall == "fchdir" {
+        return Some(Call::Fchdir);
+    }
+    let path = || {
+        let (_, path) = args.split_once('"')?;
+        file_trace::parse_quoted(path)
+    };
+    if EXEC_SYSCALLS.contains(&syscall) {
+        Some(Call::Exec(path()?))
+    } else if syscall == "chdir" {
+        Some(Call::Chdir(path()?))
+    } else {
+        None
+    }
+}
+
+/// Returns whether `program` is permitted by `allow_exec`. Entries that contain a `/` must match
+/// the full path of the program. Other entries match the program's file name.
+pub(crate) fn is_allowed(program: &Path, allow_exec: &[String]) -> bool {
+    allow_exec.iter().any(|entry| {
+        if entry.contains('/') {
+            program == Path::new(entry)
+        } else {
+            program.file_name() == Some(entry.as_ref())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_trace() {
+        let trace = indoc! {r#"
+            100 execve("/build/script", ["/build/script"], 0x7ffd /* 20 vars */) = 0
+            100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD, child_tidptr=0x7f) = 101
+            100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD, child_tidptr=0x7f) = 102
+            100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD, child_tidptr=0x7f) = 103
+            100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD, child_tidptr=0x7f) = 104
+            101 execve("/usr/local/bin/cc", ["cc", "-c", "foo.c"], 0x7ffd /* 20 vars */) = -1 ENOENT (No such file or directory)
+            101 execve("/usr/bin/cc", ["cc", "-c", "foo.c"], 0x7ffd /* 20 vars */ <unfinished ...>
+            100 +++ exited with 0 +++
+            101 <... execve resumed>) = 0
+            102 execve("./configure", ["./configure"], 0x7ffd /* 20 vars */) = 0
+            103 execveat(AT_FDCWD, "/usr/bin/pkg-config", ["pkg-config"], 0x7ffd /* 20 vars */, 0) = 0
+            104 execve("/usr/bin/cc", ["cc", "-c", "bar.c"], 0x7ffd /* 20 vars */) = 0
+        "#};
+        assert_eq!(
+            super::executed_programs(trace, Path::new("/build/script"), Path::new("/src")),
+            vec![
+                PathBuf::from("/src/configure"),
+                PathBuf::from("/usr/bin/cc"),
+                PathBuf::from("/usr/bin/pkg-config"),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        let trace = indoc! {r#"
+            100 execve("/build/script", ["/build/script"], 0x7ffd /* 20 vars */) = 0
+            100 clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|SIGCHLD, child_tidptr=0x7f) = 101
+            101 chdir("/nonexistent") = -1 ENOENT (No such file or directory)
+            101 chdir("sub") = 0
+            101 execve("./configure", ["./configure"], 0x7ffd /* 20 vars */) = 0
+            101 clone3({flags=CLONE_VM|CLONE_VFORK, exit_signal=SIGCHLD, stack=0x7f, stack_size=0x9000}, 88 <unfinished ...>
+            102 execve("./gen", ["./gen"], 0x7ffd /* 20 vars */) = 0
+            101 <... clone3 resumed>) = 102
+            100 vfork( <unfinished ...>
+            103 execve("bin/tool", ["bin/tool"], 0x7ffd /* 20 vars */) = 0
+            100 <... vfork resumed>) = 103
+            100 chdir("/tmp") = 0
+            100 fork() = 104
+            104 execve("./a", ["./a"], 0x7ffd /* 20 vars */) = 0
+            100 fork() = 105
+            105 fchdir(3) = 0
+            105 execve("./b", ["./b"], 0x7ffd /* 20 vars */) = 0
+            100 +++ exited with 0 +++
+        "#};
+        assert_eq!(
+            super::executed_programs(trace, Path::new("/build/script"), Path::new("/src")),
+            vec![
+                PathBuf::from("/src/bin/tool"),
+                PathBuf::from("/src/sub/configure"),
+                PathBuf::from("/src/sub/gen"),
+                PathBuf::from("/tmp/a"),
+                PathBuf::from("./b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn allowed_programs() {
+        let allow_exec = vec!["cc".to_owned(), "/opt/tools/bin/protoc".to_owned()];
+        let is_allowed = |program: &str| super::is_allowed(Path::new(program), &allow_exec);
+        assert!(is_allowed("/usr/bin/cc"));
+        assert!(is_allowed("/opt/tools/bin/protoc"));
+        assert!(!is_allowed("/usr/bin/protoc"));
+        assert!(!is_allowed("/usr/bin/c++"));
+    }
+}
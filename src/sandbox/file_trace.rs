@@ -0,0 +1,248 @@
+//! Support for finding out which files a build script was denied access to by the sandbox. We run
+//! the build script under `strace` inside the sandbox, then look for file accesses that failed,
+//! even though the path exists outside the sandbox.
+
+use serde::Deserialize;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Errors that indicate that the sandbox may have denied access to a path.
+const DENIED_ERRORS: &[&str] = &["ENOENT", "EACCES", "EPERM", "EROFS"];
+
+/// System calls that modify the path that is their first argument.
+const WRITE_SYSCALLS: &[&str] = &[
+    "creat",
+    "mkdir",
+    "mkdirat",
+    "mknod",
+    "mknodat",
+    "rmdir",
+    "unlink",
+    "unlinkat",
+    "rename",
+    "renameat",
+    "renameat2",
+    "link",
+    "linkat",
+    "symlink",
+    "symlinkat",
+    "truncate",
+    "chmod",
+    "fchmodat",
+    "chown",
+    "lchown",
+    "fchownat",
+    "utime",
+    "utimes",
+    "utimensat",
+];
+
+/// Flags to open that indicate that the file is being opened for writing.
+const WRITE_FLAGS: &[&str] = &["O_WRONLY", "O_RDWR", "O_CREAT", "O_TRUNC", "O_APPEND"];
+
+/// An attempt to access a path that failed, probably because the sandbox didn't permit it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub(crate) struct FileAccess {
+    pub(crate) path: PathBuf,
+    pub(crate) write: bool,
+}
+
+/// Returns the path to `strace` if it's installed.
+pub(crate) fn strace_path() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join("strace"))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Returns the arguments to pass to `strace` in order to run `binary`, writing a trace of the
+/// system calls selected by `filter` (e.g. `file`) to `trace_file`.
+pub(crate) fn strace_args(trace_file: &Path, filter: &str, binary: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-f", "-qq", "-s", "4096", "-e"]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+    args.push(format!("trace={filter}").into());
+    args.push("-o".into());
+    args.push(trace_file.into());
+    args.push("--".into());
+    args.push(binary.into());
+    args
+}
+
+/// Parses the output of `strace` and returns the file accesses that failed even though the path
+/// exists outside the sandbox. Relative paths are resolved relative to `cwd`.
+pub(crate) fn denied_accesses(trace: &str, cwd: &Path) -> Vec<FileAccess> {
+    let exists = |path: &Path| path.symlink_metadata().is_ok();
+    failed_accesses(trace, cwd)
+        .into_iter()
+        .filter(|access| {
+            exists(&access.path) || (access.write && access.path.parent().map_or(false, exists))
+        })
+        .collect()
+}
+
+/// Returns all file accesses in `trace` that failed with an error that the sandbox might have
+/// caused.
+fn failed_accesses(trace: &str, cwd: &Path) -> BTreeSet<FileAccess> {
+    system_calls(trace)
+        .iter()
+        .filter_map(|call| parse_call(call, cwd))
+        .collect()
+}
+
+/// Returns the system calls in the output of `strace`, without PIDs.
+pub(super) fn system_calls(trace: &str) -> Vec<Cow<'_, str>> {
+    system_calls_by_pid(trace)
+        .into_iter()
+        .map(|(_, call)| call)
+        .collect()
+}
+
+/// Returns the system calls in the output of `strace`, each with the PID that made it.
+pub(super) fn system_calls_by_pid(trace: &str) -> Vec<(&str, Cow<'_, str>)> {
+    // When tracing multiple processes, a system call can be split over two lines if another process
+    // does something while it's in progress. We keep the first part of such calls, keyed by PID.
+    let mut unfinished: HashMap<&str, &str> = HashMap::new();
+    let mut calls = Vec::new();
+    for line in trace.lines() {
+        let (pid, call) = line.split_once(' ').unwrap_or(("", line));
+        let call = call.trim_start();
+        if let Some(start) = call.strip_suffix(" <unfinished ...>") {
+            unfinished.insert(pid, start);
+            continue;
+        }
+        if let Some(resumed) = call.strip_prefix("<... ") {
+            let Some(start) = unfinished.remove(pid) else {
+                continue;
+            };
+            let Some((_, rest)) = resumed.split_once(" resumed>") else {
+                continue;
+            };
+            calls.push((pid, Cow::Owned(format!("{start}{rest}"))));
+        } else {
+            calls.push((pid, Cow::Borrowed(call)));
+        }
+    }
+    calls
+}
+
+/// Parses a single system call from a trace, returning the access if it failed.
+fn parse_call(call: &str, cwd: &Path) -> Option<FileAccess> {
+    let (syscall, rest) = call.split_once('(')?;
+    let (args, result) = rest.rsplit_once(") = ")?;
+    let errno = result.strip_prefix("-1 ")?.split(' ').next()?;
+    if !DENIED_ERRORS.contains(&errno) {
+        return None;
+    }
+    let (before_path, path) = args.split_once('"')?;
+    let path = parse_quoted(path)?;
+    // Relative paths given to the *at system calls are relative to a file descriptor, which we
+    // can only resolve if it's the current directory.
+    if !Path::new(&path).is_absolute()
+        && !before_path.is_empty()
+        && !before_path.starts_with("AT_FDCWD")
+    {
+        return None;
+    }
+    let write = WRITE_SYSCALLS.contains(&syscall)
+        || (syscall.starts_with("open") && WRITE_FLAGS.iter().any(|flag| args.contains(flag)));
+    Some(FileAccess {
+        path: cwd.join(path),
+        write,
+    })
+}
+
+/// Parses a string that `strace` has quoted, given everything after the opening quote.
+pub(super) fn parse_quoted(quoted: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                // Other escapes are for bytes that aren't valid UTF-8 or aren't printable, which we
+                // don't expect in paths that we'd suggest allowing.
+                c @ ('"' | '\\') => out.push(c),
+                _ => return None,
+            },
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileAccess;
+    use indoc::indoc;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_trace() {
+        let trace = indoc! {r#"
+            100 execve("/build/script", ["/build/script"], 0x7ffd /* 20 vars */) = 0
+            100 openat(AT_FDCWD, "/etc/ld.so.cache", O_RDONLY|O_CLOEXEC) = 3
+            100 openat(AT_FDCWD, "/opt/foo/include/foo.h", O_RDONLY) = -1 ENOENT (No such file or directory)
+            101 openat(AT_FDCWD, "/usr/share/x \"y\"", O_RDONLY <unfinished ...>
+            100 mkdir("/var/cache/foo", 0777) = -1 EROFS (Read-only file system)
+            101 <... openat resumed>) = -1 EACCES (Permission denied)
+            100 openat(AT_FDCWD, "out.txt", O_WRONLY|O_CREAT|O_TRUNC, 0666) = -1 EACCES (Permission denied)
+            100 openat(3</some/dir>, "relative", O_RDONLY) = -1 ENOENT (No such file or directory)
+            100 statx(AT_FDCWD, "/nonexistent", AT_STATX_SYNC_AS_STAT, 0x7ff) = -1 ENOTDIR (Not a directory)
+            100 +++ exited with 1 +++
+        "#};
+        let access = |path: &str, write| FileAccess {
+            path: PathBuf::from(path),
+            write,
+        };
+        assert_eq!(
+            super::failed_accesses(trace, Path::new("/src"))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                access("/opt/foo/include/foo.h", false),
+                access("/src/out.txt", true),
+                access("/usr/share/x \"y\"", false),
+                access("/var/cache/foo", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_existing_paths_are_denied() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::write(dir.join("exists.h"), "").unwrap();
+        let trace = format!(
+            indoc! {r#"
+                100 openat(AT_FDCWD, "{dir}/exists.h", O_RDONLY) = -1 EACCES (Permission denied)
+                100 openat(AT_FDCWD, "{dir}/missing.h", O_RDONLY) = -1 ENOENT (No such file or directory)
+                100 openat(AT_FDCWD, "{dir}/new.txt", O_WRONLY|O_CREAT, 0666) = -1 EROFS (Read-only file system)
+                100 mkdir("{dir}/missing/new", 0777) = -1 ENOENT (No such file or directory)
+            "#},
+            dir = dir.display()
+        );
+        assert_eq!(
+            super::denied_accesses(&trace, Path::new("/src")),
+            vec![
+                FileAccess {
+                    path: dir.join("exists.h"),
+                    write: false,
+                },
+                FileAccess {
+                    path: dir.join("new.txt"),
+                    write: true,
+                },
+            ]
+        );
+    }
+}
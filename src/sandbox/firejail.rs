@@ -0,0 +1,160 @@
+use super::Sandbox;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Default)]
+pub(super) struct Firejail {
+    args: Vec<OsString>,
+    read_only: Vec<PathBuf>,
+    writable: Vec<PathBuf>,
+    hidden: Vec<PathBuf>,
+    allow_network: bool,
+    /// If set, the network relay, which runs the binary.
+    relay: Option<PathBuf>,
+}
+
+/// Returns `prefix` with `path` appended.
+fn flag(prefix: &str, path: &Path) -> OsString {
+    let mut flag = OsString::from(prefix);
+    flag.push(path);
+    flag
+}
+
+impl Sandbox for Firejail {
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command> {
+        let mut command = Command::new("firejail");
+        // Firejail doesn't have an equivalent of bubblewrap's `--clearenv`, so we clear the
+        // environment here and pass the variables that we want via `--env`.
+        command.env_clear().current_dir(dir);
+        command
+            .args(["--quiet", "--noprofile"])
+            .args(["--private-dev", "--nogroups", "--nonewprivs"])
+            .arg("--hostname=none");
+        if !self.allow_network {
+            command.arg("--net=none");
+        }
+        // Firejail has no equivalent of mounting a tmpfs over an arbitrary directory. Instead,
+        // whitelisting a path makes the rest of the directory that contains it inaccessible.
+        for dir in &self.hidden {
+            let mut whitelisted = false;
+            for bound in self.read_only.iter().chain(&self.writable) {
+                if bound != dir && bound.starts_with(dir) {
+                    command.arg(flag("--whitelist=", bound));
+                    whitelisted = true;
+                }
+            }
+            if whitelisted {
+                continue;
+            }
+            if dir == Path::new("/tmp") {
+                command.arg("--private-tmp");
+            } else {
+                command.arg(flag("--blacklist=", dir));
+            }
+        }
+        for dir in &self.read_only {
+            command.arg(flag("--read-only=", dir));
+        }
+        for dir in &self.writable {
+            command.arg(flag("--read-write=", dir));
+        }
+        command
+            .args(&self.args)
+            .arg("--")
+            .args(&self.relay)
+            .arg(binary)
+            .args(args);
+        Ok(command)
+    }
+
+    fn raw_arg(&mut self, arg: &OsStr) {
+        self.args.push(arg.to_owned());
+    }
+
+    fn tmpfs(&mut self, dir: &Path) {
+        self.hidden.push(dir.to_owned());
+    }
+
+    fn ro_bind(&mut self, dir: &Path) {
+        self.read_only.push(dir.to_owned());
+    }
+
+    fn writable_bind(&mut self, dir: &Path) {
+        self.writable.push(dir.to_owned());
+    }
+
+    fn set_env(&mut self, var: &OsStr, value: &OsStr) {
+        let mut arg = OsString::from("--env=");
+        arg.push(var);
+        arg.push("=");
+        arg.push(value);
+        self.args.push(arg);
+    }
+
+    fn allow_network(&mut self) {
+        self.allow_network = true;
+    }
+
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool> {
+        self.relay = Some(super::net_proxy::bind_relay(self, socket_path)?);
+        Ok(true)
+    }
+}
+
+pub(super) fn has_firejail() -> bool {
+    Command::new("firejail")
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Firejail;
+    use crate::sandbox::Sandbox;
+    use std::path::Path;
+
+    #[test]
+    fn hidden_dirs() {
+        let mut sandbox = Firejail::default();
+        sandbox.ro_bind(Path::new("/"));
+        sandbox.tmpfs(Path::new("/home/user"));
+        sandbox.tmpfs(Path::new("/tmp"));
+        sandbox.tmpfs(Path::new("/var"));
+        sandbox.ro_bind(Path::new("/home/user/.cargo/registry"));
+        sandbox.writable_bind(Path::new("/home/user/project/target/out"));
+        let command = sandbox
+            .command(Path::new("/bin/build-script"), &[], Path::new("/"))
+            .unwrap();
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                "--quiet",
+                "--noprofile",
+                "--private-dev",
+                "--nogroups",
+                "--nonewprivs",
+                "--hostname=none",
+                "--net=none",
+                "--whitelist=/home/user/.cargo/registry",
+                "--whitelist=/home/user/project/target/out",
+                "--private-tmp",
+                "--blacklist=/var",
+                "--read-only=/",
+                "--read-only=/home/user/.cargo/registry",
+                "--read-write=/home/user/project/target/out",
+                "--",
+                "/bin/build-script",
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! A sandbox that uses Landlock to restrict filesystem access and a seccomp filter to restrict
+//! network access. Unlike the other sandboxes, this doesn't require any external binary or
+//! unprivileged user namespaces. Restrictions can only be applied to the current process, so we run
+//! a copy of ourselves, which applies the restrictions, then executes the binary. When network
+//! access goes via the proxy, that copy of ourselves also acts as the relay and Landlock restricts
+//! TCP connections to only the relay.
+
+use super::net_proxy;
+use super::Sandbox;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use landlock::path_beneath_rules;
+use landlock::Access;
+use landlock::AccessFs;
+use landlock::AccessNet;
+use landlock::CompatLevel;
+use landlock::Compatible;
+use landlock::NetPort;
+use landlock::Ruleset;
+use landlock::RulesetAttr;
+use landlock::RulesetCreatedAttr;
+use landlock::RulesetStatus;
+use landlock::ABI;
+use seccompiler::SeccompAction;
+use seccompiler::SeccompCmpArgLen;
+use seccompiler::SeccompCmpOp;
+use seccompiler::SeccompCondition;
+use seccompiler::SeccompFilter;
+use seccompiler::SeccompRule;
+use serde::Deserialize;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Environment variable used to pass the rules to the copy of ourselves that runs the binary.
+const RULES_ENV: &str = "CACKLE_LANDLOCK_RULES";
+
+/// The most recent Landlock ABI that we know about. Features from later ABIs than the kernel
+/// supports are ignored.
+const ABI: ABI = ABI::V5;
+
+/// Devices that are always writable.
+const WRITABLE_DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/full"];
+
+#[derive(Default)]
+pub(super) struct Landlock {
+    rules: Rules,
+    env: Vec<(OsString, OsString)>,
+    raw_args: Vec<OsString>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
+struct Rules {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    /// Directories that shouldn't be readable, even though an ancestor is.
+    hidden: Vec<PathBuf>,
+    allow_network: bool,
+    /// If set, the socket of the proxy via which network access should go.
+    net_relay: Option<PathBuf>,
+}
+
+impl Sandbox for Landlock {
+    fn raw_arg(&mut self, arg: &OsStr) {
+        self.raw_args.push(arg.to_owned());
+    }
+
+    fn tmpfs(&mut self, dir: &Path) {
+        self.rules.hidden.push(dir.to_owned());
+    }
+
+    fn private_tmp(&mut self, dir: &Path) {
+        self.writable_bind(dir);
+        self.set_env(OsStr::new("TMPDIR"), dir.as_os_str());
+    }
+
+    fn ro_bind(&mut self, dir: &Path) {
+        self.rules.read.push(dir.to_owned());
+    }
+
+    fn writable_bind(&mut self, dir: &Path) {
+        self.rules.write.push(dir.to_owned());
+    }
+
+    fn set_env(&mut self, var: &OsStr, value: &OsStr) {
+        self.env.push((var.to_owned(), value.to_owned()));
+    }
+
+    fn allow_network(&mut self) {
+        self.rules.allow_network = true;
+    }
+
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool> {
+        if !supports_network_rules() {
+            return Ok(false);
+        }
+        self.rules.net_relay = Some(socket_path.to_owned());
+        Ok(true)
+    }
+
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command> {
+        if !self.raw_args.is_empty() {
+            bail!("The Landlock sandbox doesn't support `extra_args`");
+        }
+        let mut command =
+            Command::new(std::env::current_exe().context("Failed to get current exe")?);
+        command
+            .arg(binary)
+            .args(args)
+            .current_dir(dir)
+            .env_clear()
+            .envs(self.env.iter().map(|(var, value)| (var, value)))
+            .env(RULES_ENV, serde_json::to_string(&self.rules)?);
+        Ok(command)
+    }
+
+    fn display_to_run(&self, binary: &Path, args: &[OsString]) -> Box<dyn Display> {
+        Box::new(LandlockDisplay {
+            read: self.rules.readable_paths(),
+            write: self.rules.write.clone(),
+            allow_network: self.rules.allow_network,
+            proxied: self.rules.net_relay.is_some(),
+            binary: binary.to_owned(),
+            args: args.to_owned(),
+        })
+    }
+}
+
+struct LandlockDisplay {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    allow_network: bool,
+    proxied: bool,
+    binary: PathBuf,
+    args: Vec<OsString>,
+}
+
+impl Display for LandlockDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Landlock running {}", self.binary.display())?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        writeln!(f)?;
+        for path in &self.read {
+            writeln!(f, "  read: {}", path.display())?;
+        }
+        for path in &self.write {
+            writeln!(f, "  write: {}", path.display())?;
+        }
+        if self.proxied {
+            write!(f, "  network: via proxy")
+        } else {
+            write!(f, "  network: {}", self.allow_network)
+        }
+    }
+}
+
+impl Rules {
+    /// Returns the paths that should be readable. Landlock rules can only grant access, not deny
+    /// it, so where a hidden directory is inside a readable directory, we instead grant access to
+    /// each entry in the readable directory, other than the hidden directory.
+    fn readable_paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for path in &self.read {
+            self.add_readable(path, &mut paths);
+        }
+        paths
+    }
+
+    fn add_readable(&self, path: &Path, out: &mut Vec<PathBuf>) {
+        if !self
+            .hidden
+            .iter()
+            .any(|hidden| hidden != path && hidden.starts_with(path))
+        {
+            out.push(path.to_owned());
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if !self.hidden.contains(&entry_path) {
+                self.add_readable(&entry_path, out);
+            }
+        }
+    }
+
+    /// Restricts the current process, and any processes that it subsequently starts, according to
+    /// our rules. If `relay_port` is supplied, then TCP connections are permitted only to that port.
+    fn restrict_self(&self, relay_port: Option<u16>) -> Result<()> {
+        let existing = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+            paths.into_iter().filter(|path| path.exists()).collect()
+        };
+        let writable = self
+            .write
+            .iter()
+            .cloned()
+            .chain(WRITABLE_DEVICES.iter().map(PathBuf::from))
+            .collect();
+        let mut ruleset = Ruleset::default().handle_access(AccessFs::from_all(ABI))?;
+        if relay_port.is_some() {
+            ruleset = ruleset
+                .set_compatibility(CompatLevel::HardRequirement)
+                .handle_access(AccessNet::from_all(ABI::V4))?
+                .set_compatibility(CompatLevel::BestEffort);
+        }
+        let mut ruleset = ruleset
+            .create()?
+            .add_rules(path_beneath_rules(
+                existing(self.readable_paths()),
+                AccessFs::from_read(ABI),
+            ))?
+            .add_rules(path_beneath_rules(
+                existing(writable),
+                AccessFs::from_all(ABI),
+            ))?;
+        if let Some(port) = relay_port {
+            ruleset = ruleset.add_rule(NetPort::new(port, AccessNet::ConnectTcp))?;
+        }
+        let status = ruleset.restrict_self()?;
+        if status.ruleset == RulesetStatus::NotEnforced {
+            bail!("Landlock isn't supported by the running kernel");
+        }
+        if !self.allow_network {
+            deny_network(relay_port.is_some())?;
+        }
+        Ok(())
+    }
+}
+
+/// Installs a seccomp filter that causes creation of IPv4 and IPv6 sockets to fail. If `allow_tcp`
+/// is true, then TCP sockets are still permitted, since Landlock restricts what they can connect to.
+fn deny_network(allow_tcp: bool) -> Result<()> {
+    let condition =
+        |arg, op, value: i32| SeccompCondition::new(arg, SeccompCmpArgLen::Dword, op, value as u64);
+    let mut rules = Vec::new();
+    for domain in [libc::AF_INET, libc::AF_INET6] {
+        let is_domain = || condition(0, SeccompCmpOp::Eq, domain);
+        if !allow_tcp {
+            rules.push(SeccompRule::new(vec![is_domain()?])?);
+            continue;
+        }
+        // The socket type can have flags such as SOCK_CLOEXEC in its upper bits.
+        for socket_type in [
+            libc::SOCK_DGRAM,
+            libc::SOCK_RAW,
+            libc::SOCK_RDM,
+            libc::SOCK_SEQPACKET,
+        ] {
+            rules.push(SeccompRule::new(vec![
+                is_domain()?,
+                condition(1, SeccompCmpOp::MaskedEq(0xf), socket_type)?,
+            ])?);
+        }
+        // Stream sockets with protocols other than TCP, e.g. SCTP.
+        rules.push(SeccompRule::new(vec![
+            is_domain()?,
+            condition(2, SeccompCmpOp::Ne, 0)?,
+            condition(2, SeccompCmpOp::Ne, libc::IPPROTO_TCP)?,
+        ])?);
+    }
+    let filter = SeccompFilter::new(
+        [(libc::SYS_socket, rules)].into_iter().collect(),
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EACCES as u32),
+        std::env::consts::ARCH
+            .try_into()
+            .map_err(|_| anyhow!("Unsupported architecture for seccomp"))?,
+    )?;
+    let program: seccompiler::BpfProgram = filter.try_into()?;
+    seccompiler::apply_filter(&program)?;
+    Ok(())
+}
+
+/// Returns whether the running kernel supports Landlock.
+pub(super) fn is_supported() -> bool {
+    Ruleset::default()
+        .set_compatibility(CompatLevel::HardRequirement)
+        .handle_access(AccessFs::from_all(ABI::V1))
+        .and_then(|ruleset| ruleset.create())
+        .is_ok()
+}
+
+/// Returns whether the running kernel supports Landlock restrictions on TCP connections.
+fn supports_network_rules() -> bool {
+    Ruleset::default()
+        .set_compatibility(CompatLevel::HardRequirement)
+        .handle_access(AccessNet::from_all(ABI::V4))
+        .and_then(|ruleset| ruleset.create())
+        .is_ok()
+}
+
+/// If `RULES_ENV` is set, then we were started by `Landlock::run`. Apply the rules, then execute the
+/// binary that we were asked to run, relaying its network connections if requested.
+pub(super) fn exec_if_requested() -> Result<()> {
+    let Some(json) = std::env::var_os(RULES_ENV) else {
+        return Ok(());
+    };
+    let rules: Rules = serde_json::from_str(
+        json.to_str()
+            .ok_or_else(|| anyhow!("{RULES_ENV} isn't valid UTF-8"))?,
+    )
+    .with_context(|| format!("Failed to parse {RULES_ENV}"))?;
+    let mut args = std::env::args_os().skip(1);
+    let binary = args
+        .next()
+        .ok_or_else(|| anyhow!("Missing binary to run with Landlock"))?;
+    // The relay needs to be bound before we restrict ourselves, so that we know which port to
+    // permit connections to.
+    let relay_listener = match &rules.net_relay {
+        Some(_) => Some(net_proxy::bind_relay_listener()?),
+        None => None,
+    };
+    let relay_port = match &relay_listener {
+        Some(listener) => Some(listener.local_addr()?.port()),
+        None => None,
+    };
+    rules.restrict_self(relay_port)?;
+    let mut command = Command::new(&binary);
+    command.args(args).env_remove(RULES_ENV);
+    if let (Some(listener), Some(socket_path)) = (relay_listener, rules.net_relay) {
+        std::process::exit(net_proxy::run_relayed(listener, socket_path, command)?);
+    }
+    let error = command.exec();
+    Err(error).with_context(|| format!("Failed to run `{}`", Path::new(&binary).display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rules;
+    use std::path::PathBuf;
+
+    #[test]
+    fn readable_paths_exclude_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for subdir in [
+            "home/user/.cargo/registry",
+            "home/other",
+            "usr/bin",
+            "usr/share",
+        ] {
+            std::fs::create_dir_all(root.join(subdir)).unwrap();
+        }
+        let rules = Rules {
+            read: vec![root.to_owned(), root.join("home/user/.cargo/registry")],
+            hidden: vec![root.join("home/user"), root.join("usr/share")],
+            ..Rules::default()
+        };
+        let mut readable: Vec<PathBuf> = rules
+            .readable_paths()
+            .into_iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_owned())
+            .collect();
+        readable.sort();
+        assert_eq!(
+            readable,
+            vec![
+                PathBuf::from("home/other"),
+                PathBuf::from("home/user/.cargo/registry"),
+                PathBuf::from("usr/bin"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Resource limits for sandboxed binaries. Limits are applied by running the binary inside the
+//! sandbox via `timeout` and `prlimit`, so they work the same way regardless of the kind of sandbox.
+
+use super::Sandbox;
+use crate::config::SandboxConfig;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Exit code used by `timeout` when the command timed out.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Messages that indicate that a process ran out of memory.
+const MEMORY_ERRORS: &[&str] = &[
+    "memory allocation of",
+    "Cannot allocate memory",
+    "out of memory",
+];
+
+/// How long after the wall-clock time limit we kill a binary that didn't exit when asked to.
+const KILL_AFTER_SECS: u64 = 10;
+
+/// A resource limit that a binary may exceed.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub(crate) enum ResourceLimit {
+    CpuTime,
+    WallTime,
+    Memory,
+    Processes,
+}
+
+/// A sandbox that applies resource limits to what it runs, then delegates to another sandbox.
+pub(super) struct Limited {
+    inner: Box<dyn Sandbox>,
+    config: SandboxConfig,
+}
+
+impl Limited {
+    /// Returns `inner`, wrapped so as to apply the limits from `config` if there are any.
+    pub(super) fn wrap(inner: Box<dyn Sandbox>, config: &SandboxConfig) -> Box<dyn Sandbox> {
+        if config.max_cpu_secs.is_none()
+            && config.max_wall_secs.is_none()
+            && config.max_memory_mib.is_none()
+            && config.max_processes.is_none()
+        {
+            return inner;
+        }
+        Box::new(Self {
+            inner,
+            config: config.clone(),
+        })
+    }
+
+    /// Returns the binary and arguments to pass to the inner sandbox in order to run `binary` with
+    /// `args` subject to our limits. If `foreground` is set, then the binary is kept in the
+    /// foreground process group, so that it can read from the terminal and receive signals such as
+    /// the one sent by Ctrl-C.
+    fn limited_command(
+        &self,
+        binary: &Path,
+        args: &[OsString],
+        foreground: bool,
+    ) -> (OsString, Vec<OsString>) {
+        let mut command: Vec<OsString> = Vec::new();
+        if let Some(secs) = self.config.max_wall_secs {
+            command.push("timeout".into());
+            if foreground {
+                command.push("--foreground".into());
+            }
+            command.push(format!("--kill-after={KILL_AFTER_SECS}").into());
+            command.push(secs.to_string().into());
+        }
+        let rlimits: Vec<OsString> = [
+            // The hard limit for CPU time is a little above the soft limit, so that the binary gets
+            // SIGXCPU before it gets SIGKILL.
+            ("cpu", self.config.max_cpu_secs.map(|secs| (secs, secs + 1))),
+            (
+                "as",
+                self.config
+                    .max_memory_mib
+                    .map(|mib| mib.saturating_mul(1024 * 1024))
+                    .map(|bytes| (bytes, bytes)),
+            ),
+            ("nproc", self.config.max_processes.map(|n| (n, n))),
+        ]
+        .into_iter()
+        .filter_map(|(name, limit)| {
+            limit.map(|(soft, hard)| OsString::from(format!("--{name}={soft}:{hard}")))
+        })
+        .collect();
+        if !rlimits.is_empty() {
+            command.push("prlimit".into());
+            command.extend(rlimits);
+            command.push("--".into());
+        }
+        command.push(binary.into());
+        command.extend(args.iter().cloned());
+        let program = command.remove(0);
+        (program, command)
+    }
+}
+
+impl Sandbox for Limited {
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command> {
+        let (program, args) = self.limited_command(binary, args, false);
+        self.inner.command(Path::new(&program), &args, dir)
+    }
+
+    fn run_interactive(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<ExitStatus> {
+        let (program, args) = self.limited_command(binary, args, true);
+        self.inner.run_interactive(Path::new(&program), &args, dir)
+    }
+
+    fn tmpfs(&mut self, dir: &Path) {
+        self.inner.tmpfs(dir);
+    }
+
+    fn set_env(&mut self, var: &OsStr, value: &OsStr) {
+        self.inner.set_env(var, value);
+    }
+
+    fn ro_bind(&mut self, dir: &Path) {
+        self.inner.ro_bind(dir);
+    }
+
+    fn writable_bind(&mut self, dir: &Path) {
+        self.inner.writable_bind(dir);
+    }
+
+    fn allow_network(&mut self) {
+        self.inner.allow_network();
+    }
+
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool> {
+        self.inner.proxy_network(socket_path)
+    }
+
+    fn raw_arg(&mut self, arg: &OsStr) {
+        self.inner.raw_arg(arg);
+    }
+
+    fn display_to_run(&self, binary: &Path, args: &[OsString]) -> Box<dyn Display> {
+        let (program, args) = self.limited_command(binary, args, false);
+        self.inner.display_to_run(Path::new(&program), &args)
+    }
+}
+
+/// Returns the limit from `config` that `output` indicates was exceeded, if any. `elapsed` is how
+/// long the binary ran for. Exceeding the time limits is detected reliably. Exceeding the memory or
+/// process limits just results in allocations or forks failing, so we go by the error messages that
+/// these commonly produce.
+pub(crate) fn exceeded_limit(
+    config: &SandboxConfig,
+    output: &std::process::Output,
+    elapsed: Duration,
+) -> Option<ResourceLimit> {
+    if output.status.success() {
+        return None;
+    }
+    // Depending on the sandbox, death by a signal may be reported as an exit code of 128 plus the
+    // signal number.
+    let signal = output
+        .status
+        .signal()
+        .or_else(|| output.status.code().map(|code| code - 128));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // A SIGKILL may come from `timeout` if the binary didn't exit when asked to, but equally from
+    // the kernel's OOM killer or from exceeding the hard CPU time limit. So we only attribute it to
+    // `timeout` if it came after `timeout` would have sent it, and we only take SIGXCPU to mean
+    // that the CPU time limit was hit.
+    let killed_by_timeout = config.max_wall_secs.map_or(false, |secs| {
+        signal == Some(libc::SIGKILL) && elapsed >= Duration::from_secs(secs + KILL_AFTER_SECS)
+    });
+    if (config.max_wall_secs.is_some() && output.status.code() == Some(TIMEOUT_EXIT_CODE))
+        || killed_by_timeout
+    {
+        Some(ResourceLimit::WallTime)
+    } else if config.max_cpu_secs.is_some() && signal == Some(libc::SIGXCPU) {
+        Some(ResourceLimit::CpuTime)
+    } else if config.max_memory_mib.is_some()
+        && MEMORY_ERRORS.iter().any(|message| stderr.contains(message))
+    {
+        Some(ResourceLimit::Memory)
+    } else if config.max_processes.is_some() && stderr.contains("Resource temporarily unavailable")
+    {
+        Some(ResourceLimit::Processes)
+    } else {
+        None
+    }
+}
+
+impl ResourceLimit {
+    /// Returns the name of the config option that sets this limit.
+    pub(crate) fn config_key(self) -> &'static str {
+        match self {
+            ResourceLimit::CpuTime => "max_cpu_secs",
+            ResourceLimit::WallTime => "max_wall_secs",
+            ResourceLimit::Memory => "max_memory_mib",
+            ResourceLimit::Processes => "max_processes",
+        }
+    }
+
+    /// Returns the value of this limit in `config`.
+    pub(crate) fn value(self, config: &SandboxConfig) -> Option<u64> {
+        match self {
+            ResourceLimit::CpuTime => config.max_cpu_secs,
+            ResourceLimit::WallTime => config.max_wall_secs,
+            ResourceLimit::Memory => config.max_memory_mib,
+            ResourceLimit::Processes => config.max_processes,
+        }
+    }
+}
+
+impl Display for ResourceLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceLimit::CpuTime => write!(f, "CPU time limit"),
+            ResourceLimit::WallTime => write!(f, "time limit"),
+            ResourceLimit::Memory => write!(f, "memory limit"),
+            ResourceLimit::Processes => write!(f, "process limit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResourceLimit;
+    use crate::config::SandboxConfig;
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::Path;
+    use std::process::ExitStatus;
+    use std::process::Output;
+    use std::time::Duration;
+
+    fn output(status: ExitStatus, stderr: &str) -> Output {
+        Output {
+            status,
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_owned(),
+        }
+    }
+
+    #[test]
+    fn exceeded_limits() {
+        let config = SandboxConfig {
+            max_cpu_secs: Some(10),
+            max_wall_secs: Some(20),
+            max_memory_mib: Some(100),
+            ..SandboxConfig::default()
+        };
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        let signalled = |signal: i32| ExitStatus::from_raw(signal);
+        let exceeded_after = |status, secs| {
+            super::exceeded_limit(&config, &output(status, ""), Duration::from_secs(secs))
+        };
+        let exceeded = |status, stderr| {
+            super::exceeded_limit(&config, &output(status, stderr), Duration::from_secs(1))
+        };
+        assert_eq!(exceeded(exited(0), ""), None);
+        assert_eq!(exceeded(exited(1), ""), None);
+        assert_eq!(exceeded(exited(124), ""), Some(ResourceLimit::WallTime));
+        assert_eq!(
+            exceeded(signalled(libc::SIGXCPU), ""),
+            Some(ResourceLimit::CpuTime)
+        );
+        assert_eq!(
+            exceeded(exited(128 + libc::SIGXCPU), ""),
+            Some(ResourceLimit::CpuTime)
+        );
+        // A SIGKILL is only attributed to `timeout` once it would have killed the binary.
+        assert_eq!(
+            exceeded_after(signalled(libc::SIGKILL), 30),
+            Some(ResourceLimit::WallTime)
+        );
+        assert_eq!(
+            exceeded_after(exited(128 + libc::SIGKILL), 30),
+            Some(ResourceLimit::WallTime)
+        );
+        assert_eq!(exceeded_after(signalled(libc::SIGKILL), 29), None);
+        assert_eq!(exceeded(signalled(libc::SIGKILL), ""), None);
+        assert_eq!(
+            exceeded(
+                signalled(libc::SIGABRT),
+                "memory allocation of 1024 bytes failed"
+            ),
+            Some(ResourceLimit::Memory)
+        );
+        assert_eq!(
+            exceeded(exited(1), "fork: Resource temporarily unavailable"),
+            None
+        );
+        assert_eq!(
+            super::exceeded_limit(
+                &SandboxConfig::default(),
+                &output(exited(124), ""),
+                Duration::from_secs(1)
+            ),
+            None
+        );
+
+        // Without a wall-clock limit, a SIGKILL, e.g. from the OOM killer, isn't attributed to the
+        // CPU time limit, however long the binary ran for.
+        let config = SandboxConfig {
+            max_cpu_secs: Some(10),
+            ..SandboxConfig::default()
+        };
+        assert_eq!(
+            super::exceeded_limit(
+                &config,
+                &output(signalled(libc::SIGKILL), ""),
+                Duration::from_secs(100)
+            ),
+            None
+        );
+        assert_eq!(
+            super::exceeded_limit(
+                &config,
+                &output(signalled(libc::SIGXCPU), ""),
+                Duration::from_secs(10)
+            ),
+            Some(ResourceLimit::CpuTime)
+        );
+    }
+
+    #[test]
+    fn limited_command() {
+        let limited = super::Limited {
+            inner: Box::new(crate::sandbox::bubblewrap::Bubblewrap::default()),
+            config: SandboxConfig {
+                max_wall_secs: Some(5),
+                max_memory_mib: Some(u64::MAX),
+                ..SandboxConfig::default()
+            },
+        };
+        let command = |foreground| {
+            let (program, args) =
+                limited.limited_command(Path::new("/bin/build-script"), &[], foreground);
+            std::iter::once(program)
+                .chain(args)
+                .map(|arg| arg.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let max = u64::MAX;
+        assert_eq!(
+            command(false),
+            vec![
+                "timeout".to_owned(),
+                "--kill-after=10".to_owned(),
+                "5".to_owned(),
+                "prlimit".to_owned(),
+                format!("--as={max}:{max}"),
+                "--".to_owned(),
+                "/bin/build-script".to_owned(),
+            ]
+        );
+        assert_eq!(command(true)[1], "--foreground");
+    }
+}
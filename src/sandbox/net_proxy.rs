@@ -0,0 +1,531 @@
+//! Support for giving sandboxed build scripts access to particular hosts. Rather than giving the
+//! sandbox network access, we run a filtering HTTP proxy outside the sandbox that listens on a unix
+//! socket and only connects to hosts that are permitted. Inside the sandbox, a relay listens on a
+//! loopback TCP port and forwards connections to the proxy's socket. The build script is pointed at
+//! the relay via the usual proxy environment variables.
+
+use super::Sandbox;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+/// Environment variable that, when set, tells us to run as a relay to the proxy listening on the
+/// socket named by the variable's value.
+const RELAY_ENV: &str = "CACKLE_NET_RELAY";
+
+/// Environment variables via which we tell the build script about the proxy.
+const PROXY_ENV_VARS: &[&str] = &[
+    "http_proxy",
+    "https_proxy",
+    "all_proxy",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+];
+
+/// The maximum size of request header that we'll accept.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// An attempt by a build script to connect to a host via the proxy.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Hash)]
+pub(crate) struct NetworkAttempt {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) allowed: bool,
+}
+
+/// A running proxy. The proxy stops accepting connections when this is dropped. Connections that
+/// were already accepted are left to finish on their own.
+pub(crate) struct NetProxy {
+    socket_path: PathBuf,
+    attempts: Arc<Mutex<Vec<NetworkAttempt>>>,
+    shutting_down: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl NetProxy {
+    /// Starts a proxy listening on `socket_path` that permits connections to `allow_hosts`. Each
+    /// entry is either `host` or `host:port`. A host of the form `*.example.com` matches any
+    /// subdomain of `example.com`.
+    pub(crate) fn start(socket_path: PathBuf, allow_hosts: &[String]) -> Result<Self> {
+        // A socket may have been left behind by an earlier run that was killed.
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)
+            .with_context(|| format!("Failed to listen on `{}`", socket_path.display()))?;
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let allow_hosts = Arc::new(allow_hosts.to_owned());
+        let proxy_attempts = attempts.clone();
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let proxy_shutting_down = shutting_down.clone();
+        let accept_thread = std::thread::spawn(move || {
+            for connection in listener.incoming() {
+                if proxy_shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(connection) = connection else {
+                    break;
+                };
+                let allow_hosts = allow_hosts.clone();
+                let attempts = proxy_attempts.clone();
+                std::thread::spawn(move || {
+                    // Errors here are the result of connections being closed early, which only
+                    // affects the build script that made the connection.
+                    let _ = handle_connection(connection, &allow_hosts, &attempts);
+                });
+            }
+        });
+        Ok(Self {
+            socket_path,
+            attempts,
+            shutting_down,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub(crate) fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Returns all connection attempts that have been made so far.
+    pub(crate) fn attempts(&self) -> Vec<NetworkAttempt> {
+        self.attempts.lock().unwrap().clone()
+    }
+}
+
+impl Drop for NetProxy {
+    fn drop(&mut self) {
+        // The accept thread is blocked waiting for a connection, so we wake it with one of our own.
+        // If we can't connect, then we can't be sure that it'll wake, so we leave it running rather
+        // than block waiting for it.
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if UnixStream::connect(&self.socket_path).is_ok() {
+            if let Some(accept_thread) = self.accept_thread.take() {
+                let _ = accept_thread.join();
+            }
+        }
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+fn handle_connection(
+    mut client: UnixStream,
+    allow_hosts: &[String],
+    attempts: &Mutex<Vec<NetworkAttempt>>,
+) -> Result<()> {
+    let (head, rest) = read_head(&mut client)?;
+    let request = ProxyRequest::parse(&head)?;
+    let allowed = is_allowed(&request.host, request.port, allow_hosts);
+    attempts.lock().unwrap().push(NetworkAttempt {
+        host: request.host.clone(),
+        port: request.port,
+        allowed,
+    });
+    if !allowed {
+        return respond(
+            &mut client,
+            "403 Forbidden",
+            &format!(
+                "cackle: connection to {}:{} is not permitted\n",
+                request.host, request.port
+            ),
+        );
+    }
+    let connect_host = request.host.trim_start_matches('[').trim_end_matches(']');
+    let Ok(mut server) = TcpStream::connect((connect_host, request.port)) else {
+        return respond(
+            &mut client,
+            "502 Bad Gateway",
+            &format!(
+                "cackle: failed to connect to {}:{}\n",
+                request.host, request.port
+            ),
+        );
+    };
+    match &request.forward {
+        Some(forward) => server.write_all(forward.as_bytes())?,
+        None => client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?,
+    }
+    server.write_all(&rest)?;
+    copy_bidirectional(client, server)
+}
+
+fn respond(client: &mut UnixStream, status: &str, body: &str) -> Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: \
+         close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// Reads from `stream` until the end of the request header. Returns the header and anything that
+/// was read after it.
+fn read_head(stream: &mut impl Read) -> Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            return Ok((String::from_utf8(buf)?, rest));
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            bail!("Request header too long");
+        }
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            bail!("Connection closed before end of request header");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ProxyRequest {
+    host: String,
+    port: u16,
+    /// For plain HTTP requests, the request header to send to the server. `None` for CONNECT
+    /// requests, where everything after the header goes to the server unchanged.
+    forward: Option<String>,
+}
+
+impl ProxyRequest {
+    fn parse(head: &str) -> Result<Self> {
+        let (request_line, headers) = head
+            .split_once("\r\n")
+            .ok_or_else(|| anyhow!("Invalid request"))?;
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Invalid request line `{request_line}`");
+        };
+        if method.eq_ignore_ascii_case("CONNECT") {
+            let (host, Some(port)) = split_host_port(target) else {
+                bail!("CONNECT request without port `{target}`");
+            };
+            return Ok(Self {
+                host: host.to_owned(),
+                port,
+                forward: None,
+            });
+        }
+        let Some(url) = target
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &target[7..])
+        else {
+            bail!("Unsupported request target `{target}`");
+        };
+        let (authority, path) = match url.find('/') {
+            Some(index) => url.split_at(index),
+            None => (url, "/"),
+        };
+        let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+        let (host, port) = split_host_port(authority);
+        // The server gets the path rather than the full URL and no headers intended for the proxy.
+        let mut forward = format!("{method} {path} {version}\r\n");
+        for header in headers.split_inclusive("\r\n") {
+            if !header
+                .get(..6)
+                .map_or(false, |prefix| prefix.eq_ignore_ascii_case("proxy-"))
+            {
+                forward.push_str(header);
+            }
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port: port.unwrap_or(80),
+            forward: Some(forward),
+        })
+    }
+}
+
+/// Splits `authority` into a host and, if present, a port. IPv6 addresses must be in brackets.
+fn split_host_port(authority: &str) -> (&str, Option<u16>) {
+    if let Some((host, port)) = authority.rsplit_once(':') {
+        if !host.contains(':') || host.ends_with(']') {
+            if let Ok(port) = port.parse() {
+                return (host, Some(port));
+            }
+        }
+    }
+    (authority, None)
+}
+
+fn is_allowed(host: &str, port: u16, allow_hosts: &[String]) -> bool {
+    allow_hosts.iter().any(|entry| {
+        let (pattern, allowed_port) = split_host_port(entry);
+        allowed_port.map_or(true, |allowed_port| allowed_port == port)
+            && host_matches(host, pattern)
+    })
+}
+
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    if let Some(domain) = pattern.strip_prefix("*.") {
+        host.strip_suffix(domain)
+            .map_or(false, |prefix| prefix.len() > 1 && prefix.ends_with('.'))
+    } else {
+        host == pattern
+    }
+}
+
+/// Sets up `sandbox` so that the relay can run in it and connect to the proxy at `socket_path`.
+/// Returns the binary that should be run in the sandbox in place of the build script, which should
+/// then be passed as the first argument.
+pub(super) fn bind_relay(sandbox: &mut dyn Sandbox, socket_path: &Path) -> Result<PathBuf> {
+    let cackle_exe = std::env::current_exe().context("Failed to get current exe")?;
+    sandbox.ro_bind(&cackle_exe);
+    sandbox.ro_bind(socket_path);
+    sandbox.set_env(OsStr::new(RELAY_ENV), socket_path.as_os_str());
+    Ok(cackle_exe)
+}
+
+/// If `RELAY_ENV` is set, then we're running in a sandbox that was set up by `bind_relay`. Runs the
+/// binary that we were asked to run, relaying its connections to the proxy, then exits.
+pub(super) fn relay_if_requested() -> Result<()> {
+    let Some(socket_path) = std::env::var_os(RELAY_ENV) else {
+        return Ok(());
+    };
+    let mut args = std::env::args_os().skip(1);
+    let binary = args
+        .next()
+        .ok_or_else(|| anyhow!("Missing binary to run with network relay"))?;
+    let listener = bind_relay_listener()?;
+    let mut command = Command::new(binary);
+    command.args(args).env_remove(RELAY_ENV);
+    std::process::exit(run_relayed(listener, socket_path.into(), command)?);
+}
+
+/// Binds the TCP listener for a relay. This is separate from `run_relayed` so that the Landlock
+/// sandbox can bind it before restricting which ports can be used.
+pub(super) fn bind_relay_listener() -> Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", 0)).context("Failed to bind network relay")
+}
+
+/// Runs `command` with proxy environment variables pointing at `listener`, forwarding connections
+/// to the proxy at `socket_path`. Returns the exit code of `command`.
+pub(super) fn run_relayed(
+    listener: TcpListener,
+    socket_path: PathBuf,
+    mut command: Command,
+) -> Result<i32> {
+    let proxy_url = format!("http://{}", listener.local_addr()?);
+    for var in PROXY_ENV_VARS {
+        command.env(var, &proxy_url);
+    }
+    command.env_remove("no_proxy").env_remove("NO_PROXY");
+    std::thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let socket_path = socket_path.clone();
+            std::thread::spawn(move || {
+                if let Ok(proxy) = UnixStream::connect(socket_path) {
+                    let _ = copy_bidirectional(client, proxy);
+                }
+            });
+        }
+    });
+    let status = command.status().with_context(|| {
+        format!(
+            "Failed to run `{}`",
+            Path::new(command.get_program()).display()
+        )
+    })?;
+    // Report death by a signal the way a shell would, so that it can still be recognised, e.g. as
+    // exceeding a resource limit.
+    Ok(status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(-1))
+}
+
+/// A stream that we can copy to and from concurrently.
+trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+
+    fn shutdown_write(&self);
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+impl Stream for UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+/// Copies data in both directions between `a` and `b` until both directions are closed.
+fn copy_bidirectional(mut a: impl Stream, mut b: impl Stream) -> Result<()> {
+    let mut a_reader = a.try_clone()?;
+    let mut b_writer = b.try_clone()?;
+    let a_to_b = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut a_reader, &mut b_writer);
+        b_writer.shutdown_write();
+    });
+    let _ = std::io::copy(&mut b, &mut a);
+    a.shutdown_write();
+    let _ = a_to_b.join();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NetProxy;
+    use super::NetworkAttempt;
+    use super::ProxyRequest;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            ProxyRequest::parse("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .unwrap(),
+            ProxyRequest {
+                host: "example.com".to_owned(),
+                port: 443,
+                forward: None,
+            }
+        );
+        assert_eq!(
+            ProxyRequest::parse(
+                "GET http://[::1]:8080/a/b HTTP/1.1\r\nHost: [::1]:8080\r\nProxy-Connection: \
+                 keep-alive\r\n\r\n"
+            )
+            .unwrap(),
+            ProxyRequest {
+                host: "[::1]".to_owned(),
+                port: 8080,
+                forward: Some("GET /a/b HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n".to_owned()),
+            }
+        );
+        assert_eq!(
+            ProxyRequest::parse("GET http://example.com HTTP/1.1\r\n\r\n")
+                .unwrap()
+                .port,
+            80
+        );
+        assert!(ProxyRequest::parse("CONNECT example.com HTTP/1.1\r\n\r\n").is_err());
+        assert!(ProxyRequest::parse("GET /foo HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        let allow_hosts = vec![
+            "crates.io".to_owned(),
+            "example.com:443".to_owned(),
+            "*.github.com".to_owned(),
+        ];
+        let is_allowed = |host, port| super::is_allowed(host, port, &allow_hosts);
+        assert!(is_allowed("crates.io", 80));
+        assert!(is_allowed("Crates.IO", 443));
+        assert!(is_allowed("example.com", 443));
+        assert!(!is_allowed("example.com", 80));
+        assert!(is_allowed("api.github.com", 443));
+        assert!(!is_allowed("github.com", 443));
+        assert!(!is_allowed("evilgithub.com", 443));
+        assert!(!is_allowed("static.crates.io", 443));
+    }
+
+    /// Reads from `stream` until it's closed or we've read `len` bytes.
+    fn read_at_most(stream: &mut impl Read, len: usize) -> String {
+        let mut buf = Vec::new();
+        stream.take(len as u64).read_to_end(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn proxy_connections() {
+        // A server that echoes back whatever it receives.
+        let server = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let server_port = server.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut connection in server.incoming().flatten() {
+                let mut reader = connection.try_clone().unwrap();
+                let _ = std::io::copy(&mut reader, &mut connection);
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("proxy.sock");
+        let proxy =
+            NetProxy::start(socket_path.clone(), &[format!("127.0.0.1:{server_port}")]).unwrap();
+
+        let mut client = UnixStream::connect(&socket_path).unwrap();
+        write!(
+            client,
+            "CONNECT 127.0.0.1:{server_port} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n"
+        )
+        .unwrap();
+        let established = "HTTP/1.1 200 Connection established\r\n\r\n";
+        assert_eq!(read_at_most(&mut client, established.len()), established);
+        client.write_all(b"hello").unwrap();
+        assert_eq!(read_at_most(&mut client, 5), "hello");
+        drop(client);
+
+        let mut client = UnixStream::connect(&socket_path).unwrap();
+        client
+            .write_all(b"CONNECT denied.example.com:443 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let response = read_at_most(&mut client, usize::MAX);
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(response.contains("denied.example.com:443 is not permitted"));
+
+        assert_eq!(
+            proxy.attempts(),
+            vec![
+                NetworkAttempt {
+                    host: "127.0.0.1".to_owned(),
+                    port: server_port,
+                    allowed: true,
+                },
+                NetworkAttempt {
+                    host: "denied.example.com".to_owned(),
+                    port: 443,
+                    allowed: false,
+                },
+            ]
+        );
+
+        // Dropping the proxy stops it from accepting connections and removes the socket.
+        drop(proxy);
+        assert!(!socket_path.exists());
+        assert!(UnixStream::connect(&socket_path).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use super::Sandbox;
+use anyhow::Result;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Default)]
+pub(super) struct Nsjail {
+    args: Vec<OsString>,
+    /// If set, the network relay, which runs the binary.
+    relay: Option<PathBuf>,
+}
+
+impl Nsjail {
+    fn arg<S: AsRef<OsStr>>(&mut self, arg: S) {
+        self.args.push(arg.as_ref().to_owned());
+    }
+}
+
+impl Sandbox for Nsjail {
+    fn command(&self, binary: &Path, args: &[OsString], dir: &Path) -> Result<Command> {
+        let mut command = Command::new("nsjail");
+        command
+            .args(["--mode", "o"])
+            .arg("--quiet")
+            .args(["--hostname", "none"])
+            // By default, nsjail applies limits that are too low for many build scripts, e.g. a
+            // maximum file size of 1MiB and a time limit of 10 minutes.
+            .args(["--time_limit", "0"])
+            .args(["--rlimit_as", "inf"])
+            .args(["--rlimit_cpu", "inf"])
+            .args(["--rlimit_fsize", "inf"])
+            .args(["--rlimit_nofile", "max"])
+            .arg("--cwd")
+            .arg(dir)
+            .args(&self.args)
+            .arg("--")
+            .args(&self.relay)
+            .arg(binary)
+            .args(args);
+        Ok(command)
+    }
+
+    fn raw_arg(&mut self, arg: &OsStr) {
+        self.args.push(arg.to_owned());
+    }
+
+    fn tmpfs(&mut self, dir: &Path) {
+        self.arg("--tmpfsmount");
+        self.arg(dir);
+    }
+
+    fn ro_bind(&mut self, dir: &Path) {
+        self.arg("--bindmount_ro");
+        self.arg(dir);
+    }
+
+    fn writable_bind(&mut self, dir: &Path) {
+        self.arg("--bindmount");
+        self.arg(dir);
+    }
+
+    fn set_env(&mut self, var: &OsStr, value: &OsStr) {
+        let mut arg = var.to_owned();
+        arg.push("=");
+        arg.push(value);
+        self.arg("--env");
+        self.arg(arg);
+    }
+
+    fn allow_network(&mut self) {
+        self.arg("--disable_clone_newnet");
+    }
+
+    fn proxy_network(&mut self, socket_path: &Path) -> Result<bool> {
+        self.relay = Some(super::net_proxy::bind_relay(self, socket_path)?);
+        Ok(true)
+    }
+}
+
+pub(super) fn has_nsjail() -> bool {
+    // We don't rely on the exit status of `--help`, just on being able to run nsjail.
+    Command::new("nsjail").arg("--help").output().is_ok()
+}
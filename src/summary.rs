@@ -1,14 +1,14 @@
 use crate::config::Config;
 use crate::config::CrateName;
-use crate::config::PackageConfig;
 use crate::crate_index::CrateIndex;
 use clap::Parser;
+use serde::Serialize;
 use std::collections::BTreeMap;
-use std::collections::HashMap;
 use std::fmt::Display;
 
 /// Counts of how many packages in the dependency tree use different permissions, how many use no
 /// special permissions etc.
+#[derive(Serialize)]
 pub(crate) struct Summary {
     packages: Vec<PackageSummary>,
 }
@@ -37,6 +37,7 @@ pub(crate) struct SummaryOptions {
     print_headers: bool,
 }
 
+#[derive(Serialize)]
 struct PackageSummary {
     name: CrateName,
     permissions: Vec<String>,
@@ -44,17 +45,23 @@ struct PackageSummary {
 
 impl Summary {
     pub(crate) fn new(crate_index: &CrateIndex, config: &Config) -> Self {
-        let pkg_configs: HashMap<&CrateName, &PackageConfig> =
-            config.packages.iter().map(|(k, v)| (k, v)).collect();
-        let mut packages: Vec<PackageSummary> = crate_index
-            .package_names()
+        Self::for_packages(crate_index.package_names(), config)
+    }
+
+    /// Summarises the permissions that `config` grants to each of `package_names`.
+    pub(crate) fn for_packages<'a>(
+        package_names: impl Iterator<Item = &'a CrateName>,
+        config: &Config,
+    ) -> Self {
+        let mut packages: Vec<PackageSummary> = package_names
             .map(|name| {
                 let mut permissions = Vec::new();
                 for (crate_name, suffix) in [
-                    (name, ""),
-                    (&CrateName::for_build_script(name.as_ref()), "[build]"),
+                    (name.clone(), ""),
+                    (name.build_script(), "[build]"),
+                    (name.tests(), "[test]"),
                 ] {
-                    if let Some(pkg_config) = pkg_configs.get(crate_name) {
+                    if let Some(pkg_config) = config.package_config(&crate_name) {
                         if pkg_config.allow_proc_macro {
                             permissions.push(format!("proc_macro{suffix}"));
                         }
@@ -76,6 +83,13 @@ impl Summary {
 
         Self { packages }
     }
+
+    /// Returns each package together with the permissions that it has been granted.
+    pub(crate) fn permissions_by_package(&self) -> impl Iterator<Item = (&CrateName, &[String])> {
+        self.packages
+            .iter()
+            .map(|pkg| (&pkg.name, pkg.permissions.as_slice()))
+    }
 }
 
 impl Summary {
@@ -108,13 +122,13 @@ impl Summary {
     }
 
     fn print_by_permission(&self) {
-        let mut by_permission: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut by_permission: BTreeMap<&str, Vec<String>> = BTreeMap::new();
         for pkg in &self.packages {
             for perm in &pkg.permissions {
                 by_permission
                     .entry(perm)
                     .or_default()
-                    .push(pkg.name.as_ref());
+                    .push(pkg.name.to_string());
             }
         }
         for (perm, packages) in by_permission {
@@ -2,6 +2,8 @@ use crate::bytes::Bytes;
 use crate::names::Name;
 use anyhow::Result;
 use rustc_demangle::demangle;
+use serde::Deserialize;
+use serde::Serialize;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::str::Utf8Error;
@@ -76,6 +78,23 @@ impl<'data> Display for Symbol<'data> {
     }
 }
 
+/// Symbols are serialised in their demangled form, since that's what's useful to consumers of our
+/// machine-readable output.
+impl<'data> Serialize for Symbol<'data> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialising gives a symbol that displays the same as the symbol that was serialised, however
+/// since we serialise the demangled form, it may no longer be mangled.
+impl<'de> Deserialize<'de> for Symbol<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::borrowed(name.as_bytes()).to_heap())
+    }
+}
+
 impl<'data> Debug for Symbol<'data> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Ok(sym_string) = self.to_str() {
@@ -7,13 +7,18 @@
 
 use self::dwarf::SymbolDebugInfo;
 use self::object_file_path::ObjectFilePath;
+use self::reachability::CallGraph;
+use self::relocatable::SectionLayout;
 use crate::checker::ApiUsage;
 use crate::checker::Checker;
 use crate::checker::SourceLocation;
+use crate::config::PermissionName;
 use crate::names::Name;
 use crate::problem::ApiUsages;
 use crate::problem::ProblemList;
 use crate::symbol::Symbol;
+use crate::why::CallPath;
+use crate::why::ReferenceGraph;
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
@@ -21,7 +26,7 @@ use anyhow::Result;
 use ar::Archive;
 use gimli::Dwarf;
 use gimli::EndianSlice;
-use gimli::LittleEndian;
+use gimli::RunTimeEndian;
 use log::debug;
 use log::trace;
 use object::Object;
@@ -29,6 +34,8 @@ use object::ObjectSection;
 use object::ObjectSymbol;
 use object::RelocationTarget;
 use object::SectionIndex;
+use rayon::prelude::*;
+use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -38,9 +45,12 @@ use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 mod dwarf;
 pub(crate) mod object_file_path;
+mod reachability;
+mod relocatable;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Filetype {
@@ -48,19 +58,35 @@ enum Filetype {
     Other,
 }
 
-struct ApiUsageCollector<'input> {
-    outputs: ScanOutputs,
+struct ApiUsageCollector<'bin, 'input> {
+    collected: Collected,
+
+    bin: &'bin BinInfo<'input>,
+
+    /// Used to look up source locations in `bin`. Each thread has its own, since they can't be
+    /// shared between threads.
+    ctx: &'bin addr2line::Context<EndianSlice<'input, RunTimeEndian>>,
 
-    bin: BinInfo<'input>,
     debug_enabled: bool,
 }
 
+/// What we've collected from scanning some object files. Object files are scanned in parallel,
+/// then what was collected from each is merged.
+struct Collected {
+    outputs: ScanOutputs,
+
+    /// References between symbols. Only recorded when running `cackle why`.
+    graph: Option<ReferenceGraph>,
+
+    /// Only recorded when `only_reachable` is set.
+    call_graph: Option<CallGraph>,
+}
+
 /// Information derived from a linked binary. Generally an executable, but could also be shared
 /// object (so).
 struct BinInfo<'input> {
     filename: Arc<Path>,
     symbol_addresses: HashMap<Symbol<'input>, u64>,
-    ctx: addr2line::Context<EndianSlice<'input, LittleEndian>>,
 
     /// Information about each symbol obtained from the debug info.
     symbol_debug_info: HashMap<Symbol<'input>, SymbolDebugInfo<'input>>,
@@ -73,6 +99,13 @@ pub(crate) struct ScanOutputs {
     /// Problems not related to api_usage. These can't be fixed by config changes via the UI, since
     /// once computed, they won't be recomputed.
     base_problems: ProblemList,
+
+    /// Paths from the package being explained by `cackle why` to the API being explained.
+    call_paths: Vec<CallPath>,
+
+    /// API usages in code that can't be reached from the binary's entry points. Only populated
+    /// when `only_reachable` is set.
+    unreachable_api_usages: Vec<ApiUsages>,
 }
 
 struct ObjectIndex<'obj, 'data> {
@@ -105,32 +138,224 @@ pub(crate) fn scan_objects(
     let obj = object::File::parse(file_bytes.as_slice())
         .with_context(|| format!("Failed to parse {}", bin_path.display()))?;
     let owned_dwarf = Dwarf::load(|id| load_section(&obj, id))?;
-    let dwarf = owned_dwarf.borrow(|section| gimli::EndianSlice::new(section, gimli::LittleEndian));
-    let symbol_to_locations = dwarf::get_symbol_debug_info(&dwarf)?;
-    let ctx = addr2line::Context::from_dwarf(dwarf)
-        .with_context(|| format!("Failed to process {}", bin_path.display()))?;
+    let endian = endian(&obj);
+    let borrow_dwarf = || owned_dwarf.borrow(|section| EndianSlice::new(section, endian));
+    let symbol_to_locations = dwarf::get_symbol_debug_info(&borrow_dwarf())?;
+
+    let mut bin = BinInfo {
+        filename: Arc::from(bin_path),
+        symbol_addresses: Default::default(),
+        symbol_debug_info: symbol_to_locations,
+    };
+    bin.load_symbols(&obj)?;
+    let mut collected = Collected::new(checker);
+    if let Some(call_graph) = collected.call_graph.as_mut() {
+        call_graph.add_root(&Symbol::borrowed(b"main"));
+        for export in obj.exports()? {
+            call_graph.add_root(&Symbol::borrowed(export.name()));
+        }
+    }
+    // Each thread needs its own context, which caches what it parses from the binary's debug info,
+    // so memory use grows with the number of threads. This can be limited with `--threads`.
+    let contexts = (0..rayon::current_num_threads())
+        .map(|_| {
+            addr2line::Context::from_dwarf(borrow_dwarf())
+                .map(Mutex::new)
+                .with_context(|| format!("Failed to process {}", bin_path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Scanning an object file doesn't call back into rayon, so a thread can't start scanning
+    // another object file while it's using its context.
+    let scan_object = |filename: &ObjectFilePath, file_bytes: &[u8]| {
+        let ctx = contexts[rayon::current_thread_index().unwrap_or(0)]
+            .lock()
+            .unwrap();
+        let mut collector = ApiUsageCollector {
+            collected: Collected::new(checker),
+            bin: &bin,
+            ctx: &ctx,
+            debug_enabled: checker.args.debug,
+        };
+        collector.process_object_file_bytes(filename, file_bytes, checker)?;
+        Ok(collector.collected)
+    };
+    let scanned = paths
+        .par_iter()
+        .map(|path| {
+            let start = std::time::Instant::now();
+            let collected = scan_file(path, checker, &scan_object)
+                .with_context(|| format!("Failed to process `{}`", path.display()))?;
+            if checker.args.print_timing {
+                println!(
+                    "Scanning `{}` took {}ms",
+                    path.display(),
+                    start.elapsed().as_millis()
+                );
+            }
+            Ok(collected)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // We merge in the order of `paths` so that our outputs don't depend on which thread finished
+    // first.
+    for other in scanned {
+        collected.merge(other);
+    }
+    collected.find_call_paths(&bin, checker);
+    collected.separate_unreachable_usages();
+
+    Ok(collected.outputs)
+}
+
+/// Scans all the code in the rlib at `rlib_path`, whether or not it's been linked into any binary.
+/// Each object file in the rlib is scanned using its own debug info.
+pub(crate) fn scan_library(rlib_path: &Path, checker: &Checker) -> Result<ScanOutputs> {
+    let scanned = scan_archive(rlib_path, &|filename, file_bytes| {
+        scan_library_object(rlib_path, filename, file_bytes, checker)
+    })?;
+    let mut collected = Collected::for_library();
+    for other in scanned {
+        collected.merge(other);
+    }
+    Ok(collected.outputs)
+}
 
+/// Scans an object file from the rlib at `rlib_path`, using the object file's own debug info.
+fn scan_library_object(
+    rlib_path: &Path,
+    filename: &ObjectFilePath,
+    file_bytes: &[u8],
+    checker: &Checker,
+) -> Result<Collected> {
+    // Besides object files, rlibs contain the crate's metadata, which we skip.
+    let Ok(obj) = object::File::parse(file_bytes) else {
+        return Ok(Collected::for_library());
+    };
+    let layout = SectionLayout::new(&obj);
+    let owned_dwarf = Dwarf::load(|id| layout.load_relocated_section(&obj, id))?;
+    let dwarf = owned_dwarf.borrow(|section| EndianSlice::new(section, endian(&obj)));
+    let symbol_debug_info = dwarf::get_symbol_debug_info(&dwarf)?;
+    let ctx = addr2line::Context::from_dwarf(dwarf)
+        .with_context(|| format!("Failed to process {filename}"))?;
+    let mut bin = BinInfo {
+        filename: Arc::from(rlib_path),
+        symbol_addresses: Default::default(),
+        symbol_debug_info,
+    };
+    for symbol in obj.symbols().filter(|symbol| symbol.is_definition()) {
+        bin.symbol_addresses.insert(
+            Symbol::borrowed(symbol.name_bytes()?).to_heap(),
+            layout.symbol_address(&symbol),
+        );
+    }
     let mut collector = ApiUsageCollector {
-        outputs: Default::default(),
-        bin: BinInfo {
-            filename: Arc::from(bin_path),
-            symbol_addresses: Default::default(),
-            ctx,
-            symbol_debug_info: symbol_to_locations,
-        },
+        collected: Collected::for_library(),
+        bin: &bin,
+        ctx: &ctx,
         debug_enabled: checker.args.debug,
     };
-    collector.bin.load_symbols(&obj)?;
-    for path in paths {
-        collector
-            .process_file(path, checker)
-            .with_context(|| format!("Failed to process `{}`", path.display()))?;
+    collector.process_object_file_bytes(filename, file_bytes, checker)?;
+    Ok(collector.collected)
+}
+
+/// Scans the object file at `filename`, or if it's an archive, each of the object files that it
+/// contains, by passing them to `scan_object`.
+///
+/// Files are read into memory rather than memory-mapped, since mapping a file requires unsafe code,
+/// which this crate forbids. To limit how much memory this uses, the object files in an archive are
+/// read one at a time as threads become free to scan them.
+fn scan_file(
+    filename: &Path,
+    checker: &Checker,
+    scan_object: &(dyn Fn(&ObjectFilePath, &[u8]) -> Result<Collected> + Sync),
+) -> Result<Collected> {
+    let scanned = match Filetype::from_filename(filename) {
+        Filetype::Archive => scan_archive(filename, scan_object)?,
+        Filetype::Other => {
+            let file_bytes = std::fs::read(filename)
+                .with_context(|| format!("Failed to read `{}`", filename.display()))?;
+            vec![scan_object(
+                &ObjectFilePath::non_archive(filename),
+                &file_bytes,
+            )?]
+        }
+    };
+    let mut collected = Collected::new(checker);
+    for other in scanned {
+        collected.merge(other);
     }
+    Ok(collected)
+}
+
+/// Scans each of the object files in the archive at `filename` with `scan_object`. Returns what was
+/// collected from each in the order that they appear in the archive.
+fn scan_archive(
+    filename: &Path,
+    scan_object: &(dyn Fn(&ObjectFilePath, &[u8]) -> Result<Collected> + Sync),
+) -> Result<Vec<Collected>> {
+    let mut scanned = ArchiveMembers::open(filename)?
+        .enumerate()
+        .par_bridge()
+        .map(|(index, member)| {
+            let (member_path, file_bytes) = member?;
+            Ok((index, scan_object(&member_path, &file_bytes)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    scanned.sort_by_key(|(index, _)| *index);
+    Ok(scanned
+        .into_iter()
+        .map(|(_, collected)| collected)
+        .collect())
+}
+
+/// An iterator that reads the members of an archive one at a time.
+struct ArchiveMembers {
+    filename: PathBuf,
+    archive: Archive<File>,
+}
 
-    Ok(collector.outputs)
+impl ArchiveMembers {
+    fn open(filename: &Path) -> Result<Self> {
+        let file = File::open(filename)
+            .with_context(|| format!("Failed to open `{}`", filename.display()))?;
+        Ok(Self {
+            filename: filename.to_owned(),
+            archive: Archive::new(file),
+        })
+    }
+}
+
+impl Iterator for ArchiveMembers {
+    type Item = Result<(ObjectFilePath, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Ok(mut entry) = self.archive.next_entry()? else {
+                continue;
+            };
+            let mut buffer = Vec::new();
+            if let Err(error) = entry.read_to_end(&mut buffer) {
+                return Some(Err(error.into()));
+            }
+            return Some(
+                ObjectFilePath::in_archive(&self.filename, &entry).map(|path| (path, buffer)),
+            );
+        }
+    }
 }
 
 impl ScanOutputs {
+    pub(crate) fn api_usages(&self) -> &[ApiUsages] {
+        &self.api_usages
+    }
+
+    pub(crate) fn unreachable_api_usages(&self) -> &[ApiUsages] {
+        &self.unreachable_api_usages
+    }
+
+    pub(crate) fn take_call_paths(&mut self) -> Vec<CallPath> {
+        std::mem::take(&mut self.call_paths)
+    }
+
     pub(crate) fn problems(&self, checker: &mut Checker) -> Result<ProblemList> {
         let mut problems = self.base_problems.clone();
         for api_usage in &self.api_usages {
@@ -141,38 +366,7 @@ impl ScanOutputs {
     }
 }
 
-impl<'input> ApiUsageCollector<'input> {
-    fn process_file(&mut self, filename: &Path, checker: &Checker) -> Result<()> {
-        let mut buffer = Vec::new();
-        match Filetype::from_filename(filename) {
-            Filetype::Archive => {
-                let mut archive = Archive::new(File::open(filename)?);
-                while let Some(entry_result) = archive.next_entry() {
-                    let Ok(mut entry) = entry_result else {
-                        continue;
-                    };
-                    buffer.clear();
-                    entry.read_to_end(&mut buffer)?;
-                    self.process_object_file_bytes(
-                        &ObjectFilePath::in_archive(filename, &entry)?,
-                        &buffer,
-                        checker,
-                    )?;
-                }
-            }
-            Filetype::Other => {
-                let file_bytes = std::fs::read(filename)
-                    .with_context(|| format!("Failed to read `{}`", filename.display()))?;
-                self.process_object_file_bytes(
-                    &ObjectFilePath::non_archive(filename),
-                    &file_bytes,
-                    checker,
-                )?;
-            }
-        }
-        Ok(())
-    }
-
+impl<'bin, 'input> ApiUsageCollector<'bin, 'input> {
     /// Processes an unlinked object file - as opposed to an executable or a shared object, which
     /// has been linked.
     fn process_object_file_bytes(
@@ -186,8 +380,14 @@ impl<'input> ApiUsageCollector<'input> {
         let obj = object::File::parse(file_bytes)
             .with_context(|| format!("Failed to parse {}", filename))?;
         let object_index = ObjectIndex::new(&obj);
+        if let Some(call_graph) = self.collected.call_graph.as_mut() {
+            call_graph.add_object_symbols(&object_index);
+        }
         let mut new_api_usages: HashMap<_, Vec<ApiUsages>> = HashMap::new();
         for section in obj.sections() {
+            if let Some(call_graph) = self.collected.call_graph.as_mut() {
+                call_graph.add_section(&object_index, &section)?;
+            }
             let section_name = section.name().unwrap_or("");
             let Some(first_sym_info) = object_index.first_symbol(&section) else {
                 debug!("Skipping section `{section_name}` due to lack of debug info");
@@ -217,18 +417,33 @@ impl<'input> ApiUsageCollector<'input> {
             }
 
             for (offset, rel) in section.relocations() {
-                let location = self
-                    .bin
-                    .find_location(symbol_address_in_bin + offset - first_sym_info.offset)?
-                    .unwrap_or_else(|| debug_info.source_location());
-                // Ignore references that come from code in the rust standard library.
-                if location.is_in_rust_std() {
-                    continue;
+                let mut frames =
+                    self.find_frames(symbol_address_in_bin + offset - first_sym_info.offset)?;
+                if frames.is_empty() {
+                    frames.push(debug_info.source_location());
                 }
-                let crate_names =
-                    checker.crate_names_from_source_path(&location.filename, filename)?;
+                let location = attributed_location(&frames).cloned();
+                let crate_names = match &location {
+                    Some(location) => {
+                        checker.crate_names_from_source_path(&location.filename, filename)?
+                    }
+                    None => Vec::new(),
+                };
+                let target_symbols = object_index.target_symbols(&rel)?;
+                if let Some(graph) = self.collected.graph.as_mut() {
+                    graph.add(
+                        &first_sym_info.symbol,
+                        &target_symbols,
+                        location.as_ref().unwrap_or(&frames[0]),
+                        &crate_names,
+                    );
+                }
+                let Some(location) = location else {
+                    continue;
+                };
+                let inline_frames = inline_frames(frames);
 
-                for target_symbol in object_index.target_symbols(&rel)? {
+                for target_symbol in target_symbols {
                     trace!("{} -> {target_symbol}", first_sym_info.symbol);
 
                     let target_symbol_names = self.bin.names_from_symbol(&target_symbol)?;
@@ -261,6 +476,7 @@ impl<'input> ApiUsageCollector<'input> {
                                         from: first_sym_info.symbol.to_heap(),
                                         to: name.clone(),
                                         to_symbol: target_symbol.to_heap(),
+                                        inline_frames: inline_frames.clone(),
                                         debug_data,
                                     }],
                                 );
@@ -285,11 +501,144 @@ impl<'input> ApiUsageCollector<'input> {
                 .into_iter()
                 .min_by_key(|u| u.first_usage().unwrap().to_symbol.len())
             {
-                self.outputs.api_usages.push(shortest_target_usage);
+                self.collected
+                    .outputs
+                    .api_usages
+                    .push(shortest_target_usage);
             }
         }
         Ok(())
     }
+
+    /// Returns the source location of `offset` within each function in the chain of inlined
+    /// functions that contains it, starting with the innermost function. If there was no inlining,
+    /// then there will be at most one location.
+    fn find_frames(&self, offset: u64) -> Result<Vec<SourceLocation>> {
+        use addr2line::Location;
+
+        let mut frames = self
+            .ctx
+            .find_frames(offset)
+            .skip_all_loads()
+            .context("find_frames failed")?;
+        let mut locations = Vec::new();
+        while let Some(frame) = frames.next().context("find_frames failed")? {
+            let Some(Location {
+                file: Some(file),
+                line: Some(line),
+                column,
+            }) = frame.location
+            else {
+                continue;
+            };
+            locations.push(SourceLocation {
+                filename: PathBuf::from(file),
+                line,
+                column,
+            });
+        }
+        Ok(locations)
+    }
+}
+
+/// Returns the frame to which a reference from code with the inlined `frames` should be attributed.
+/// This is the innermost frame that isn't in the rust standard library. e.g. if a standard library
+/// function that uses an API gets inlined into a crate's code, then that crate is using the API. If
+/// all frames are in the standard library, then we ignore the reference.
+fn attributed_location(frames: &[SourceLocation]) -> Option<&SourceLocation> {
+    frames.iter().find(|location| !location.is_in_rust_std())
+}
+
+/// Returns the frames to record for an API usage. We only record the frames if there was inlining.
+fn inline_frames(frames: Vec<SourceLocation>) -> Vec<SourceLocation> {
+    if frames.len() > 1 {
+        frames
+    } else {
+        Vec::new()
+    }
+}
+
+impl Collected {
+    fn new(checker: &Checker) -> Self {
+        Self {
+            outputs: ScanOutputs::default(),
+            graph: checker
+                .args
+                .why_args()
+                .map(|why_args| ReferenceGraph::new(why_args.package.clone())),
+            call_graph: checker
+                .config
+                .common
+                .only_reachable
+                .then(CallGraph::default),
+        }
+    }
+
+    /// Returns an empty collection for code from a library. Library code is checked regardless of
+    /// what calls it, so we don't record references or calls.
+    fn for_library() -> Self {
+        Self {
+            outputs: ScanOutputs::default(),
+            graph: None,
+            call_graph: None,
+        }
+    }
+
+    fn merge(&mut self, other: Collected) {
+        self.outputs.api_usages.extend(other.outputs.api_usages);
+        self.outputs
+            .base_problems
+            .merge(other.outputs.base_problems);
+        if let (Some(graph), Some(other_graph)) = (self.graph.as_mut(), other.graph) {
+            graph.merge(other_graph);
+        }
+        if let (Some(call_graph), Some(other_call_graph)) =
+            (self.call_graph.as_mut(), other.call_graph)
+        {
+            call_graph.merge(other_call_graph);
+        }
+    }
+
+    /// Finds paths from the package being explained to the API being explained, if we're running
+    /// `cackle why`. We prefer paths that end at a function that's part of the API. If there are
+    /// none, then we settle for paths ending at a function that's only part of the API by virtue
+    /// of its generic parameters, e.g. `serde_json::to_writer<std::net::TcpStream>`.
+    fn find_call_paths(&mut self, bin: &BinInfo, checker: &Checker) {
+        let (Some(graph), Some(why_args)) = (&self.graph, checker.args.why_args()) else {
+            return;
+        };
+        let api = PermissionName::new(&why_args.api);
+        let in_api = |names: Result<Vec<Name>>| {
+            names
+                .unwrap_or_default()
+                .iter()
+                .any(|name| checker.apis_for_name(name).contains(&api))
+        };
+        let mut call_paths = graph.paths(|symbol| in_api(symbol.names()));
+        if call_paths.is_empty() {
+            call_paths = graph.paths(|symbol| in_api(bin.names_from_symbol(symbol)));
+        }
+        self.outputs.call_paths = call_paths;
+    }
+
+    /// Moves API usages from functions that can't be reached from the binary's entry points into
+    /// `unreachable_api_usages`, if we're only checking reachable code.
+    fn separate_unreachable_usages(&mut self) {
+        let Some(call_graph) = &self.call_graph else {
+            return;
+        };
+        let reachable = call_graph.reachable();
+        let (reachable_usages, unreachable_usages) = std::mem::take(&mut self.outputs.api_usages)
+            .into_iter()
+            .partition(|api_usages| {
+                api_usages
+                    .first_usage()
+                    .map(|usage| reachable.contains(&usage.from))
+                    .unwrap_or(true)
+            });
+        self.outputs.api_usages = reachable_usages;
+        self.outputs.unreachable_api_usages = unreachable_usages;
+    }
 }
 
 impl<'obj, 'data> ObjectIndex<'obj, 'data> {
@@ -423,31 +772,6 @@ impl<'input> BinInfo<'input> {
         }
         Ok(names)
     }
-
-    fn find_location(&self, offset: u64) -> Result<Option<SourceLocation>> {
-        use addr2line::Location;
-
-        let Some(location) = self
-            .ctx
-            .find_location(offset)
-            .context("find_location failed")?
-        else {
-            return Ok(None);
-        };
-        let Location {
-            file: Some(file),
-            line: Some(line),
-            column,
-        } = location
-        else {
-            return Ok(None);
-        };
-        Ok(Some(SourceLocation {
-            filename: PathBuf::from(file),
-            line,
-            column,
-        }))
-    }
 }
 
 /// Loads section `id` from `obj`.
@@ -464,6 +788,15 @@ fn load_section<'data>(
     Ok(data)
 }
 
+/// Returns the byte order of `obj` in the form that gimli needs.
+fn endian(obj: &object::File) -> RunTimeEndian {
+    if obj.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    }
+}
+
 impl Filetype {
     fn from_filename(filename: &Path) -> Self {
         let Some(extension) = filename.extension() else {
@@ -479,9 +812,50 @@ impl Filetype {
 
 /// Additional information that might be useful for debugging. Only available when --debug is
 /// passed.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub(crate) struct UsageDebugData {
     bin_path: Arc<Path>,
     object_file_path: ObjectFilePath,
     section_name: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::SourceLocation;
+    use std::path::PathBuf;
+
+    fn location(filename: &str, line: u32) -> SourceLocation {
+        SourceLocation {
+            filename: PathBuf::from(filename),
+            line,
+            column: None,
+        }
+    }
+
+    #[test]
+    fn frame_selection() {
+        let std_frame = location("/rustc/abc123/library/std/src/fs.rs", 10);
+        let registry_frame = location("/cargo/registry/src/foo/lib.rs", 20);
+        let inlined_frame = location("/home/user/crab1/src/util.rs", 30);
+        let caller_frame = location("/home/user/crab2/src/lib.rs", 40);
+
+        let frames = vec![
+            std_frame.clone(),
+            registry_frame.clone(),
+            inlined_frame.clone(),
+            caller_frame.clone(),
+        ];
+        assert_eq!(super::attributed_location(&frames), Some(&inlined_frame));
+        assert_eq!(super::inline_frames(frames.clone()), frames);
+
+        let frames = vec![std_frame, registry_frame];
+        assert_eq!(super::attributed_location(&frames), None);
+
+        let frames = vec![caller_frame.clone()];
+        assert_eq!(super::attributed_location(&frames), Some(&caller_frame));
+        assert_eq!(super::inline_frames(frames), Vec::new());
+
+        assert_eq!(super::attributed_location(&[]), None);
+        assert_eq!(super::inline_frames(Vec::new()), Vec::new());
+    }
+}
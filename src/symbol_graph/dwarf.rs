@@ -5,7 +5,7 @@ use anyhow::Context;
 use anyhow::Result;
 use gimli::Dwarf;
 use gimli::EndianSlice;
-use gimli::LittleEndian;
+use gimli::RunTimeEndian;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::os::unix::prelude::OsStrExt;
@@ -39,7 +39,7 @@ impl<'input> SymbolDebugInfo<'input> {
 }
 
 pub(super) fn get_symbol_debug_info<'input>(
-    dwarf: &Dwarf<EndianSlice<'input, LittleEndian>>,
+    dwarf: &Dwarf<EndianSlice<'input, RunTimeEndian>>,
 ) -> Result<HashMap<Symbol<'input>, SymbolDebugInfo<'input>>> {
     let mut output: HashMap<Symbol, SymbolDebugInfo> = HashMap::new();
     let mut units = dwarf.units();
@@ -115,7 +115,7 @@ pub(super) fn get_symbol_debug_info<'input>(
     Ok(output)
 }
 
-fn path_from_opt_slice(slice: Option<gimli::EndianSlice<gimli::LittleEndian>>) -> &Path {
+fn path_from_opt_slice(slice: Option<EndianSlice<RunTimeEndian>>) -> &Path {
     slice
         .map(|dir| Path::new(OsStr::from_bytes(dir.slice())))
         .unwrap_or_else(|| Path::new(""))
@@ -1,5 +1,6 @@
 use anyhow::Context;
 use anyhow::Result;
+use serde::Serialize;
 use std::fmt::Display;
 use std::fs::File;
 use std::path::Path;
@@ -7,7 +8,7 @@ use std::path::PathBuf;
 
 /// Represents the name of an object file, possibly contained within an archive. Note, we only
 /// support a single level of archive. i.e. archives within archives aren't supported.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub(crate) struct ObjectFilePath {
     pub(crate) outer: PathBuf,
     pub(crate) inner: Option<PathBuf>,
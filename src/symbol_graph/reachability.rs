@@ -0,0 +1,173 @@
+//! Determines which functions in a binary can be reached from its entry points. Entry points are
+//! `main`, symbols exported by the binary, functions referenced from sections such as `.init_array`
+//! that hold pointers to functions run before `main` or at exit, and symbols that aren't Rust
+//! mangled, since these are most likely called from code that we don't scan, such as the standard
+//! library or C code. Sections that the linker retains regardless of whether they're referenced, or
+//! that code can find via `__start_` and `__stop_` symbols, as is done by crates such as `linkme`,
+//! are treated like `.init_array`.
+//!
+//! References from data, e.g. from vtables, are followed just like calls, so what we consider
+//! reachable is an over-approximation of what can actually be called.
+
+use super::ObjectIndex;
+use crate::symbol::Symbol;
+use anyhow::Result;
+use object::Object;
+use object::ObjectSection;
+use object::ObjectSymbol;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Prefixes of sections that contain pointers to functions that are run without being called.
+const ENTRY_SECTION_PREFIXES: &[&str] = &[".init_array", ".fini_array", ".ctors", ".dtors"];
+
+/// The ELF flag for sections that the linker shouldn't discard, even if they're not referenced.
+const SHF_GNU_RETAIN: u64 = 0x200000;
+
+/// Prefixes of mangled Rust symbols.
+const MANGLED_PREFIXES: &[&[u8]] = &[b"_ZN", b"_R"];
+
+#[derive(Default)]
+pub(super) struct CallGraph {
+    /// For the first symbol in each section, the symbols referenced by that section.
+    references: HashMap<Symbol<'static>, Vec<Symbol<'static>>>,
+
+    /// Maps symbols that aren't the first symbol in their section to the first symbol in their
+    /// section.
+    aliases: HashMap<Symbol<'static>, Symbol<'static>>,
+
+    roots: Vec<Symbol<'static>>,
+}
+
+impl CallGraph {
+    pub(super) fn add_root(&mut self, symbol: &Symbol) {
+        self.roots.push(symbol.to_heap());
+    }
+
+    pub(super) fn merge(&mut self, other: CallGraph) {
+        for (from, targets) in other.references {
+            self.references.entry(from).or_default().extend(targets);
+        }
+        self.aliases.extend(other.aliases);
+        self.roots.extend(other.roots);
+    }
+
+    /// Adds roots and aliases for the symbols defined by an object file.
+    pub(super) fn add_object_symbols(&mut self, object_index: &ObjectIndex) {
+        for symbol in object_index.obj.symbols() {
+            let name = symbol.name_bytes().unwrap_or_default();
+            if name.is_empty() || !symbol.is_definition() {
+                continue;
+            }
+            if symbol.is_global() && !MANGLED_PREFIXES.iter().any(|p| name.starts_with(p)) {
+                self.add_root(&Symbol::borrowed(name));
+            }
+            let Some(first_symbol) = symbol
+                .section_index()
+                .and_then(|index| object_index.section_infos.get(index.0))
+                .and_then(|info| info.first_symbol.as_ref())
+            else {
+                continue;
+            };
+            let symbol = Symbol::borrowed(name);
+            if symbol != first_symbol.symbol {
+                self.aliases
+                    .insert(symbol.to_heap(), first_symbol.symbol.to_heap());
+            }
+        }
+    }
+
+    /// Adds the references made by `section`.
+    pub(super) fn add_section(
+        &mut self,
+        object_index: &ObjectIndex,
+        section: &object::Section,
+    ) -> Result<()> {
+        let is_entry_section = is_entry_section(section);
+        let first_symbol = object_index.first_symbol(section);
+        if !is_entry_section && first_symbol.is_none() {
+            return Ok(());
+        }
+        let mut targets = Vec::new();
+        for (_, rel) in section.relocations() {
+            targets.extend(
+                object_index
+                    .target_symbols(&rel)?
+                    .iter()
+                    .map(Symbol::to_heap),
+            );
+        }
+        if is_entry_section {
+            self.roots.extend(targets);
+        } else if let Some(first_symbol) = first_symbol {
+            self.references
+                .entry(first_symbol.symbol.to_heap())
+                .or_default()
+                .extend(targets);
+        }
+        Ok(())
+    }
+
+    /// Returns the symbols that can be reached from our roots.
+    pub(super) fn reachable(&self) -> HashSet<&Symbol<'static>> {
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<&Symbol<'static>> = self.roots.iter().collect();
+        while let Some(symbol) = pending.pop() {
+            let symbol = self.aliases.get(symbol).unwrap_or(symbol);
+            if !reachable.insert(symbol) {
+                continue;
+            }
+            pending.extend(self.references.get(symbol).into_iter().flatten());
+        }
+        reachable
+    }
+}
+
+/// Returns whether `section` contains references to things that might be used without being
+/// referenced from code.
+fn is_entry_section(section: &object::Section) -> bool {
+    let name = section.name().unwrap_or("");
+    let retained = matches!(section.flags(), object::SectionFlags::Elf { sh_flags }
+        if sh_flags & SHF_GNU_RETAIN != 0);
+    // The linker only defines `__start_` and `__stop_` symbols for sections with names that are
+    // valid C identifiers.
+    let has_start_stop_symbols = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    retained
+        || has_start_stop_symbols
+        || ENTRY_SECTION_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallGraph;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn reachable_symbols() {
+        let sym = |name: &'static str| Symbol::borrowed(name.as_bytes());
+        let mut graph = CallGraph::default();
+        graph.add_root(&sym("main"));
+        graph
+            .references
+            .insert(sym("main"), vec![sym("a"), sym("b_alias")]);
+        graph.references.insert(sym("a"), vec![sym("main")]);
+        graph.references.insert(sym("b"), vec![sym("c")]);
+        graph.references.insert(sym("dead"), vec![sym("d")]);
+        graph.aliases.insert(sym("b_alias"), sym("b"));
+
+        let reachable = graph.reachable();
+        for name in ["main", "a", "b", "c"] {
+            assert!(reachable.contains(&sym(name)), "{name} should be reachable");
+        }
+        for name in ["dead", "d"] {
+            assert!(
+                !reachable.contains(&sym(name)),
+                "{name} shouldn't be reachable"
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Support for reading the debug info in object files that haven't been linked. In an unlinked
+//! object file, every section starts at address 0 and references from the debug info to code and to
+//! other debug info sections are left for the linker to fill in via relocations. So that we can look
+//! up code by address, we assign each section its own address range, then apply the relocations in
+//! the debug info sections ourselves, much as a linker would.
+
+use object::Object;
+use object::ObjectSection;
+use object::ObjectSymbol;
+use object::RelocationKind;
+use object::RelocationTarget;
+use object::SectionIndex;
+use std::borrow::Cow;
+
+/// The address at which we place the first section. We avoid 0, since the debug info uses that for
+/// code that the linker discarded.
+const FIRST_SECTION_ADDRESS: u64 = 0x1000;
+
+/// Addresses that we've assigned to the sections of an object file.
+pub(super) struct SectionLayout {
+    addresses: Vec<u64>,
+}
+
+impl SectionLayout {
+    pub(super) fn new(obj: &object::File) -> Self {
+        let max_section_index = obj.sections().map(|s| s.index().0).max().unwrap_or(0);
+        let mut addresses = vec![0; max_section_index + 1];
+        let mut next = FIRST_SECTION_ADDRESS;
+        for section in obj.sections() {
+            // References to debug info sections are offsets from the start of the section, so
+            // these sections stay at 0.
+            if section.name().unwrap_or("").starts_with(".debug") {
+                continue;
+            }
+            let align = section.align().max(1);
+            let address = (next + align - 1) / align * align;
+            addresses[section.index().0] = address;
+            next = address + section.size().max(1);
+        }
+        Self { addresses }
+    }
+
+    fn section_address(&self, index: SectionIndex) -> u64 {
+        self.addresses.get(index.0).copied().unwrap_or(0)
+    }
+
+    /// Returns the address that we've assigned to `symbol`, or its value if it isn't defined
+    /// relative to a section.
+    pub(super) fn symbol_address(&self, symbol: &object::Symbol) -> u64 {
+        match symbol.section_index() {
+            Some(index) => self.section_address(index) + symbol.address(),
+            None => symbol.address(),
+        }
+    }
+
+    /// Loads the section with the supplied name from `obj`, applying its relocations as though the
+    /// sections of `obj` had been placed at the addresses that we assigned.
+    pub(super) fn load_relocated_section<'data>(
+        &self,
+        obj: &object::File<'data>,
+        id: gimli::SectionId,
+    ) -> Result<Cow<'data, [u8]>, gimli::Error> {
+        let Some(section) = obj.section_by_name(id.name()) else {
+            return Ok(Cow::Borrowed([].as_slice()));
+        };
+        let Ok(mut data) = section.uncompressed_data() else {
+            return Ok(Cow::Borrowed([].as_slice()));
+        };
+        let little_endian = obj.is_little_endian();
+        for (offset, rel) in section.relocations() {
+            if rel.kind() != RelocationKind::Absolute {
+                continue;
+            }
+            let target = match rel.target() {
+                RelocationTarget::Symbol(index) => match obj.symbol_by_index(index) {
+                    Ok(symbol) => self.symbol_address(&symbol),
+                    Err(_) => continue,
+                },
+                RelocationTarget::Section(index) => self.section_address(index),
+                _ => continue,
+            };
+            let Ok(offset) = usize::try_from(offset) else {
+                continue;
+            };
+            let size = usize::from(rel.size() / 8);
+            if size == 0 || size > 8 {
+                continue;
+            }
+            let Some(bytes) = data.to_mut().get_mut(offset..offset + size) else {
+                continue;
+            };
+            let addend = if rel.has_implicit_addend() {
+                read_uint(bytes, little_endian) as i64
+            } else {
+                rel.addend()
+            };
+            write_uint(bytes, target.wrapping_add(addend as u64), little_endian);
+        }
+        Ok(data)
+    }
+}
+
+/// Reads an unsigned integer that occupies all of `bytes`, which must be at most 8 bytes.
+fn read_uint(bytes: &[u8], little_endian: bool) -> u64 {
+    let mut value = [0; 8];
+    if little_endian {
+        value[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(value)
+    } else {
+        value[8 - bytes.len()..].copy_from_slice(bytes);
+        u64::from_be_bytes(value)
+    }
+}
+
+/// Writes `value` to `bytes`, truncating it to however many bytes `bytes` has.
+fn write_uint(bytes: &mut [u8], value: u64, little_endian: bool) {
+    if little_endian {
+        bytes.copy_from_slice(&value.to_le_bytes()[..bytes.len()]);
+    } else {
+        bytes.copy_from_slice(&value.to_be_bytes()[8 - bytes.len()..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SectionLayout;
+    use gimli::EndianSlice;
+    use object::Object;
+    use object::ObjectSection;
+    use object::ObjectSymbol;
+    use std::process::Command;
+
+    #[test]
+    fn uint_byte_order() {
+        let mut bytes = [0; 4];
+        super::write_uint(&mut bytes, 0x1234_5678, true);
+        assert_eq!(bytes, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(super::read_uint(&bytes, true), 0x1234_5678);
+        super::write_uint(&mut bytes, 0x1234_5678, false);
+        assert_eq!(bytes, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(super::read_uint(&bytes, false), 0x1234_5678);
+    }
+
+    #[test]
+    fn section_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("lib.rs");
+        std::fs::write(
+            &source,
+            "#[no_mangle]\npub fn cackle_first() -> u32 { 1 }\n\n\
+             #[no_mangle]\npub fn cackle_second() -> u32 { 2 }\n",
+        )
+        .unwrap();
+        let object_path = dir.path().join("lib.o");
+        let status = Command::new("rustc")
+            .args([
+                "--crate-type=lib",
+                "--emit=obj",
+                "-Cdebuginfo=2",
+                "-Copt-level=0",
+            ])
+            .arg("-o")
+            .arg(&object_path)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let bytes = std::fs::read(&object_path).unwrap();
+        let obj = object::File::parse(bytes.as_slice()).unwrap();
+        let layout = SectionLayout::new(&obj);
+
+        // Sections that contain code or data each get their own non-overlapping address range.
+        let mut ranges = Vec::new();
+        for section in obj.sections() {
+            let address = layout.section_address(section.index());
+            if section.name().unwrap().starts_with(".debug") {
+                assert_eq!(address, 0);
+            } else if section.size() > 0 {
+                assert_eq!(address % section.align().max(1), 0);
+                ranges.push(address..address + section.size());
+            }
+        }
+        ranges.sort_by_key(|range| range.start);
+        assert!(ranges[0].start > 0);
+        assert!(ranges.windows(2).all(|w| w[0].end <= w[1].start));
+
+        // Looking up a function's address in the relocated debug info finds the function.
+        let dwarf = gimli::Dwarf::load(|id| layout.load_relocated_section(&obj, id)).unwrap();
+        let ctx = addr2line::Context::from_dwarf(
+            dwarf.borrow(|section| EndianSlice::new(section, crate::symbol_graph::endian(&obj))),
+        )
+        .unwrap();
+        for (name, line) in [("cackle_first", 2), ("cackle_second", 5)] {
+            let symbol = obj
+                .symbols()
+                .find(|symbol| symbol.name() == Ok(name))
+                .unwrap();
+            let location = ctx
+                .find_location(layout.symbol_address(&symbol))
+                .unwrap()
+                .unwrap();
+            assert!(location.file.unwrap().ends_with("lib.rs"));
+            assert_eq!(location.line, Some(line));
+        }
+    }
+}
@@ -3,6 +3,7 @@
 
 use crate::events::AppEvent;
 use crate::problem_store::ProblemStoreRef;
+use crate::report::Reporter;
 use crate::Args;
 use anyhow::Result;
 use clap::ValueEnum;
@@ -13,6 +14,7 @@ use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread::JoinHandle;
 
+mod auto_fix;
 mod basic_term;
 mod full_term;
 mod null_ui;
@@ -22,6 +24,9 @@ pub(crate) enum Kind {
     None,
     Basic,
     Full,
+    /// Applies fixes automatically. Not selectable with `--ui`, since it's used by `cackle init`.
+    #[value(skip)]
+    AutoFix,
 }
 
 trait UserInterface: Send {
@@ -38,11 +43,12 @@ pub(crate) fn start_ui(
     problem_store: ProblemStoreRef,
     event_receiver: Receiver<AppEvent>,
     abort_sender: Sender<()>,
+    reporter: Reporter,
 ) -> Result<JoinHandle<Result<()>>> {
     let mut ui: Box<dyn UserInterface> = match args.ui_kind() {
         Kind::None => {
             info!("Starting null UI");
-            Box::new(null_ui::NullUi::new(args))
+            Box::new(null_ui::NullUi::new(args, reporter))
         }
         Kind::Basic => {
             info!("Starting basic terminal UI");
@@ -55,6 +61,10 @@ pub(crate) fn start_ui(
                 abort_sender,
             )?)
         }
+        Kind::AutoFix => {
+            info!("Starting auto-fix UI");
+            Box::new(auto_fix::AutoFixUi::new(config_path, reporter))
+        }
     };
     Ok(std::thread::Builder::new()
         .name("UI".to_owned())
@@ -0,0 +1,105 @@
+//! A user-interface that never prompts, but instead applies the automatic fix for each problem as
+//! it arrives. Used by `cackle init` to generate config that grants whatever permissions are used.
+
+use crate::config_editor;
+use crate::config_editor::ConfigEditor;
+use crate::events::AppEvent;
+use crate::problem::Severity;
+use crate::problem_store::ProblemStoreRef;
+use crate::report::Reporter;
+use anyhow::Result;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+pub(crate) struct AutoFixUi {
+    config_path: PathBuf,
+    /// Source paths within this directory are written relative to it in config comments.
+    root_path: PathBuf,
+    reporter: Reporter,
+}
+
+impl AutoFixUi {
+    pub(crate) fn new(config_path: &Path, reporter: Reporter) -> Self {
+        Self {
+            config_path: config_path.to_owned(),
+            root_path: config_path.parent().map(Path::to_owned).unwrap_or_default(),
+            reporter,
+        }
+    }
+}
+
+impl super::UserInterface for AutoFixUi {
+    fn run(
+        &mut self,
+        problem_store: ProblemStoreRef,
+        event_receiver: Receiver<AppEvent>,
+    ) -> Result<()> {
+        while let Ok(event) = event_receiver.recv() {
+            match event {
+                AppEvent::Shutdown => return Ok(()),
+                AppEvent::ProblemsAdded => {}
+            }
+            let mut pstore = problem_store.lock();
+            loop {
+                let Some((index, problem)) = pstore.deduplicated_into_iter().next() else {
+                    break;
+                };
+                let Some(edit) = config_editor::automatic_fix_for_problem(problem) else {
+                    // We can't fix this problem ourselves, so report it. If it's an error, then the
+                    // user will need to decide what to do about it.
+                    let severity = problem.severity();
+                    self.reporter.problem(severity, problem)?;
+                    if matches!(severity, Severity::Error) {
+                        pstore.abort();
+                        break;
+                    }
+                    pstore.resolve(index);
+                    continue;
+                };
+                let mut editor = ConfigEditor::from_file(&self.config_path)?;
+                edit.apply_with_comments(&mut editor, &self.root_path)?;
+                editor.write(&self.config_path)?;
+                pstore.replace(index, edit.replacement_problems());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_auto_fix_ui_allows_unsafe() {
+    use crate::checker::SourceLocation;
+    use crate::problem::Problem;
+    use crate::proxy::rpc::UnsafeUsage;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("cackle.toml");
+    let mut ui = AutoFixUi::new(&config_path, Default::default());
+    let (event_send, event_recv) = std::sync::mpsc::channel();
+    let mut problem_store = crate::problem_store::create(event_send.clone());
+    let join_handle = std::thread::spawn({
+        let problem_store = problem_store.clone();
+        move || {
+            crate::ui::UserInterface::run(&mut ui, problem_store, event_recv).unwrap();
+        }
+    });
+    let outcome = problem_store.fix_problems(
+        Problem::DisallowedUnsafe(UnsafeUsage {
+            crate_name: "crab1".into(),
+            locations: vec![SourceLocation {
+                filename: dir.path().join("crab1/src/lib.rs"),
+                line: 10,
+                column: None,
+            }],
+        })
+        .into(),
+    );
+    assert_eq!(outcome, crate::outcome::Outcome::Continue);
+    event_send.send(AppEvent::Shutdown).unwrap();
+    join_handle.join().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&config_path).unwrap(),
+        "[pkg.crab1]\n# unsafe at crab1/src/lib.rs [10]\nallow_unsafe = true\n"
+    );
+}
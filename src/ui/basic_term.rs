@@ -105,7 +105,7 @@ impl BasicTermUi {
         let sandbox_kind = sandbox::available_kind();
         if sandbox_kind == SandboxKind::Disabled {
             println!(indoc! {r#"
-                bwrap (bubblewrap) doesn't seem to be installed, so sandboxing will be disabled.
+                No supported sandbox seems to be available, so sandboxing will be disabled.
                 If you'd like to sandbox execution of build scripts, press control-c, install
                 bubble wrap, then try again. On system with apt, you can run:
                 sudo apt install bubblewrap
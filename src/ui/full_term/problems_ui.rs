@@ -314,6 +314,20 @@ impl ProblemsUi {
 
         let mut lines = usage_source_lines(&**usage).unwrap_or_else(error_lines);
 
+        let inline_frames = usage.inline_frames();
+        if !inline_frames.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from("Inlined code, innermost first:"));
+            for location in inline_frames {
+                let marker = if location == usage.source_location() {
+                    "> "
+                } else {
+                    "  "
+                };
+                lines.push(Line::from(format!("{marker}{location}")));
+            }
+        }
+
         if let Some(debug_data) = usage.debug_data() {
             lines.push(Line::from(""));
             for line in debug_data.lines() {
@@ -555,6 +569,12 @@ fn usages_for_problem(
 trait DisplayUsage {
     fn source_location(&self) -> &SourceLocation;
 
+    /// The location within each function in the chain of inlined functions that the usage is in,
+    /// if any.
+    fn inline_frames(&self) -> &[SourceLocation] {
+        &[]
+    }
+
     fn debug_data(&self) -> Option<String> {
         None
     }
@@ -568,6 +588,10 @@ impl DisplayUsage for ApiUsage {
         &self.source_location
     }
 
+    fn inline_frames(&self) -> &[SourceLocation] {
+        &self.inline_frames
+    }
+
     fn debug_data(&self) -> Option<String> {
         self.debug_data
             .as_ref()
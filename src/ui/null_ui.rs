@@ -3,19 +3,23 @@
 use crate::events::AppEvent;
 use crate::problem::Severity;
 use crate::problem_store::ProblemStoreRef;
+use crate::report::Reporter;
 use crate::Args;
 use anyhow::Result;
-use colored::Colorize;
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 
 pub(crate) struct NullUi {
     args: Arc<Args>,
+    reporter: Reporter,
 }
 
 impl NullUi {
-    pub(crate) fn new(args: &Arc<Args>) -> Self {
-        Self { args: args.clone() }
+    pub(crate) fn new(args: &Arc<Args>, reporter: Reporter) -> Self {
+        Self {
+            args: args.clone(),
+            reporter,
+        }
     }
 }
 
@@ -38,15 +42,10 @@ impl super::UserInterface for NullUi {
                         } else {
                             problem.severity()
                         };
-                        match severity {
-                            Severity::Warning => {
-                                println!("{} {problem:#}", "WARNING:".yellow())
-                            }
-                            Severity::Error => {
-                                has_errors = true;
-                                println!("{} {problem:#}", "ERROR:".red())
-                            }
+                        if matches!(severity, Severity::Error) {
+                            has_errors = true;
                         }
+                        self.reporter.problem(severity, problem)?;
                     }
                     if has_errors {
                         pstore.abort();
@@ -74,7 +73,7 @@ impl super::UserInterface for NullUi {
 fn test_null_ui_with_warning() {
     use crate::problem::Problem::UnusedPackageConfig;
 
-    let mut ui = NullUi::new(&Arc::new(Args::default()));
+    let mut ui = NullUi::new(&Arc::new(Args::default()), Default::default());
     let (event_send, event_recv) = std::sync::mpsc::channel();
     let mut problem_store = crate::problem_store::create(event_send.clone());
     let join_handle = std::thread::spawn({
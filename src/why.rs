@@ -0,0 +1,255 @@
+//! Explains how a package comes to use an API. While scanning a binary, we record which symbols
+//! reference which other symbols, then search for chains of references from the package's code to
+//! symbols that are part of the API.
+
+use crate::checker::SourceLocation;
+use crate::config::CrateName;
+use crate::symbol::Symbol;
+use clap::Parser;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+#[derive(Parser, Debug, Clone)]
+pub(crate) struct WhyArgs {
+    /// The package to explain, e.g. `crab1`, `crab1@1.0.0` or `crab1.build`. Without a version, all
+    /// versions of the package are explained.
+    pub(crate) package: CrateName,
+
+    /// The API that the package uses, e.g. `net`.
+    pub(crate) api: String,
+}
+
+/// References between symbols in a binary.
+pub(crate) struct ReferenceGraph {
+    package: CrateName,
+
+    /// For each symbol, the symbols that it references and where.
+    references: HashMap<Symbol<'static>, Vec<Reference>>,
+
+    /// Symbols containing references that are attributed to `package`. These are where our paths
+    /// start.
+    roots: HashSet<Symbol<'static>>,
+}
+
+struct Reference {
+    to: Symbol<'static>,
+    location: SourceLocation,
+}
+
+/// A chain of references from a function in a package to a symbol that's part of an API.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct CallPath {
+    hops: Vec<Hop>,
+    target: Symbol<'static>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Hop {
+    /// The function containing the reference to the next function in the path.
+    function: Symbol<'static>,
+    /// Where in `function` the reference is.
+    location: SourceLocation,
+}
+
+impl ReferenceGraph {
+    pub(crate) fn new(package: CrateName) -> Self {
+        Self {
+            package,
+            references: HashMap::new(),
+            roots: HashSet::new(),
+        }
+    }
+
+    /// Records that `from` references each of `targets` at `location`. `crate_names` are the
+    /// crates to which the references are attributed.
+    pub(crate) fn add(
+        &mut self,
+        from: &Symbol,
+        targets: &[Symbol],
+        location: &SourceLocation,
+        crate_names: &[CrateName],
+    ) {
+        let from = from.to_heap();
+        if crate_names
+            .iter()
+            .any(|crate_name| self.is_package(crate_name))
+        {
+            self.roots.insert(from.clone());
+        }
+        let references = self.references.entry(from).or_default();
+        for target in targets {
+            references.push(Reference {
+                to: target.to_heap(),
+                location: location.clone(),
+            });
+        }
+    }
+
+    /// Adds the references from `other`, which was built for the same package.
+    pub(crate) fn merge(&mut self, other: ReferenceGraph) {
+        for (from, references) in other.references {
+            self.references.entry(from).or_default().extend(references);
+        }
+        self.roots.extend(other.roots);
+    }
+
+    fn is_package(&self, crate_name: &CrateName) -> bool {
+        if self.package.version().is_some() {
+            *crate_name == self.package
+        } else {
+            crate_name.unversioned() == self.package
+        }
+    }
+
+    /// Returns, for each symbol for which `is_target` returns true and that is reachable from the
+    /// package's code, the shortest path by which it's reached. Paths end at the first target
+    /// reached.
+    pub(crate) fn paths(&self, is_target: impl Fn(&Symbol) -> bool) -> Vec<CallPath> {
+        let mut roots: Vec<&Symbol<'static>> = self.roots.iter().collect();
+        roots.sort();
+        // For each symbol that we've reached, the symbol that we reached it from and where.
+        let mut reached_from: HashMap<
+            &Symbol<'static>,
+            Option<(&Symbol<'static>, &SourceLocation)>,
+        > = roots.iter().map(|root| (*root, None)).collect();
+        let mut queue: VecDeque<&Symbol<'static>> = roots.into_iter().collect();
+        let mut targets = Vec::new();
+        while let Some(symbol) = queue.pop_front() {
+            for reference in self.references.get(symbol).into_iter().flatten() {
+                if reached_from.contains_key(&reference.to) {
+                    continue;
+                }
+                reached_from.insert(&reference.to, Some((symbol, &reference.location)));
+                if is_target(&reference.to) {
+                    targets.push(&reference.to);
+                } else {
+                    queue.push_back(&reference.to);
+                }
+            }
+        }
+        let mut paths: Vec<CallPath> = targets
+            .into_iter()
+            .map(|target| {
+                let mut hops = Vec::new();
+                let mut symbol = target;
+                while let Some(Some((from, location))) = reached_from.get(symbol) {
+                    hops.push(Hop {
+                        function: (*from).clone(),
+                        location: (*location).clone(),
+                    });
+                    symbol = from;
+                }
+                hops.reverse();
+                CallPath {
+                    hops,
+                    target: target.clone(),
+                }
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl Display for CallPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for hop in &self.hops {
+            writeln!(f, "  {}", hop.function)?;
+            writeln!(f, "    at {}", hop.location)?;
+        }
+        writeln!(f, "  {}", self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReferenceGraph;
+    use crate::checker::SourceLocation;
+    use crate::config::CrateName;
+    use crate::symbol::Symbol;
+
+    fn location(line: u32) -> SourceLocation {
+        SourceLocation {
+            filename: "src/lib.rs".into(),
+            line,
+            column: None,
+        }
+    }
+
+    #[test]
+    fn shortest_paths() {
+        let sym = |name: &'static str| Symbol::borrowed(name.as_bytes());
+        let crab1 = [CrateName::from("crab1")];
+        let crab2 = [CrateName::from("crab2")];
+        let mut graph = ReferenceGraph::new(CrateName::from("crab1"));
+        graph.add(&sym("crab1::a"), &[sym("crab1::b")], &location(1), &crab1);
+        graph.add(&sym("crab1::a"), &[sym("crab2::c")], &location(2), &crab1);
+        graph.add(&sym("crab1::b"), &[sym("crab2::d")], &location(3), &crab1);
+        graph.add(&sym("crab2::c"), &[sym("crab2::d")], &location(4), &crab2);
+        graph.add(
+            &sym("crab2::d"),
+            &[sym("std::net::connect")],
+            &location(5),
+            &crab2,
+        );
+        graph.add(
+            &sym("crab2::e"),
+            &[sym("std::net::bind")],
+            &location(6),
+            &crab2,
+        );
+
+        let paths = graph.paths(|symbol| symbol.to_string().starts_with("std::net"));
+        assert_eq!(paths.len(), 1);
+        assert_eq!(
+            paths[0].to_string(),
+            "  crab1::b\n    at src/lib.rs [3]\n  crab2::d\n    at src/lib.rs [5]\n  \
+             std::net::connect\n"
+        );
+
+        let paths = graph.paths(|symbol| symbol.to_string().starts_with("std::fs"));
+        assert!(paths.is_empty());
+
+        // A version-qualified package only matches that version.
+        let mut graph = ReferenceGraph::new("crab1@1.0.0".parse().unwrap());
+        graph.add(
+            &sym("crab1::a"),
+            &[sym("std::net::connect")],
+            &location(1),
+            &crab1,
+        );
+        assert!(graph
+            .paths(|symbol| symbol.to_string().starts_with("std::net"))
+            .is_empty());
+    }
+
+    #[test]
+    fn merged_graphs() {
+        let sym = |name: &'static str| Symbol::borrowed(name.as_bytes());
+        let mut graph = ReferenceGraph::new(CrateName::from("crab1"));
+        graph.add(
+            &sym("crab1::a"),
+            &[sym("crab2::b")],
+            &location(1),
+            &[CrateName::from("crab1")],
+        );
+        let mut other = ReferenceGraph::new(CrateName::from("crab1"));
+        other.add(
+            &sym("crab2::b"),
+            &[sym("std::net::connect")],
+            &location(2),
+            &[CrateName::from("crab2")],
+        );
+        graph.merge(other);
+
+        let paths = graph.paths(|symbol| symbol.to_string().starts_with("std::net"));
+        assert_eq!(paths.len(), 1);
+        assert_eq!(
+            paths[0].to_string(),
+            "  crab1::a\n    at src/lib.rs [1]\n  crab2::b\n    at src/lib.rs [2]\n  \
+             std::net::connect\n"
+        );
+    }
+}